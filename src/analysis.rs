@@ -0,0 +1,857 @@
+//! Analysis of the traces of one run and the merge of all runs into the results of the session
+//!
+//! Spans borrow the traces of their run, so a run is analyzed as a whole and only hands back owned values
+//! and names borrowed from the arguments. This lets the runs be analyzed in parallel. The merge goes in run
+//! order, so the results do not depend on which run finished first.
+use anyhow::Result;
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
+use time::Duration;
+
+use crate::{
+    CounterResults, FilterErrors, PointResults, RunFilterSummary, RunResults,
+    args::Args,
+    bins::{self, Bin},
+    breakdown::{self, Breakdown},
+    clusters::{self, Cluster},
+    correlation::RunMetrics,
+    counters,
+    error::BenchError,
+    export,
+    filter::{self, Filter},
+    flamegraph::{self, Folded},
+    frames,
+    functions::{self, Profile},
+    idle::{self, IdleGaps},
+    latency, nesting,
+    parse::{self, Event},
+    payload, phases, sched,
+    span::{self, Span, SpanIntegrity},
+    spill::{Spill, SpilledSpans},
+    threads::{self, ThreadBreakdown},
+    trace,
+};
+
+/// Time of a parent span filter and of the span filters nested in it
+pub(crate) type Nested<'a> = HashMap<&'a str, (Duration, HashMap<&'a str, Duration>)>;
+
+/// Everything the analysis of a run needs besides its traces
+pub(crate) struct Analysis<'a> {
+    pub(crate) args: &'a Args,
+    pub(crate) filters: &'a [Filter<'a>],
+    pub(crate) breakdown_filter: Option<&'a Filter<'a>>,
+    pub(crate) clusters: &'a [Cluster],
+    pub(crate) bin_width: Duration,
+    /// The result name of the start offset of every span filter
+    pub(crate) start_offset_names: &'a HashMap<&'a str, String>,
+}
+
+#[derive(Default)]
+/// The results of one run. The collections have the shape of the ones in [`Session`] so they can be appended
+pub(crate) struct RunAnalysis<'a> {
+    /// The run starting at 1
+    pub(crate) run: usize,
+    pub(crate) results: RunResults,
+    pub(crate) metrics: RunMetrics,
+    pub(crate) errors: FilterErrors<'a>,
+    pub(crate) points: PointResults,
+    pub(crate) breakdown: Option<Breakdown>,
+    pub(crate) threads: HashMap<&'a str, Vec<ThreadBreakdown>>,
+    pub(crate) bins: HashMap<&'a str, Vec<Vec<Bin>>>,
+    pub(crate) depths: HashMap<&'a str, Vec<(usize, f64)>>,
+    pub(crate) idle_gaps: HashMap<&'a str, Vec<Option<IdleGaps>>>,
+    pub(crate) phases: Option<Result<Vec<Duration>>>,
+    pub(crate) counters: CounterResults,
+    pub(crate) chrome_trace: export::ChromeTrace,
+    pub(crate) nested: Nested<'a>,
+    pub(crate) summaries: HashMap<&'a str, Vec<RunFilterSummary>>,
+    pub(crate) lossy: bool,
+    pub(crate) cluster_time: HashMap<&'a str, HashMap<String, Duration>>,
+    pub(crate) functions: Option<Profile>,
+    pub(crate) flamegraph: Folded,
+    pub(crate) span_args: HashMap<&'a str, Vec<(i64, Duration)>>,
+    /// The spans of the span filters written to disk with `--spill`, the results only have their sample
+    pub(crate) spilled: HashMap<String, SpilledSpans>,
+    /// The trace file of the run, kept with `--keep-traces` or read with `--from-file`
+    pub(crate) trace: Option<PathBuf>,
+}
+
+impl<'a> Analysis<'a> {
+    /// Analyze the traces of the run
+    pub(crate) fn analyze(&self, run: usize, report: &parse::ParseReport) -> RunAnalysis<'a> {
+        let args = self.args;
+        let traces = &report.traces;
+        let mut analysis = RunAnalysis {
+            run,
+            lossy: report.is_lossy(),
+            ..RunAnalysis::default()
+        };
+        let RunAnalysis {
+            results: run_results,
+            metrics,
+            errors,
+            points,
+            breakdown: run_breakdown,
+            threads: run_threads,
+            bins: run_bins,
+            depths,
+            idle_gaps: run_idle_gaps,
+            phases: run_phases,
+            counters: run_counters,
+            chrome_trace,
+            nested,
+            summaries,
+            cluster_time,
+            functions: run_functions,
+            flamegraph: run_flamegraph,
+            span_args,
+            ..
+        } = &mut analysis;
+
+        let differences = filter::find_notable_differences(traces, self.filters);
+        for (key, value) in differences.iter() {
+            if let Ok(d) = value {
+                metrics.insert(key.to_string(), d.as_seconds_f64());
+                run_results.entry(key.to_string()).or_default().push(*d);
+            } else {
+                errors.entry(key).and_modify(|v| *v += 1).or_insert(1);
+            }
+        }
+
+        let (mut spans, integrity) = if args.include_truncated {
+            span::find_spans_including_truncated(traces, &report.lost_event_regions)
+        } else {
+            span::find_intact_spans(traces, &report.lost_event_regions)
+        };
+        integrity_points(metrics, points, &integrity, report.lost_events);
+        let anchor = trace::find_anchor(traces, args.anchor.as_deref());
+        if anchor.is_none() && (!args.bins.is_empty() || !args.start_offsets.is_empty()) {
+            errors.entry("anchor").and_modify(|v| *v += 1).or_insert(1);
+        }
+        let process_names = trace::process_names(traces);
+        if !args.predicates.is_empty() {
+            spans.retain(|s| {
+                args.predicates
+                    .iter()
+                    .all(|p| p.matches(s.start, &process_names))
+            });
+        }
+        let span_index = span::SpanIndex::new(&spans);
+        // without the sched lines, i.e., traced without the category, every span would look like running
+        let sched_index = (args.sched_stats && !report.sched.is_empty())
+            .then(|| sched::SchedIndex::new(&report.sched));
+        if args.sched_stats && report.sched.is_empty() {
+            errors.entry("sched").and_modify(|v| *v += 1).or_insert(1);
+        }
+        let mut exported_spans: Vec<(&str, &Span)> = Vec::new();
+        let mut counted_parents: Vec<&String> = Vec::new();
+        for filter in args.spans.iter() {
+            let name = &filter.name;
+            let mut matched = span_index.matching(&filter.matcher);
+            if let Some(parent) = args
+                .span_parents
+                .iter()
+                .find(|(child, _parent)| child == name)
+                .and_then(|(_child, parent)| args.spans.iter().find(|s| s.name == *parent))
+            {
+                let parents = span_index.matching(&parent.matcher);
+                matched.retain(|s| nesting::innermost_parent(s, &parents).is_some());
+                let (parent_total, children) = nested.entry(&parent.name).or_default();
+                *children.entry(name).or_default() +=
+                    matched.iter().map(|s| s.duration()).sum::<Duration>();
+                if !counted_parents.contains(&&parent.name) {
+                    *parent_total += nesting::outermost_duration(&parents);
+                    counted_parents.push(&parent.name);
+                }
+            }
+            summaries.entry(name).or_default().push(RunFilterSummary {
+                count: matched.len() as i64,
+                total: matched.iter().map(|s| s.duration()).sum(),
+            });
+            if args.chrome_trace.is_some() {
+                exported_spans.extend(matched.iter().map(|s| (name.as_str(), *s)));
+            }
+            metrics.insert(
+                name.clone(),
+                matched
+                    .iter()
+                    .map(|s| s.duration())
+                    .sum::<Duration>()
+                    .as_seconds_f64(),
+            );
+            metrics.insert(format!("{} count", name), matched.len() as f64);
+            let unterminated = integrity
+                .unterminated
+                .iter()
+                .filter(|(function, _)| filter.matcher.matches(function))
+                .map(|(_, count)| count)
+                .sum::<u32>();
+            if unterminated > 0 {
+                *errors.entry(name).or_default() += unterminated;
+            }
+            run_results
+                .entry(name.clone())
+                .or_default()
+                .extend(matched.iter().map(|s| s.duration()));
+            run_results
+                .entry(format!("{} (self)", name))
+                .or_default()
+                .extend(matched.iter().map(|s| s.self_duration));
+            if let Some(sched_index) = &sched_index {
+                for s in matched.iter() {
+                    let stats = sched_index.stats(s);
+                    for (state, duration) in [
+                        ("running", stats.running),
+                        ("runnable", stats.runnable),
+                        ("blocked", stats.blocked),
+                    ] {
+                        run_results
+                            .entry(format!("{} ({})", name, state))
+                            .or_default()
+                            .push(duration);
+                    }
+                    points
+                        .entry(format!("{} context switches", name))
+                        .or_default()
+                        .push(stats.switches as i64);
+                }
+            }
+            let processes = matched.iter().map(|s| s.start.pid).collect::<HashSet<_>>();
+            if (args.split_by_process || args.split_processes.contains(name)) && processes.len() > 1
+            {
+                for s in matched.iter() {
+                    let process = process_names
+                        .get(&s.start.pid)
+                        .map_or(s.start.pid.to_string(), |p| p.to_string());
+                    run_results
+                        .entry(format!("{} [{}]", name, process))
+                        .or_default()
+                        .push(s.duration());
+                }
+            }
+            if let Ok(depth) = span::recursion_depth(&matched) {
+                depths.entry(name).or_default().push(depth);
+            }
+            if !self.clusters.is_empty() {
+                let time = cluster_time.entry(name).or_default();
+                for (cluster, dur) in
+                    clusters::cluster_shares(&matched, self.clusters, sched_index.as_ref())
+                {
+                    *time.entry(cluster).or_default() += dur;
+                }
+            }
+            if args.per_thread {
+                run_threads
+                    .entry(name)
+                    .or_default()
+                    .push(threads::thread_breakdown(&matched));
+            }
+            if let Some(anchor) = anchor
+                && args.bins.contains(name)
+            {
+                run_bins.entry(name).or_default().push(bins::bin_spans(
+                    &matched,
+                    anchor,
+                    self.bin_width,
+                ));
+            }
+            if let Some(anchor) = anchor
+                && let Some(key) = self.start_offset_names.get(name.as_str())
+                && let Some(first) = matched.iter().map(|s| s.start.timestamp).min()
+            {
+                run_results
+                    .entry(key.clone())
+                    .or_default()
+                    .push(trace::difference_of_timestamps(&first, anchor));
+            }
+        }
+
+        for filter in args.intervals.iter() {
+            match latency::find_interval(traces, filter) {
+                Some(interval) => {
+                    metrics.insert(filter.name.clone(), interval.as_seconds_f64());
+                    run_results
+                        .entry(filter.name.clone())
+                        .or_default()
+                        .push(interval);
+                }
+                None => *errors.entry(filter.name.as_str()).or_default() += 1,
+            }
+        }
+
+        if args.chrome_trace.is_some() {
+            if args.spans.is_empty() {
+                exported_spans.extend(spans.iter().map(|s| (s.name(), s)));
+            }
+            chrome_trace.add_run(run, &exported_spans, traces);
+        }
+
+        for filter in args
+            .spans
+            .iter()
+            .filter(|s| args.idle_gaps.contains(&s.name))
+        {
+            run_idle_gaps
+                .entry(&filter.name)
+                .or_default()
+                .push(idle::idle_gaps(&spans, filter));
+        }
+
+        for filter in args.latencies.iter() {
+            let (latencies, unpaired) = latency::find_latencies(traces, filter);
+            summaries
+                .entry(filter.name.as_str())
+                .or_default()
+                .push(RunFilterSummary {
+                    count: latencies.len() as i64,
+                    total: latencies.iter().sum(),
+                });
+            run_results
+                .entry(filter.name.clone())
+                .or_default()
+                .extend(latencies);
+            if unpaired > 0 {
+                errors
+                    .entry(filter.name.as_str())
+                    .and_modify(|v| *v += unpaired)
+                    .or_insert(unpaired);
+            }
+        }
+
+        if let Some(filter) = &args.frames {
+            let name = &filter.name;
+            let budget = Duration::seconds_f64(args.frame_budget / 1000.0);
+            match frames::frame_stats(&span_index.matching(&filter.matcher), budget) {
+                Some(stats) => {
+                    metrics.insert(format!("{} fps", name), stats.fps);
+                    metrics.insert(format!("{} janky frames", name), stats.janky as f64);
+                    for (point, value) in [
+                        ("fps", stats.fps.round() as i64),
+                        ("janky frames", stats.janky as i64),
+                        ("dropped frames", stats.dropped as i64),
+                    ] {
+                        points
+                            .entry(format!("{} {}", name, point))
+                            .or_default()
+                            .push(value);
+                    }
+                    run_results
+                        .entry(format!("{} longest stutter", name))
+                        .or_default()
+                        .push(stats.longest);
+                    run_results
+                        .entry(format!("{} frame time", name))
+                        .or_default()
+                        .extend(stats.frame_times);
+                }
+                None => *errors.entry(name).or_default() += 1,
+            }
+        }
+
+        let handoff_spans = if args.handoffs.is_empty() {
+            Vec::new()
+        } else {
+            span::find_handoffs(traces, &report.lost_event_regions)
+        };
+        for filter in args.handoffs.iter() {
+            let handoffs = handoff_spans
+                .iter()
+                .filter(|s| filter.matcher.matches(s.name()))
+                .map(|s| s.duration())
+                .collect::<Vec<_>>();
+            metrics.insert(
+                filter.name.clone(),
+                handoffs.iter().sum::<Duration>().as_seconds_f64(),
+            );
+            summaries
+                .entry(filter.name.as_str())
+                .or_default()
+                .push(RunFilterSummary {
+                    count: handoffs.len() as i64,
+                    total: handoffs.iter().sum(),
+                });
+            run_results
+                .entry(filter.name.clone())
+                .or_default()
+                .extend(handoffs);
+        }
+
+        for filter in args.span_args.iter() {
+            let matched = span_index.matching(&filter.span.matcher);
+            let (values, invalid) = payload::span_values(&matched, filter);
+            points
+                .entry(filter.name.clone())
+                .or_default()
+                .extend(values.iter().map(|(value, _d)| value));
+            span_args.entry(&filter.name).or_default().extend(values);
+            if invalid > 0 {
+                *errors.entry(filter.name.as_str()).or_default() += invalid;
+            }
+        }
+
+        for filter in args.payloads.iter() {
+            let payloads = payload::find_payloads(traces, filter);
+            let values = &payloads.values;
+            let sum = values.iter().sum::<i64>();
+            points
+                .entry(format!("{} sum", filter.name))
+                .or_default()
+                .push(sum);
+            if let Some(max) = values.iter().max() {
+                points
+                    .entry(format!("{} max", filter.name))
+                    .or_default()
+                    .push(*max);
+                points
+                    .entry(format!("{} mean", filter.name))
+                    .or_default()
+                    .push(sum / values.len() as i64);
+            }
+            if payloads.invalid > 0 {
+                errors
+                    .entry(filter.name.as_str())
+                    .and_modify(|v| *v += payloads.invalid)
+                    .or_insert(payloads.invalid);
+            }
+        }
+
+        *run_counters = args
+            .counters
+            .iter()
+            .map(|name| (name.clone(), counters::counter_series(traces, name)))
+            .collect();
+
+        for window in args.counter_windows.iter() {
+            match counters::counter_in_window(traces, &spans, window) {
+                Ok(values) => {
+                    for (suffix, value) in [
+                        ("peak", values.peak),
+                        ("avg", values.avg),
+                        ("delta", values.delta),
+                    ] {
+                        points
+                            .entry(format!("{} {}", window.name, suffix))
+                            .or_default()
+                            .push(value);
+                    }
+                }
+                Err(_) => {
+                    errors
+                        .entry(window.name.as_str())
+                        .and_modify(|v| *v += 1)
+                        .or_insert(1);
+                }
+            }
+        }
+
+        if args.phases.len() > 1 {
+            *run_phases = Some(phases::find_phases(traces, &args.phases));
+        }
+
+        if args.functions.is_some() {
+            *run_functions = Some(functions::profile(traces, &spans));
+        }
+
+        if args.flamegraph.is_some() {
+            *run_flamegraph = flamegraph::fold(&spans, &process_names);
+        }
+
+        // a failing window is already counted in the errors of the filter
+        if let Some(filter) = self.breakdown_filter {
+            *run_breakdown = breakdown::window_breakdown(traces, &spans, filter).ok();
+        }
+
+        if args.runs() == 1 && args.all_traces {
+            println!("Printing {} traces", &traces.len());
+            for i in traces {
+                println!("{:?}", i);
+            }
+            println!("----------------------------------------------------------\n\n");
+        }
+        analysis
+    }
+
+    /// Analyze the run while its traces are read, only the open spans are kept in memory.
+    /// This finds the filters and the span filters, `--stream` conflicts with everything needing the traces.
+    pub(crate) fn analyze_stream(
+        &self,
+        run: usize,
+        events: impl Iterator<Item = Result<Event, BenchError>>,
+    ) -> Result<RunAnalysis<'a>> {
+        let args = self.args;
+        let mut analysis = RunAnalysis {
+            run,
+            ..RunAnalysis::default()
+        };
+        let mut windows = self
+            .filters
+            .iter()
+            .map(|_| filter::WindowMatches::default())
+            .collect::<Vec<_>>();
+        // the duration and self duration of the spans of every span filter
+        let mut matched = vec![Vec::new(); args.spans.len()];
+        let mut spills = args
+            .spans
+            .iter()
+            .map(|_| args.spill.then(Spill::create).transpose())
+            .collect::<Result<Vec<_>>>()?;
+        let mut stream = span::SpanStream::new();
+        let mut lost_events = 0;
+        for event in events {
+            match event? {
+                Event::Trace(trace) => {
+                    for (filter, window) in self.filters.iter().zip(windows.iter_mut()) {
+                        window.push(filter, &trace);
+                    }
+                    let Some(span) = stream.push(Event::Trace(trace)) else {
+                        continue;
+                    };
+                    for ((filter, spans), spill) in args
+                        .spans
+                        .iter()
+                        .zip(matched.iter_mut())
+                        .zip(spills.iter_mut())
+                    {
+                        if !filter.matcher.matches(&span.name) {
+                            continue;
+                        }
+                        match spill {
+                            Some(spill) => spill.push(span.duration(), span.self_duration)?,
+                            None => spans.push((span.duration(), span.self_duration)),
+                        }
+                    }
+                }
+                Event::Lost(region, warning) => {
+                    analysis.lossy = true;
+                    lost_events += region.events.unwrap_or(0);
+                    if !args.quiet() {
+                        eprintln!("run {}: {}", run, warning);
+                    }
+                    stream.push(Event::Lost(region, warning));
+                }
+                Event::Unreadable(line) => {
+                    if !args.quiet() {
+                        eprintln!("run {}: could not read line {}", run, line);
+                    }
+                }
+                Event::Sched(_) => {}
+            }
+        }
+        let RunAnalysis {
+            results,
+            metrics,
+            errors,
+            points,
+            summaries,
+            spilled,
+            ..
+        } = &mut analysis;
+
+        for (filter, window) in self.filters.iter().zip(windows) {
+            match window.duration(filter) {
+                Ok(d) => {
+                    metrics.insert(filter.name.to_owned(), d.as_seconds_f64());
+                    results.entry(filter.name.to_owned()).or_default().push(d);
+                }
+                Err(_) => *errors.entry(filter.name).or_default() += 1,
+            }
+        }
+
+        let integrity = stream.finish();
+        integrity_points(metrics, points, &integrity, lost_events);
+        for ((filter, mut spans), spill) in args.spans.iter().zip(matched).zip(spills) {
+            let name = &filter.name;
+            let (count, total) = match spill {
+                Some(spill) => {
+                    let spill = spill.finish()?;
+                    spans = spill.sample.clone();
+                    let count_total = (spill.count as i64, spill.total);
+                    spilled.insert(name.clone(), spill);
+                    count_total
+                }
+                None => (
+                    spans.len() as i64,
+                    spans.iter().map(|(d, _self)| *d).sum::<Duration>(),
+                ),
+            };
+            summaries
+                .entry(name)
+                .or_default()
+                .push(RunFilterSummary { count, total });
+            metrics.insert(name.clone(), total.as_seconds_f64());
+            metrics.insert(format!("{} count", name), count as f64);
+            let unterminated = integrity
+                .unterminated
+                .iter()
+                .filter(|(function, _)| filter.matcher.matches(function))
+                .map(|(_, count)| count)
+                .sum::<u32>();
+            if unterminated > 0 {
+                *errors.entry(name).or_default() += unterminated;
+            }
+            results
+                .entry(name.clone())
+                .or_default()
+                .extend(spans.iter().map(|(d, _self)| *d));
+            results
+                .entry(format!("{} (self)", name))
+                .or_default()
+                .extend(spans.iter().map(|(_d, self_duration)| *self_duration));
+        }
+        Ok(analysis)
+    }
+}
+
+/// Add the markers that could not be paired and the lost events as metrics and points
+fn integrity_points(
+    metrics: &mut RunMetrics,
+    points: &mut PointResults,
+    integrity: &SpanIntegrity,
+    lost_events: u64,
+) {
+    for (key, value) in [
+        ("unmatched ends", integrity.unmatched_ends as i64),
+        (
+            "unterminated starts",
+            integrity.unterminated_starts() as i64,
+        ),
+        ("lost events", lost_events as i64),
+        ("lost spans", integrity.lost as i64),
+    ] {
+        metrics.insert(key.to_owned(), value as f64);
+        points.entry(key.to_owned()).or_default().push(value);
+    }
+}
+
+/// Append the values of every key
+fn append<K: Eq + std::hash::Hash, V>(into: &mut HashMap<K, Vec<V>>, from: HashMap<K, Vec<V>>) {
+    for (key, values) in from {
+        into.entry(key).or_default().extend(values);
+    }
+}
+
+#[derive(Default)]
+/// The results of all runs of the session
+pub(crate) struct Session<'a> {
+    /// Keep the results of every run for the samples and the json output
+    keep_samples: bool,
+    /// Include the runs that lost events in the results
+    allow_lossy: bool,
+    pub(crate) results: RunResults,
+    pub(crate) errors: FilterErrors<'a>,
+    pub(crate) breakdowns: Vec<Breakdown>,
+    pub(crate) threads: HashMap<&'a str, Vec<ThreadBreakdown>>,
+    pub(crate) bins: HashMap<&'a str, Vec<Vec<Bin>>>,
+    pub(crate) depths: HashMap<&'a str, Vec<(usize, f64)>>,
+    pub(crate) idle_gaps: HashMap<&'a str, Vec<Option<IdleGaps>>>,
+    pub(crate) run_metrics: Vec<RunMetrics>,
+    pub(crate) points: PointResults,
+    pub(crate) phases: Vec<Result<Vec<Duration>>>,
+    pub(crate) run_samples: Vec<RunResults>,
+    /// The point values of every run, only kept like the samples
+    pub(crate) run_points: Vec<PointResults>,
+    /// The spilled spans of every run, only kept like the samples
+    pub(crate) run_spilled: Vec<HashMap<String, SpilledSpans>>,
+    /// The number of every run in the results
+    pub(crate) run_numbers: Vec<usize>,
+    /// The trace file of every run in the results, if it is known
+    pub(crate) run_traces: Vec<Option<PathBuf>>,
+    pub(crate) counters: Vec<CounterResults>,
+    pub(crate) chrome_trace: export::ChromeTrace,
+    pub(crate) nested: Nested<'a>,
+    pub(crate) summaries: HashMap<&'a str, Vec<RunFilterSummary>>,
+    /// The runs that lost events. Unless they are allowed they are only counted as error
+    pub(crate) lossy_runs: Vec<usize>,
+    pub(crate) cluster_time: HashMap<&'a str, HashMap<String, Duration>>,
+    pub(crate) functions: Vec<Profile>,
+    /// The stacks of all runs added up
+    pub(crate) flamegraph: Folded,
+    /// The numbers of the span argument filters with the durations of their spans
+    pub(crate) span_args: HashMap<&'a str, Vec<(i64, Duration)>>,
+}
+
+impl<'a> Session<'a> {
+    pub(crate) fn new(args: &Args) -> Self {
+        Session {
+            keep_samples: args.samples.is_some() || args.writes_json(),
+            allow_lossy: args.allow_lossy,
+            ..Session::default()
+        }
+    }
+
+    /// Add the next run. Runs have to be added in order
+    pub(crate) fn add(&mut self, run: RunAnalysis<'a>) {
+        if run.lossy {
+            self.lossy_runs.push(run.run);
+            if !self.allow_lossy {
+                *self.errors.entry("__buffer_overflow").or_default() += 1;
+                return;
+            }
+        }
+        for (key, durations) in run.results.iter() {
+            self.results
+                .entry(key.clone())
+                .or_default()
+                .extend(durations);
+        }
+        if self.keep_samples {
+            self.run_samples.push(run.results);
+        }
+        self.run_numbers.push(run.run);
+        self.run_traces.push(run.trace);
+        self.run_metrics.push(run.metrics);
+        for (key, count) in run.errors {
+            *self.errors.entry(key).or_default() += count;
+        }
+        if self.keep_samples {
+            self.run_points.push(run.points.clone());
+            self.run_spilled.push(run.spilled);
+        }
+        append(&mut self.points, run.points);
+        self.breakdowns.extend(run.breakdown);
+        append(&mut self.threads, run.threads);
+        append(&mut self.bins, run.bins);
+        append(&mut self.depths, run.depths);
+        append(&mut self.idle_gaps, run.idle_gaps);
+        self.phases.extend(run.phases);
+        self.counters.push(run.counters);
+        self.chrome_trace.append(run.chrome_trace);
+        for (parent, (total, children)) in run.nested {
+            let (parent_total, parent_children) = self.nested.entry(parent).or_default();
+            *parent_total += total;
+            for (child, duration) in children {
+                *parent_children.entry(child).or_default() += duration;
+            }
+        }
+        append(&mut self.summaries, run.summaries);
+        self.functions.extend(run.functions);
+        flamegraph::append(&mut self.flamegraph, run.flamegraph);
+        append(&mut self.span_args, run.span_args);
+        for (name, shares) in run.cluster_time {
+            let time = self.cluster_time.entry(name).or_default();
+            for (cluster, duration) in shares {
+                *time.entry(cluster).or_default() += duration;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use rayon::prelude::*;
+
+    const FIXTURES: [&str; 3] = [
+        include_str!("../tests/fixtures/run1.ftrace"),
+        include_str!("../tests/fixtures/run2.ftrace"),
+        include_str!("../tests/fixtures/run3.ftrace"),
+    ];
+
+    fn session<'a>(
+        analysis: &Analysis<'a>,
+        reports: &[parse::ParseReport],
+        jobs: usize,
+    ) -> Session<'a> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build()
+            .unwrap();
+        let runs = pool.install(|| {
+            reports
+                .par_iter()
+                .enumerate()
+                .map(|(i, report)| analysis.analyze(i + 1, report))
+                .collect::<Vec<_>>()
+        });
+        let mut session = Session::new(analysis.args);
+        for run in runs {
+            session.add(run);
+        }
+        session
+    }
+
+    #[test]
+    fn parallel_analysis_equals_sequential() {
+        let args = Args::parse_from([
+            "hitrace-bench",
+            "--from-file=run1.ftrace",
+            "--from-file=run2.ftrace",
+            "--from-file=run3.ftrace",
+            "--span=LoadPage",
+            "--span=prefix:Layout",
+            "--span-parent=prefix:Layout=LoadPage",
+            "--latency=Paint=PaintStart..PaintEnd",
+            "--counter=mem",
+        ]);
+        let reports = FIXTURES
+            .iter()
+            .map(|f| parse::parse_report(f.as_bytes(), "").unwrap())
+            .collect::<Vec<_>>();
+        let filters = Vec::new();
+        let start_offset_names = HashMap::new();
+        let analysis = Analysis {
+            args: &args,
+            filters: &filters,
+            breakdown_filter: None,
+            clusters: &[],
+            bin_width: Duration::milliseconds(10),
+            start_offset_names: &start_offset_names,
+        };
+        let sequential = session(&analysis, &reports, 1);
+        let parallel = session(&analysis, &reports, 4);
+        assert_eq!(sequential.results, parallel.results);
+        assert_eq!(sequential.points, parallel.points);
+        assert_eq!(sequential.errors, parallel.errors);
+        assert_eq!(sequential.run_metrics, parallel.run_metrics);
+        assert_eq!(sequential.counters, parallel.counters);
+        assert_eq!(sequential.nested, parallel.nested);
+        assert_eq!(sequential.results["LoadPage"].len(), 3);
+        assert_eq!(
+            sequential.results["LoadPage"],
+            [
+                Duration::microseconds(255),
+                Duration::microseconds(400),
+                Duration::microseconds(310)
+            ]
+        );
+    }
+
+    #[test]
+    fn streaming_analysis_equals_full_analysis() {
+        let args = Args::parse_from([
+            "hitrace-bench",
+            "--from-file=run1.ftrace",
+            "--stream",
+            "--span=LoadPage",
+            "--span=prefix:Layout",
+        ]);
+        let filters = vec![Filter {
+            name: "Paint",
+            first: |t| t.function.contains("PaintStart"),
+            last: |t| t.function.contains("PaintEnd"),
+        }];
+        let start_offset_names = HashMap::new();
+        let analysis = Analysis {
+            args: &args,
+            filters: &filters,
+            breakdown_filter: None,
+            clusters: &[],
+            bin_width: Duration::milliseconds(10),
+            start_offset_names: &start_offset_names,
+        };
+        for fixture in FIXTURES {
+            let report = parse::parse_report(fixture.as_bytes(), "").unwrap();
+            let full = analysis.analyze(1, &report);
+            let streamed = analysis
+                .analyze_stream(1, parse::TraceReader::new(fixture.as_bytes(), "").unwrap())
+                .unwrap();
+            assert_eq!(full.results, streamed.results);
+            assert_eq!(full.points, streamed.points);
+            assert_eq!(full.errors, streamed.errors);
+            assert_eq!(full.metrics, streamed.metrics);
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! Flag runs that are far away from the median of the session
+use std::collections::BTreeSet;
+
+use crate::{args::Args, correlation::RunMetrics, filter::Filter};
+
+/// A run whose value is suspicious
+pub(crate) struct Anomaly {
+    /// Index of the run starting at 0
+    pub(crate) run: usize,
+    /// Median of the metric over the session
+    pub(crate) median: f64,
+    /// Distance to the median in median absolute deviations
+    pub(crate) mads: f64,
+}
+
+/// Median of the values, None if there are no values
+fn median_of(mut values: Vec<f64>) -> Option<f64> {
+    values.sort_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    if values.is_empty() {
+        None
+    } else if values.len().is_multiple_of(2) {
+        Some((values[mid - 1] + values[mid]) / 2.0)
+    } else {
+        Some(values[mid])
+    }
+}
+
+/// Find the runs whose value of the metric is more than `threshold` median absolute deviations from the median.
+/// Runs without the metric are never flagged.
+pub(crate) fn find_anomalies(runs: &[RunMetrics], metric: &str, threshold: f64) -> Vec<Anomaly> {
+    let values = runs
+        .iter()
+        .enumerate()
+        .filter_map(|(run, m)| m.get(metric).map(|v| (run, *v)))
+        .collect::<Vec<_>>();
+    let Some(median) = median_of(values.iter().map(|(_run, v)| *v).collect()) else {
+        return Vec::new();
+    };
+    let mad = median_of(values.iter().map(|(_run, v)| (v - median).abs()).collect()).unwrap();
+
+    values
+        .into_iter()
+        .filter(|(_run, value)| (value - median).abs() > threshold * mad)
+        .map(|(run, value)| Anomaly {
+            run,
+            median,
+            mads: (value - median).abs() / mad,
+        })
+        .collect()
+}
+
+/// The metrics of `--anomaly-metric`, all filters if none were given
+pub(crate) fn considered_metrics(args: &Args, filters: &[Filter]) -> Vec<String> {
+    if args.anomaly_metrics.is_empty() {
+        filters.iter().map(|f| f.name.to_owned()).collect()
+    } else {
+        args.anomaly_metrics.clone()
+    }
+}
+
+/// The runs flagged in any of the metrics, as indices into the runs
+pub(crate) fn flagged_runs(
+    runs: &[RunMetrics],
+    metrics: &[String],
+    threshold: f64,
+) -> BTreeSet<usize> {
+    metrics
+        .iter()
+        .flat_map(|m| find_anomalies(runs, m, threshold))
+        .map(|a| a.run)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_far_from_the_median_are_flagged() {
+        let runs =
+            [1.0, 1.1, 0.9, 1.0, 5.0].map(|v| RunMetrics::from([(String::from("Layout"), v)]));
+        let flagged = flagged_runs(&runs, &[String::from("Layout")], 3.0);
+        assert_eq!(flagged, BTreeSet::from([4]));
+        assert!(flagged_runs(&runs, &[String::from("Paint")], 3.0).is_empty());
+    }
+}
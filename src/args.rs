@@ -1,9 +1,31 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+use crate::{
+    backend::Backend,
+    clusters::{Cluster, parse_cluster},
+    compare::CompareFormat,
+    counters::{CounterWindow, parse_counter_window},
+    keep::KeepPolicy,
+    latency::{LatencyFilter, parse_latency_filter},
+    matcher::{SpanFilter, parse_span_filter},
+    matrix::{Target, parse_target},
+    nesting::parse_span_parent,
+    outliers::OutlierPolicy,
+    payload::{PayloadFilter, SpanArgFilter, parse_payload_filter, parse_span_arg_filter},
+    predicate::Predicate,
+    report::ReportFormat,
+    score::{ScoreComponent, parse_score_component},
+    store::Statistic,
+};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 /// Run servo on an open harmony device and collect timing information
 pub(crate) struct Args {
+    #[command(subcommand)]
+    pub(crate) command: Option<Command>,
+
     #[arg(short, long)]
     /// Show all traces for servo
     pub(crate) all_traces: bool,
@@ -12,6 +34,11 @@ pub(crate) struct Args {
     #[arg(short = 'n', long, default_value_t = 1)]
     pub(crate) tries: usize,
 
+    /// Number of runs before the tries that are analyzed but not part of the statistics.
+    /// When reading trace files the first files are the warmup runs
+    #[arg(long, default_value_t = 0)]
+    pub(crate) warmup: usize,
+
     /// The homepage we try to load
     #[arg(short = 'p', long, default_value_t = String::from("https://servo.org"))]
     pub(crate) homepage: String,
@@ -28,6 +55,20 @@ pub(crate) struct Args {
     #[arg(short, long, default_value_t = false)]
     pub(crate) computer_output: bool,
 
+    /// Print the statistics as a markdown, csv, Bencher Metric Format or json report instead of the plain text output.
+    /// The markdown report has the deltas against `--baseline`, marking changes beyond `--fail-threshold`.
+    /// The json report is the document of `--json`
+    #[arg(long, visible_alias = "output", value_enum, conflicts_with_all = ["computer_output", "bencher"])]
+    pub(crate) report: Option<ReportFormat>,
+
+    /// Write the report of `--report` to this file instead of printing it
+    #[arg(long, requires = "report")]
+    pub(crate) output_file: Option<PathBuf>,
+
+    /// The device family to trace, hitrace over hdc for OpenHarmony or atrace over adb for Android
+    #[arg(long, value_enum, default_value_t = Backend::Hitrace)]
+    pub(crate) backend: Backend,
+
     /// Name of the app bundle to start
     #[arg(short, long, default_value_t = String::from("org.servo.servo"))]
     pub(crate) bundle_name: String,
@@ -35,4 +76,522 @@ pub(crate) struct Args {
     /// Use Bencher output format
     #[arg(long, default_value_t = false)]
     pub(crate) bencher: bool,
+
+    /// Break the window of the named filter down into the spans running in it
+    #[arg(long)]
+    pub(crate) breakdown: Option<String>,
+
+    /// Collect the durations of all spans with the given function name. Can be given multiple times.
+    /// Prefix with `prefix:`, `contains:` or `re:` to match names starting with, containing or matching a regex
+    #[arg(long = "span", value_parser = parse_span_filter)]
+    pub(crate) spans: Vec<SpanFilter>,
+
+    /// Only analyze the traces of the processes with this name, resolved in every run as the app gets a new pid
+    /// every launch. Takes a span filter matcher, i.e., `prefix:org.servo` for all processes of a multi-process app
+    #[arg(long, value_parser = parse_span_filter)]
+    pub(crate) process: Option<SpanFilter>,
+
+    /// Only measure the spans whose start trace matches the expression, i.e.,
+    /// `function=prefix:Layout && pid_of(org.servo.servo)`. Conditions are `function=`, `thread=`, `process=`,
+    /// `arg(<key>)=` with a span filter matcher, `pid=`, `tid=` and `pid_of(<process>)`, combined with `&&`, `||`,
+    /// `!` and parentheses. Can be given multiple times, all have to match
+    #[arg(long = "filter")]
+    pub(crate) predicates: Vec<Predicate>,
+
+    /// End-to-end latency of the async spans of the span filter that start in one process and end in another,
+    /// i.e., from the input event in the content process to the frame presented by the compositor.
+    /// Can be given multiple times
+    #[arg(long = "handoff", value_parser = parse_span_filter)]
+    pub(crate) handoffs: Vec<SpanFilter>,
+
+    /// Analyze the frames given by the spans of the span filter, i.e., the vsync or composition spans.
+    /// Reports the frame time between the ends of consecutive spans, the frames per second, the janky frames
+    /// that took longer than `--frame-budget`, the dropped frames and the longest stutter of every run
+    #[arg(long, value_parser = parse_span_filter)]
+    pub(crate) frames: Option<SpanFilter>,
+
+    /// Frame budget in milliseconds, i.e., `8.3` for a 120 Hz display
+    #[arg(long, default_value_t = 16.7, requires = "frames")]
+    pub(crate) frame_budget: f64,
+
+    /// Only count the spans of a span filter that start inside a span of another span filter of the same
+    /// process as `child=parent`. Can be given multiple times
+    #[arg(long = "span-parent", value_parser = parse_span_parent)]
+    pub(crate) span_parents: Vec<(String, String)>,
+
+    /// Split the spans of every span filter by the thread they ran on
+    #[arg(long, default_value_t = false)]
+    pub(crate) per_thread: bool,
+
+    /// Split the time of every span filter into running, runnable and blocked and count the context switches.
+    /// Traces with the `sched` category
+    #[arg(long, default_value_t = false)]
+    pub(crate) sched_stats: bool,
+
+    /// Sample the memory of the app every this many milliseconds while it runs and add its peak and average
+    /// pss to the points. Reads `/proc/<pid>/smaps_rollup` and falls back to `hidumper --mem <pid>`
+    #[arg(long, conflicts_with = "from_files")]
+    pub(crate) memory_interval: Option<u64>,
+
+    /// Number of threads shown in the per thread split before merging the rest into other
+    #[arg(long, default_value_t = 3)]
+    pub(crate) top_threads: usize,
+
+    /// Report this many functions with the highest self time over all spans, with their calls, total and
+    /// mean time and the share of the run they took
+    #[arg(long)]
+    pub(crate) functions: Option<usize>,
+
+    /// Function of the trace at which the time of a run starts, defaults to the first trace
+    #[arg(long)]
+    pub(crate) anchor: Option<String>,
+
+    /// Bucket the span starts of the span filter into time bins relative to the anchor. Can be given multiple times
+    #[arg(long = "bins")]
+    pub(crate) bins: Vec<String>,
+
+    /// Width of the time bins in milliseconds
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u64).range(1..))]
+    pub(crate) bin_width: u64,
+
+    /// Correlate the per run values of the given metrics with each other. Can be given multiple times.
+    /// Metrics are filter names, `<span filter> count`, `unmatched ends`, `unterminated starts` and `lost events`.
+    /// The json of `--json` always has the correlations of every pair of metrics
+    #[arg(long = "correlate")]
+    pub(crate) correlate: Vec<String>,
+
+    /// Latency between every occurrence of two instant markers as `name=Start..End`.
+    /// With `name=Start..End:id` only markers with the same `id=` argument are paired. Can be given multiple times
+    #[arg(long = "latency", visible_alias = "interval-all", value_parser = parse_latency_filter)]
+    pub(crate) latencies: Vec<LatencyFilter>,
+
+    /// Time from the first occurrence of a marker to the first occurrence of another marker after it
+    /// in the same process as `name=Start..End`. Can be given multiple times
+    #[arg(long = "interval", value_parser = parse_latency_filter)]
+    pub(crate) intervals: Vec<LatencyFilter>,
+
+    /// Collect every value of the counter with the given name. Can be given multiple times
+    #[arg(long = "counter")]
+    pub(crate) counters: Vec<String>,
+
+    /// Peak, average and change of a counter while the first span of a function was open as `name=counter@function`.
+    /// Can be given multiple times
+    #[arg(long = "counter-in-span", value_parser = parse_counter_window)]
+    pub(crate) counter_windows: Vec<CounterWindow>,
+
+    /// Ordered list of milestone markers, the phases between consecutive milestones are reported
+    #[arg(long, value_delimiter = ',')]
+    pub(crate) phases: Vec<String>,
+
+    /// Discard outliers in the durations of every filter before computing the statistics
+    #[arg(long, value_enum, default_value_t = OutlierPolicy::None)]
+    pub(crate) outliers: OutlierPolicy,
+
+    /// Fraction of the smallest and of the largest durations discarded by `--outliers trim`
+    #[arg(long, default_value_t = 0.1, value_parser = parse_trim_fraction)]
+    pub(crate) trim_fraction: f64,
+
+    /// Also report this percentile of every filter and point, e.g. `95` or `99.9`. Can be given multiple times
+    #[arg(long = "percentile", value_parser = parse_percentile)]
+    pub(crate) percentiles: Vec<f64>,
+
+    /// Flag runs that are more than this many median absolute deviations away from the median of the session
+    #[arg(long)]
+    pub(crate) mad_threshold: Option<f64>,
+
+    /// Metric considered for flagging runs, defaults to all filters. Can be given multiple times
+    #[arg(long = "anomaly-metric")]
+    pub(crate) anomaly_metrics: Vec<String>,
+
+    /// Report p99 and p99.9 over the samples of every span and latency filter
+    #[arg(long, default_value_t = false)]
+    pub(crate) tail: bool,
+
+    /// Minimum number of samples needed to report a tail percentile
+    #[arg(long, default_value_t = 100)]
+    pub(crate) tail_min_samples: usize,
+
+    /// Report the gaps in the spans of the span filter where no child span was open on the same thread.
+    /// Can be given multiple times
+    #[arg(long = "idle-gaps")]
+    pub(crate) idle_gaps: Vec<String>,
+
+    /// Report the offset from the anchor at which the span filter first starts as `start@<name>`.
+    /// Can be given multiple times
+    #[arg(long = "start-offset")]
+    pub(crate) start_offsets: Vec<String>,
+
+    /// Write every sample and point value of every run to this file, as json if it ends in `.json`
+    /// and as csv otherwise. With `--baseline` the samples of the baseline are in it too, the `side`
+    /// column is `baseline` or `current`
+    #[arg(long, visible_alias = "csv")]
+    pub(crate) samples: Option<PathBuf>,
+
+    /// Keep at most this many samples per filter and run in the sample export
+    #[arg(long)]
+    pub(crate) sample_limit: Option<usize>,
+
+    /// Write all results, statistics and per run values as json to this file.
+    /// With `-` the json is printed instead of the results
+    #[arg(long)]
+    pub(crate) json: Option<PathBuf>,
+
+    /// Export the spans of the span filters and all instant traces of every run as Chrome trace events.
+    /// Without span filters all spans are exported
+    #[arg(long, visible_alias = "export-trace-json")]
+    pub(crate) chrome_trace: Option<PathBuf>,
+
+    /// Fold the sync spans of all runs into stacks of process and open spans weighted by self time.
+    /// Written as flamegraph svg if the path ends in `.svg` and as collapsed stack lines otherwise
+    #[arg(long)]
+    pub(crate) flamegraph: Option<PathBuf>,
+
+    /// Compare the results against this file written by `--json`
+    #[arg(long)]
+    pub(crate) baseline: Option<PathBuf>,
+
+    /// Fail if the mean of any filter is more than this many percent above the baseline, e.g. `5` or `5%`
+    #[arg(long, requires = "baseline", visible_alias = "fail-on-regression", value_parser = parse_threshold)]
+    pub(crate) fail_threshold: Option<f64>,
+
+    /// Copy the trace of every run into this directory with a `manifest.json` of when the runs were captured
+    /// and the mean of every filter per run. The runs of the `--json` output reference their trace file
+    #[arg(long, conflicts_with = "from_files")]
+    pub(crate) keep_traces: Option<PathBuf>,
+
+    /// Which traces of `--keep-traces` stay after the session, `flagged` keeps the runs flagged by `--mad-threshold`
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = KeepPolicy::All,
+        requires = "keep_traces",
+        requires_if("flagged", "mad_threshold")
+    )]
+    pub(crate) keep_policy: KeepPolicy,
+
+    /// Write a self-contained html report with the statistics, the distribution of every filter,
+    /// the device and app version and the deltas against `--baseline` to `index.html` in this directory
+    #[arg(long)]
+    pub(crate) html_report: Option<PathBuf>,
+
+    /// Label of the build under test, i.e., the commit
+    #[arg(long, default_value_t = String::new())]
+    pub(crate) label: String,
+
+    /// Filter taking part in the score as `filter:weight:reference_ms`. The score is the weighted geometric mean
+    /// of the filter averages divided by their reference. Can be given multiple times
+    #[arg(long = "score", value_parser = parse_score_component)]
+    pub(crate) score: Vec<ScoreComponent>,
+
+    /// Toml file with budgets per filter. The run fails if a budget is exceeded
+    #[arg(long)]
+    pub(crate) budgets: Option<PathBuf>,
+
+    /// Fail if any run lost trace events because the trace buffer overflowed
+    #[arg(long, default_value_t = false)]
+    pub(crate) fail_on_lost_events: bool,
+
+    /// Keep the runs that lost trace events in the statistics instead of excluding them
+    #[arg(long, default_value_t = false)]
+    pub(crate) allow_lossy: bool,
+
+    /// Sum, maximum and mean per run of a numeric argument of an instant marker as `name=Marker:key`.
+    /// Can be given multiple times
+    #[arg(long = "point-arg", value_parser = parse_payload_filter)]
+    pub(crate) payloads: Vec<PayloadFilter>,
+
+    /// A number in the arguments of every span of a span filter as point, with its correlation to the duration
+    /// of the span. Given as `name=<span filter>@<regex>`, the first capture group is the number, i.e.,
+    /// `decode size=prefix:ImageDecode@size=(\d+)`. Can be given multiple times
+    #[arg(long = "span-arg", value_parser = parse_span_arg_filter)]
+    pub(crate) span_args: Vec<SpanArgFilter>,
+
+    /// A cpu cluster as `name=0-3`, the time of every span filter is split over the clusters.
+    /// With `--sched-stats` the running time of the spans is split by the cpus they ran on, otherwise the whole
+    /// span counts for the cpu it started on. Can be given multiple times
+    #[arg(long = "cluster", value_parser = parse_cluster)]
+    pub(crate) clusters: Vec<Cluster>,
+
+    /// Read the cpu clusters from the cpufreq policies of the device
+    #[arg(long, default_value_t = false, conflicts_with = "clusters")]
+    pub(crate) detect_clusters: bool,
+
+    /// Split the results of every span filter by process. Runs where all spans of a filter are in one
+    /// process keep only the merged result
+    #[arg(long, default_value_t = false, visible_alias = "per-process")]
+    pub(crate) split_by_process: bool,
+
+    /// Split the results of the span filter by process like `--split-by-process`. Can be given multiple times
+    #[arg(long = "split-process")]
+    pub(crate) split_processes: Vec<String>,
+
+    /// Print a histogram of the values under every filter and point
+    #[arg(long, default_value_t = false)]
+    pub(crate) histogram: bool,
+
+    /// Number of buckets of the histograms
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+    pub(crate) histogram_buckets: u64,
+
+    /// Use buckets of equal width on a log scale for the histograms
+    #[arg(long, default_value_t = false, requires = "histogram")]
+    pub(crate) histogram_log: bool,
+
+    /// Abort a run on the device after this many seconds, stopping the trace and the app. It counts as a timeout error
+    #[arg(long)]
+    pub(crate) timeout: Option<u64>,
+
+    /// Retry a run that failed, timed out or matched no filter at all up to this many times.
+    /// A run that still fails in its last attempt is left out of the results and counted as failed run
+    #[arg(long, default_value_t = 0)]
+    pub(crate) retries: u32,
+
+    /// Seconds to wait before the first retry of a run, doubled for every further retry
+    #[arg(long, default_value_t = 1)]
+    pub(crate) retry_backoff: u64,
+
+    /// Analyze this trace file instead of tracing the device. Every file is one run, can be given multiple times
+    #[arg(long = "from-file", visible_alias = "trace-file", conflicts_with_all = ["tries", "detect_clusters"])]
+    pub(crate) from_files: Vec<PathBuf>,
+
+    /// Read the options from this toml file, options on the command line override it
+    #[arg(long)]
+    pub(crate) config: Option<PathBuf>,
+
+    /// The benchmark of the config file to run
+    #[arg(long, requires = "config")]
+    pub(crate) benchmark: Option<String>,
+
+    /// Number of threads analyzing the runs, defaults to the number of cpus
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub(crate) jobs: Option<u64>,
+
+    /// Serial of the device to trace, needed when more than one device is connected
+    #[arg(long, conflicts_with = "from_files")]
+    pub(crate) device: Option<String>,
+
+    /// Run the benchmark on every connected device and compare the devices. The json has the document of
+    /// every device by serial, `--store` stores a session per device and a baseline written with all devices
+    /// compares every device against its own results. `--budgets` and `--fail-on-lost-events` check every device
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with_all = ["device", "from_files", "detect_clusters", "bencher", "computer_output", "samples", "chrome_trace", "flamegraph", "html_report"]
+    )]
+    pub(crate) all_devices: bool,
+
+    /// Benchmark every target with the full number of runs instead of `--homepage` and compare the targets.
+    /// Given as `name=<url> [<launch arg>...]` where the launch args are added to `aa start`, i.e.,
+    /// `webgpu=https://servo.org --ps=--pref dom_webgpu_enabled=true`. Can be given multiple times,
+    /// a config file lists them as `target = [...]`. The json has the document of every target by name,
+    /// `--store` stores a session per target and a baseline written with targets compares every target
+    /// against its own results. `--budgets` and `--fail-on-lost-events` check every target
+    #[arg(
+        long = "target",
+        value_parser = parse_target,
+        conflicts_with_all = ["all_devices", "from_files", "bencher", "computer_output", "samples", "chrome_trace", "flamegraph", "html_report"]
+    )]
+    pub(crate) targets: Vec<Target>,
+
+    /// Rank the targets or devices by this filter or point, the ones that regressed most against their baseline
+    /// first, and list the noisiest ones. The json has the full ranking
+    #[arg(long)]
+    pub(crate) rank: Option<String>,
+
+    /// Number of targets or devices shown in the rankings of `--rank`
+    #[arg(long, default_value_t = 10, requires = "rank")]
+    pub(crate) rank_top: usize,
+
+    /// Trace the devices of `--all-devices` at the same time, one thread per device
+    #[arg(long, default_value_t = false, requires = "all_devices")]
+    pub(crate) parallel_devices: bool,
+
+    /// Analyze the trace files while reading them, keeping only the open spans in memory.
+    /// Only the filters and span filters are analyzed
+    #[arg(
+        long,
+        default_value_t = false,
+        requires = "from_files",
+        conflicts_with_all = [
+            "all_traces", "breakdown", "span_parents", "per_thread", "functions", "bins", "latencies",
+            "intervals", "counters", "counter_windows", "phases", "idle_gaps", "start_offsets", "chrome_trace",
+            "flamegraph", "sched_stats", "predicates", "process", "payloads", "span_args", "handoffs", "frames", "clusters", "split_by_process", "split_processes",
+        ]
+    )]
+    pub(crate) stream: bool,
+
+    /// Write the spans of the span filters to a temporary file while streaming, so memory does not grow with
+    /// their number. Their count and total are exact, the other statistics come from a sample of 10000 spans
+    /// of every run. The sample export reads all spans back from the file
+    #[arg(long, default_value_t = false, requires = "stream")]
+    pub(crate) spill: bool,
+
+    /// Include the spans that are still open at the end of the trace, ending them at the last trace.
+    /// They are still counted as errors of their span filter
+    #[arg(long, default_value_t = false, conflicts_with = "stream")]
+    pub(crate) include_truncated: bool,
+
+    /// Append the results of the session to this SQLite database, see the history subcommand
+    #[arg(long)]
+    pub(crate) store: Option<PathBuf>,
+
+    /// Run this command on the device with hdc shell before every run. Can be given multiple times
+    #[arg(long = "pre-run-cmd", conflicts_with = "from_files")]
+    pub(crate) pre_run_cmds: Vec<String>,
+
+    /// Run this command on the device with hdc shell after every run. Can be given multiple times
+    #[arg(long = "post-run-cmd", conflicts_with = "from_files")]
+    pub(crate) post_run_cmds: Vec<String>,
+
+    /// Skip the run when one of its pre or post run commands fails, instead of only counting the failure
+    #[arg(long, default_value_t = false)]
+    pub(crate) abort_on_hook_failure: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub(crate) enum Command {
+    /// Test whether the results of two `--json` files differ by more than noise
+    Compare {
+        /// The results before the change
+        old: PathBuf,
+        /// The results after the change
+        new: PathBuf,
+        /// Confidence level of the intervals, differences with a p-value below 1 - confidence are significant
+        #[arg(long, default_value_t = 0.95, value_parser = parse_confidence)]
+        confidence: f64,
+        /// Fail if the number of spans per run of a span filter grew significantly by more than this many
+        /// percent, e.g. `10` or `10%`
+        #[arg(long, value_parser = parse_threshold)]
+        count_threshold: Option<f64>,
+        /// Print the comparison as text, a markdown table or json
+        #[arg(long, value_enum, default_value_t = CompareFormat::Text)]
+        format: CompareFormat,
+        /// Write the samples of both files to this file with a `side` column of `old` or `new`, as json if it
+        /// ends in `.json` and as csv otherwise
+        #[arg(long, visible_alias = "csv")]
+        samples: Option<PathBuf>,
+        /// Keep at most this many samples per filter and run in the sample export
+        #[arg(long)]
+        sample_limit: Option<usize>,
+    },
+    /// Keep tracing the device and print the count, p50 and p95 of every span filter until interrupted
+    Monitor {
+        /// Seconds of spans the statistics are over
+        #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+        window: u64,
+        /// Print the statistics every this many seconds
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+        every: u64,
+    },
+    /// Print the trend of a filter over the sessions stored with `--store`
+    History {
+        /// The database written by `--store`
+        store: PathBuf,
+        /// The filter or point
+        name: String,
+        /// Number of sessions to show, the latest ones
+        #[arg(long, default_value_t = 10)]
+        last: usize,
+    },
+    /// Fit a line through a statistic of a filter over the sessions stored with `--store`, printing its slope,
+    /// the drift over the sessions and the largest step between two sessions
+    Trend {
+        /// The database written by `--store`
+        store: PathBuf,
+        /// The filter or point
+        name: String,
+        /// Number of sessions the trend is over, the latest ones
+        #[arg(long, default_value_t = 10)]
+        last: usize,
+        /// The statistic of every session
+        #[arg(long, value_enum, default_value_t = Statistic::Avg)]
+        statistic: Statistic,
+        /// Only the sessions whose label or commit matches this glob, i.e., `nightly-*`
+        #[arg(long)]
+        label: Option<String>,
+        /// Only the sessions of the device with this serial
+        #[arg(long)]
+        device: Option<String>,
+        /// Only the sessions of the target with this name
+        #[arg(long)]
+        target: Option<String>,
+        /// Print the sessions and the trend as json
+        #[arg(long, default_value_t = false)]
+        json: bool,
+    },
+}
+
+/// Parse a confidence level, it has to be in (0, 1)
+fn parse_confidence(s: &str) -> Result<f64, String> {
+    let confidence: f64 = s
+        .parse()
+        .map_err(|e| format!("Invalid confidence {}: {}", s, e))?;
+    if confidence > 0.0 && confidence < 1.0 {
+        Ok(confidence)
+    } else {
+        Err(format!("The confidence {} is not in (0, 1)", s))
+    }
+}
+
+/// Parse a regression threshold in percent, a trailing `%` is allowed
+fn parse_threshold(s: &str) -> Result<f64, String> {
+    s.strip_suffix('%')
+        .unwrap_or(s)
+        .trim()
+        .parse()
+        .map_err(|e| format!("Invalid threshold {}: {}", s, e))
+}
+
+/// Parse a trim fraction, it has to be in [0, 0.5)
+fn parse_trim_fraction(s: &str) -> Result<f64, String> {
+    let fraction: f64 = s
+        .parse()
+        .map_err(|e| format!("Invalid trim fraction {}: {}", s, e))?;
+    if (0.0..0.5).contains(&fraction) {
+        Ok(fraction)
+    } else {
+        Err(format!("The trim fraction {} is not in [0, 0.5)", s))
+    }
+}
+
+/// Parse a percentile, it has to be in (0, 100]
+fn parse_percentile(s: &str) -> Result<f64, String> {
+    let p: f64 = s
+        .parse()
+        .map_err(|e| format!("Invalid percentile {}: {}", s, e))?;
+    if p > 0.0 && p <= 100.0 {
+        Ok(p)
+    } else {
+        Err(format!("The percentile {} is not in (0, 100]", s))
+    }
+}
+
+impl Args {
+    /// The json document goes to stdout
+    pub(crate) fn json_to_stdout(&self) -> bool {
+        self.json
+            .as_ref()
+            .is_some_and(|path| path.as_os_str() == "-")
+    }
+
+    /// The json document is written, with `--json` or as the report
+    pub(crate) fn writes_json(&self) -> bool {
+        self.json.is_some() || self.report == Some(ReportFormat::Json)
+    }
+
+    /// Stdout is a machine readable document, so nothing else may be printed to it
+    pub(crate) fn quiet(&self) -> bool {
+        self.bencher
+            || (self.report.is_some() && self.output_file.is_none())
+            || self.json_to_stdout()
+    }
+
+    /// The number of runs including the warmup runs, one per trace file when reading files
+    pub(crate) fn runs(&self) -> usize {
+        if self.from_files.is_empty() {
+            self.warmup + self.tries
+        } else {
+            self.from_files.len()
+        }
+    }
 }
@@ -0,0 +1,343 @@
+//! The tools a device family is traced with, selected with `--backend`
+//!
+//! OpenHarmony traces with hitrace over hdc and Android with atrace over adb. Both write the ftrace text
+//! format with `tracing_mark_write` lines, so only the commands differ.
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use std::process::Command;
+
+use crate::parse::TraceFormat;
+
+/// Where the trace is written on the device before it is received
+pub(crate) const REMOTE_TRACE: &str = "/data/local/tmp/ohtrace.txt";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, ValueEnum)]
+/// The device family
+pub(crate) enum Backend {
+    /// OpenHarmony with hitrace over hdc
+    #[default]
+    Hitrace,
+    /// Android with atrace over adb
+    Atrace,
+}
+
+impl Backend {
+    /// The implementation of the backend
+    pub(crate) fn capture(self) -> &'static dyn CaptureBackend {
+        match self {
+            Backend::Hitrace => &Hitrace,
+            Backend::Atrace => &Atrace,
+        }
+    }
+}
+
+/// The commands of a device family. Shell commands are the arguments of `<tool> shell`
+pub(crate) trait CaptureBackend: Sync {
+    /// The name of the tool in the path talking to the devices
+    fn tool(&self) -> &'static str;
+
+    /// The option selecting the device by serial
+    fn serial_option(&self) -> &'static str;
+
+    /// The serials in the output of `list_command`
+    fn parse_targets(&self, output: &str) -> Vec<String>;
+
+    /// The arguments listing the connected devices
+    fn list_command(&self) -> &'static [&'static str];
+
+    /// Start tracing the categories of the app into a buffer of this many kB
+    fn start_trace(&self, buffer: u64, bundle_name: &str, sched: bool) -> Vec<String>;
+
+    /// Stop tracing and write the trace to [`REMOTE_TRACE`]
+    fn stop_trace(&self, buffer: u64) -> Vec<String>;
+
+    /// Start the app on the page
+    fn start_app(&self, bundle_name: &str, homepage: &str) -> Vec<String>;
+
+    /// Stop the app
+    fn force_stop(&self, bundle_name: &str) -> Vec<String>;
+
+    /// The arguments copying a file from the device
+    fn receive(&self, remote: &str) -> Vec<String>;
+
+    /// The mount point of the tracefs
+    fn tracefs(&self) -> &'static str;
+
+    /// Print the model of the device
+    fn model(&self) -> Vec<String>;
+
+    /// Print the information of the installed app
+    fn app_info(&self, bundle_name: &str) -> Vec<String>;
+
+    /// The version name in the output of `app_info`
+    fn parse_app_version(&self, info: &str) -> Option<String>;
+
+    /// Print the memory of the process, for devices where `smaps_rollup` is not readable
+    fn memory_dump(&self, pid: &str) -> Option<Vec<String>>;
+
+    /// The dialect of the trace
+    fn trace_format(&self) -> TraceFormat;
+}
+
+fn owned(args: &[&str]) -> Vec<String> {
+    args.iter().map(|a| a.to_string()).collect()
+}
+
+/// OpenHarmony
+struct Hitrace;
+
+impl CaptureBackend for Hitrace {
+    fn tool(&self) -> &'static str {
+        "hdc"
+    }
+
+    fn serial_option(&self) -> &'static str {
+        "-t"
+    }
+
+    fn list_command(&self) -> &'static [&'static str] {
+        &["list", "targets"]
+    }
+
+    fn parse_targets(&self, output: &str) -> Vec<String> {
+        output
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && *l != "[Empty]")
+            .map(str::to_owned)
+            .collect()
+    }
+
+    fn start_trace(&self, buffer: u64, _bundle_name: &str, sched: bool) -> Vec<String> {
+        let buffer = buffer.to_string();
+        let mut hitrace = vec![
+            "hitrace", "-b", &buffer, "app", "graphic", "ohos", "freq", "idle", "memory",
+        ];
+        if sched {
+            hitrace.push("sched");
+        }
+        hitrace.push("--trace_begin");
+        owned(&hitrace)
+    }
+
+    fn stop_trace(&self, buffer: u64) -> Vec<String> {
+        owned(&[
+            "hitrace",
+            "-b",
+            &buffer.to_string(),
+            "--trace_finish",
+            "-o",
+            REMOTE_TRACE,
+        ])
+    }
+
+    fn start_app(&self, bundle_name: &str, homepage: &str) -> Vec<String> {
+        owned(&[
+            "aa",
+            "start",
+            "-a",
+            "EntryAbility",
+            "-b",
+            bundle_name,
+            "-U",
+            homepage,
+            "--ps=--pref",
+            "js_disable_jit=true",
+        ])
+    }
+
+    fn force_stop(&self, bundle_name: &str) -> Vec<String> {
+        owned(&["aa", "force-stop", bundle_name])
+    }
+
+    fn receive(&self, remote: &str) -> Vec<String> {
+        owned(&["file", "recv", remote])
+    }
+
+    fn tracefs(&self) -> &'static str {
+        "/sys/kernel/debug/tracing"
+    }
+
+    fn model(&self) -> Vec<String> {
+        owned(&["param", "get", "const.product.model"])
+    }
+
+    fn app_info(&self, bundle_name: &str) -> Vec<String> {
+        owned(&["bm", "dump", "-n", bundle_name])
+    }
+
+    /// `bm dump` prints json with a `"versionName": "1.0.0",` line
+    fn parse_app_version(&self, info: &str) -> Option<String> {
+        info.lines()
+            .find_map(|l| l.trim().strip_prefix("\"versionName\":"))
+            .map(|v| v.trim().trim_end_matches(',').trim_matches('"').to_owned())
+    }
+
+    fn memory_dump(&self, pid: &str) -> Option<Vec<String>> {
+        Some(owned(&["hidumper", "--mem", pid]))
+    }
+
+    fn trace_format(&self) -> TraceFormat {
+        TraceFormat::Hitrace
+    }
+}
+
+/// Android
+struct Atrace;
+
+impl CaptureBackend for Atrace {
+    fn tool(&self) -> &'static str {
+        "adb"
+    }
+
+    fn serial_option(&self) -> &'static str {
+        "-s"
+    }
+
+    fn list_command(&self) -> &'static [&'static str] {
+        &["devices"]
+    }
+
+    /// Lines of `<serial>\tdevice` after the `List of devices attached` header, offline and unauthorized
+    /// devices are left out
+    fn parse_targets(&self, output: &str) -> Vec<String> {
+        output
+            .lines()
+            .filter_map(|l| l.split_once('\t'))
+            .filter(|(_serial, state)| state.trim() == "device")
+            .map(|(serial, _state)| serial.to_owned())
+            .collect()
+    }
+
+    fn start_trace(&self, buffer: u64, bundle_name: &str, sched: bool) -> Vec<String> {
+        let buffer = buffer.to_string();
+        let mut atrace = vec![
+            "atrace",
+            "--async_start",
+            "-b",
+            &buffer,
+            "-a",
+            bundle_name,
+            "gfx",
+            "view",
+            "input",
+            "freq",
+            "idle",
+            "memory",
+        ];
+        if sched {
+            atrace.push("sched");
+        }
+        owned(&atrace)
+    }
+
+    fn stop_trace(&self, buffer: u64) -> Vec<String> {
+        owned(&[
+            "atrace",
+            "--async_stop",
+            "-b",
+            &buffer.to_string(),
+            "-o",
+            REMOTE_TRACE,
+        ])
+    }
+
+    fn start_app(&self, bundle_name: &str, homepage: &str) -> Vec<String> {
+        owned(&[
+            "am",
+            "start",
+            "-a",
+            "android.intent.action.VIEW",
+            "-d",
+            homepage,
+            bundle_name,
+        ])
+    }
+
+    fn force_stop(&self, bundle_name: &str) -> Vec<String> {
+        owned(&["am", "force-stop", bundle_name])
+    }
+
+    fn receive(&self, remote: &str) -> Vec<String> {
+        owned(&["pull", remote])
+    }
+
+    fn tracefs(&self) -> &'static str {
+        "/sys/kernel/tracing"
+    }
+
+    fn model(&self) -> Vec<String> {
+        owned(&["getprop", "ro.product.model"])
+    }
+
+    fn app_info(&self, bundle_name: &str) -> Vec<String> {
+        owned(&["dumpsys", "package", bundle_name])
+    }
+
+    /// `dumpsys package` prints a `versionName=1.0.0` line
+    fn parse_app_version(&self, info: &str) -> Option<String> {
+        info.lines()
+            .find_map(|l| l.trim().strip_prefix("versionName="))
+            .map(str::to_owned)
+    }
+
+    fn memory_dump(&self, _pid: &str) -> Option<Vec<String>> {
+        None
+    }
+
+    fn trace_format(&self) -> TraceFormat {
+        TraceFormat::Atrace
+    }
+}
+
+#[derive(Clone, Copy)]
+/// A device of the backend, without a serial it is the only connected device
+pub(crate) struct Device<'a> {
+    pub(crate) backend: &'static dyn CaptureBackend,
+    pub(crate) serial: Option<&'a str>,
+}
+
+impl<'a> Device<'a> {
+    pub(crate) fn new(backend: Backend, serial: Option<&'a str>) -> Self {
+        Device {
+            backend: backend.capture(),
+            serial,
+        }
+    }
+
+    /// A command of the tool talking to the device
+    pub(crate) fn command(&self) -> Result<Command> {
+        let tool = self.backend.tool();
+        let path = which::which(tool).with_context(|| format!("Is {} in the path?", tool))?;
+        let mut cmd = Command::new(path);
+        if let Some(serial) = self.serial {
+            cmd.args([self.backend.serial_option(), serial]);
+        }
+        Ok(cmd)
+    }
+
+    /// A command running the arguments in the shell of the device
+    pub(crate) fn shell<S: AsRef<std::ffi::OsStr>>(&self, args: &[S]) -> Result<Command> {
+        let mut cmd = self.command()?;
+        cmd.arg("shell").args(args);
+        Ok(cmd)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backends_list_their_devices() {
+        let hdc = "127.0.0.1:5555\n\n";
+        assert_eq!(Hitrace.parse_targets(hdc), ["127.0.0.1:5555"]);
+        assert!(Hitrace.parse_targets("[Empty]\n").is_empty());
+        let adb = "List of devices attached\nemulator-5554\tdevice\n0123456789\tunauthorized\n\n";
+        assert_eq!(Atrace.parse_targets(adb), ["emulator-5554"]);
+        assert_eq!(
+            Atrace.parse_app_version("    versionCode=1 minSdk=30\n    versionName=0.0.1\n"),
+            Some(String::from("0.0.1"))
+        );
+    }
+}
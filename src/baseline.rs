@@ -0,0 +1,262 @@
+//! Comparing the results against a baseline written by `--json`
+use anyhow::{Context, Result, anyhow};
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+};
+use time::Duration;
+
+use crate::{PointResults, RunResults, utils::avg_min_max};
+
+/// Version of the json document this can read
+const VERSION: u32 = 1;
+
+#[derive(Debug, Deserialize)]
+struct BaselineFilter {
+    samples_us: Vec<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BaselinePoint {
+    values: Vec<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BaselineRun {
+    #[serde(default)]
+    run: usize,
+    #[serde(default)]
+    results_us: BTreeMap<String, Vec<i64>>,
+    metrics: BTreeMap<String, f64>,
+}
+
+#[derive(Debug, Deserialize)]
+/// The parts of the json document needed for a comparison
+pub(crate) struct Baseline {
+    version: u32,
+    #[serde(default)]
+    label: String,
+    filters: BTreeMap<String, BaselineFilter>,
+    points: BTreeMap<String, BaselinePoint>,
+    #[serde(default)]
+    runs: Vec<BaselineRun>,
+}
+
+#[derive(Debug, Deserialize)]
+/// The json document of `--target` or `--all-devices`, a document of every target or device
+struct GroupedBaseline {
+    version: u32,
+    #[serde(default)]
+    targets: BTreeMap<String, Baseline>,
+    #[serde(default)]
+    devices: BTreeMap<String, Baseline>,
+}
+
+/// The baselines of the targets or devices of a session
+pub(crate) enum Baselines {
+    /// The baseline of a single session, every target or device is compared against it
+    Shared(Baseline),
+    /// The baseline of every target by name or device by serial
+    ByName(BTreeMap<String, Baseline>),
+}
+
+impl Baselines {
+    /// The baseline of the target or device, None if the baseline does not have it
+    pub(crate) fn get(&self, name: &str) -> Option<&Baseline> {
+        match self {
+            Baselines::Shared(baseline) => Some(baseline),
+            Baselines::ByName(baselines) => baselines.get(name),
+        }
+    }
+}
+
+fn check_version(path: &Path, version: u32) -> Result<()> {
+    if version != VERSION {
+        return Err(anyhow!(
+            "Baseline {:?} has version {} but only version {} is supported",
+            path,
+            version,
+            VERSION
+        ));
+    }
+    Ok(())
+}
+
+/// Read a baseline written by `--json`
+pub(crate) fn read_baseline(path: &Path) -> Result<Baseline> {
+    let content =
+        std::fs::read_to_string(path).with_context(|| format!("Could not read {:?}", path))?;
+    let baseline: Baseline = serde_json::from_str(&content)
+        .with_context(|| format!("Could not parse baseline in {:?}", path))?;
+    check_version(path, baseline.version)?;
+    Ok(baseline)
+}
+
+/// Read a baseline written by `--json` with or without `--target` or `--all-devices`
+pub(crate) fn read_baselines(path: &Path) -> Result<Baselines> {
+    let content =
+        std::fs::read_to_string(path).with_context(|| format!("Could not read {:?}", path))?;
+    let document: serde_json::Value = serde_json::from_str(&content)
+        .with_context(|| format!("Could not parse baseline in {:?}", path))?;
+    if document.get("targets").is_none() && document.get("devices").is_none() {
+        let baseline: Baseline = serde_json::from_value(document)
+            .with_context(|| format!("Could not parse baseline in {:?}", path))?;
+        check_version(path, baseline.version)?;
+        return Ok(Baselines::Shared(baseline));
+    }
+    let grouped: GroupedBaseline = serde_json::from_value(document)
+        .with_context(|| format!("Could not parse baseline in {:?}", path))?;
+    check_version(path, grouped.version)?;
+    let mut baselines = grouped.targets;
+    baselines.extend(grouped.devices);
+    for baseline in baselines.values() {
+        check_version(path, baseline.version)?;
+    }
+    Ok(Baselines::ByName(baselines))
+}
+
+impl Baseline {
+    /// The samples of every filter in microseconds
+    pub(crate) fn filter_samples(&self) -> BTreeMap<&str, &[i64]> {
+        self.filters
+            .iter()
+            .map(|(name, f)| (name.as_str(), f.samples_us.as_slice()))
+            .collect()
+    }
+
+    /// The values of every point
+    pub(crate) fn point_values(&self) -> BTreeMap<&str, &[i64]> {
+        self.points
+            .iter()
+            .map(|(name, p)| (name.as_str(), p.values.as_slice()))
+            .collect()
+    }
+
+    /// The build label the results were written with
+    pub(crate) fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// The durations of every run and the run numbers
+    pub(crate) fn run_results(&self) -> (Vec<RunResults>, Vec<usize>) {
+        self.runs
+            .iter()
+            .map(|run| {
+                let results = run
+                    .results_us
+                    .iter()
+                    .map(|(name, micros)| {
+                        let durations = micros.iter().map(|m| Duration::microseconds(*m));
+                        (name.clone(), durations.collect())
+                    })
+                    .collect();
+                (results, run.run)
+            })
+            .unzip()
+    }
+
+    /// The number of spans in every run by span filter, from the `<span filter> count` metrics
+    pub(crate) fn span_counts(&self) -> BTreeMap<&str, Vec<f64>> {
+        let mut counts: BTreeMap<&str, Vec<f64>> = BTreeMap::new();
+        for run in &self.runs {
+            for (metric, value) in &run.metrics {
+                if let Some(name) = metric.strip_suffix(" count") {
+                    counts.entry(name).or_default().push(*value);
+                }
+            }
+        }
+        counts
+    }
+}
+
+/// One line of the comparison table
+pub(crate) struct Comparison {
+    /// Name of the filter or point
+    pub(crate) name: String,
+    /// A point metric, otherwise a filter with durations in microseconds
+    pub(crate) point: bool,
+    /// Mean and median in the baseline, None if it is not in the baseline
+    pub(crate) baseline: Option<(i64, i64)>,
+    /// Mean and median in the current results, None if it is not in them
+    pub(crate) current: Option<(i64, i64)>,
+}
+
+impl Comparison {
+    /// Change of the mean and the median in percent of the baseline
+    pub(crate) fn percent(&self) -> Option<(f64, f64)> {
+        let ((base_mean, base_median), (mean, median)) = self.baseline.zip(self.current)?;
+        let percent = |base: i64, value: i64| {
+            if value == base {
+                0.0
+            } else if base == 0 {
+                f64::INFINITY.copysign((value - base) as f64)
+            } else {
+                (value - base) as f64 / base.abs() as f64 * 100.0
+            }
+        };
+        Some((percent(base_mean, mean), percent(base_median, median)))
+    }
+
+    /// A filter whose mean grew by more than the threshold in percent
+    pub(crate) fn regressed(&self, threshold: f64) -> bool {
+        !self.point
+            && self
+                .percent()
+                .is_some_and(|(mean, _median)| mean > threshold)
+    }
+}
+
+/// Mean and median of the values, None if there are none
+fn mean_median(values: &[i64]) -> Option<(i64, i64)> {
+    avg_min_max(values).ok().map(|s| (s.avg, s.median))
+}
+
+/// Join the baseline with the current results by name. Names only in one of them are included
+/// with the other side missing. Filters come before points, both sorted by name.
+pub(crate) fn compare(
+    baseline: &Baseline,
+    results: &RunResults,
+    points: &PointResults,
+) -> Vec<Comparison> {
+    let mut comparisons = Vec::new();
+    let filters = baseline
+        .filters
+        .keys()
+        .chain(results.keys())
+        .collect::<BTreeSet<_>>();
+    for name in filters {
+        let current = results.get(name).map(|durations| {
+            durations
+                .iter()
+                .map(|d| d.whole_microseconds() as i64)
+                .collect::<Vec<_>>()
+        });
+        comparisons.push(Comparison {
+            name: name.clone(),
+            point: false,
+            baseline: baseline
+                .filters
+                .get(name)
+                .and_then(|f| mean_median(&f.samples_us)),
+            current: current.and_then(|c| mean_median(&c)),
+        });
+    }
+    let names = baseline
+        .points
+        .keys()
+        .chain(points.keys())
+        .collect::<BTreeSet<_>>();
+    for name in names {
+        comparisons.push(Comparison {
+            name: name.clone(),
+            point: true,
+            baseline: baseline
+                .points
+                .get(name)
+                .and_then(|p| mean_median(&p.values)),
+            current: points.get(name).and_then(|p| mean_median(p)),
+        });
+    }
+    comparisons
+}
@@ -0,0 +1,69 @@
+//! Bucket the span starts of a filter into fixed width time bins relative to the anchor
+use time::Duration;
+
+use crate::{
+    span::Span,
+    trace::{TimeStamp, difference_of_timestamps},
+};
+
+#[derive(Debug, Default, Clone)]
+/// The spans starting in one time bin
+pub(crate) struct Bin {
+    /// Number of spans starting in the bin
+    pub(crate) count: usize,
+    /// Summed duration of the spans starting in the bin
+    pub(crate) total: Duration,
+}
+
+/// Bin the spans by the offset of their start from the anchor. Spans starting before the anchor are not binned.
+/// All bins from the anchor to the last span start are returned, including empty ones.
+pub(crate) fn bin_spans(spans: &[&Span], anchor: &TimeStamp, width: Duration) -> Vec<Bin> {
+    let mut bins: Vec<Bin> = Vec::new();
+    for span in spans {
+        let offset = difference_of_timestamps(&span.start.timestamp, anchor);
+        if offset.is_negative() {
+            continue;
+        }
+        let index = (offset.whole_nanoseconds() / width.whole_nanoseconds()) as usize;
+        if bins.len() <= index {
+            bins.resize(index + 1, Bin::default());
+        }
+        bins[index].count += 1;
+        bins[index].total += span.duration();
+    }
+    bins
+}
+
+/// Average the bins over all runs. Shorter runs count as empty bins so the bins line up.
+/// Returns the average count and average total duration per bin.
+pub(crate) fn average_bins(runs: &[Vec<Bin>]) -> Vec<(f64, Duration)> {
+    let length = runs.iter().map(|r| r.len()).max().unwrap_or(0);
+    let number_of_runs = runs.len() as f64;
+    (0..length)
+        .map(|index| {
+            let (count, total) = runs
+                .iter()
+                .filter_map(|r| r.get(index))
+                .fold((0, Duration::ZERO), |(count, total), bin| {
+                    (count + bin.count, total + bin.total)
+                });
+            (count as f64 / number_of_runs, total / number_of_runs)
+        })
+        .collect()
+}
+
+/// Render the values as a sparkline scaled to the largest value
+pub(crate) fn sparkline(values: &[f64]) -> String {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let max = values.iter().cloned().fold(0.0, f64::max);
+    values
+        .iter()
+        .map(|v| {
+            if max == 0.0 {
+                BARS[0]
+            } else {
+                BARS[((v / max) * (BARS.len() - 1) as f64).round() as usize]
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,102 @@
+//! Break the window of a filter down into the spans that were running in it
+//!
+//! The attribution rule is as follows:
+//! - Only sync spans on the thread of the trace starting the window count. Work on other threads
+//!   runs concurrently to the window and is ignored.
+//! - Spans enclosing the whole window are ancestors of the window and not part of the breakdown.
+//! - Of the remaining spans intersecting the window only the outermost ones are taken, clipped to the window.
+//! - Time covered by more than one of these spans is attributed to the one that started first.
+//! - Time covered by no span is idle.
+use anyhow::Result;
+use std::{cmp::Reverse, collections::HashMap};
+use time::Duration;
+
+use crate::{
+    Trace,
+    filter::{Filter, filter_window},
+    span::Span,
+    trace::difference_of_timestamps,
+};
+
+/// The window of a filter in one run split into the top-level spans
+pub(crate) struct Breakdown {
+    /// The length of the window
+    pub(crate) window: Duration,
+    /// The time attributed to every span name
+    pub(crate) spans: HashMap<String, Duration>,
+}
+
+impl Breakdown {
+    /// The time of the window not attributed to any span
+    pub(crate) fn idle(&self) -> Duration {
+        self.window - self.spans.values().sum::<Duration>()
+    }
+}
+
+/// Compute the breakdown of the window given by the filter
+pub(crate) fn window_breakdown(
+    traces: &[Trace],
+    spans: &[Span],
+    filter: &Filter,
+) -> Result<Breakdown> {
+    let (first, last) = filter_window(traces, filter)?;
+    let window_start = first.timestamp;
+    let window_end = last.timestamp;
+
+    let mut candidates = spans
+        .iter()
+        .filter(|s| !s.is_async())
+        .filter(|s| s.start.pid == first.pid && s.start.tid == first.tid)
+        .filter(|s| s.start.timestamp < window_end && s.end.timestamp > window_start)
+        .filter(|s| !(s.start.timestamp <= window_start && s.end.timestamp >= window_end))
+        .collect::<Vec<_>>();
+    // Outer spans sort before the spans nested in them
+    candidates.sort_by(|a, b| {
+        a.start
+            .timestamp
+            .cmp(&b.start.timestamp)
+            .then(b.end.timestamp.cmp(&a.end.timestamp))
+    });
+
+    let mut spans: HashMap<String, Duration> = HashMap::new();
+    let mut covered_until = window_start;
+    for span in candidates {
+        let start = span.start.timestamp.max(covered_until);
+        let end = span.end.timestamp.min(window_end);
+        if end > start {
+            *spans.entry(span.name().to_owned()).or_default() +=
+                difference_of_timestamps(&end, &start);
+            covered_until = end;
+        }
+    }
+
+    Ok(Breakdown {
+        window: difference_of_timestamps(&window_end, &window_start),
+        spans,
+    })
+}
+
+/// Average the breakdowns of several runs.
+/// Returns the name, the average time per run and the percentage of the average window, sorted by time.
+/// The idle time is included under the name `idle`.
+pub(crate) fn summarize(breakdowns: &[Breakdown]) -> Vec<(String, Duration, f64)> {
+    let runs = breakdowns.len() as f64;
+    let mut totals: HashMap<String, Duration> = HashMap::new();
+    for b in breakdowns {
+        for (name, dur) in b.spans.iter() {
+            *totals.entry(name.clone()).or_default() += *dur;
+        }
+        *totals.entry(String::from("idle")).or_default() += b.idle();
+    }
+    let window = breakdowns.iter().map(|b| b.window).sum::<Duration>() / runs;
+
+    let mut summary = totals
+        .into_iter()
+        .map(|(name, total)| {
+            let avg = total / runs;
+            (name, avg, avg / window * 100.0)
+        })
+        .collect::<Vec<_>>();
+    summary.sort_by_key(|(_name, avg, _percentage)| Reverse(*avg));
+    summary
+}
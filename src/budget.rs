@@ -0,0 +1,139 @@
+//! Performance budgets per filter read from a toml file
+//!
+//! The file maps filter names to budgets, durations are in milliseconds:
+//! ```toml
+//! ["Load->Compl"]
+//! mean = 800
+//! p95 = 1000
+//! p99 = 1500
+//!
+//! [LayoutThread]
+//! count = 120
+//! ```
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::{collections::HashMap, path::Path};
+
+use crate::{
+    RunResults,
+    correlation::RunMetrics,
+    utils::{avg_min_max, percentile},
+};
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+/// The budget of one filter, every part is optional
+pub(crate) struct Budget {
+    /// Maximum average duration in milliseconds
+    mean: Option<f64>,
+    /// Maximum 95th percentile duration in milliseconds
+    p95: Option<f64>,
+    /// Maximum 99th percentile duration in milliseconds, needs `--tail-min-samples` durations
+    p99: Option<f64>,
+    /// Maximum average number of spans per run
+    count: Option<f64>,
+}
+
+/// The budgets given by filter name
+pub(crate) type Budgets = HashMap<String, Budget>;
+
+/// Read the budgets from the file
+pub(crate) fn read_budgets(path: &Path) -> Result<Budgets> {
+    let content =
+        std::fs::read_to_string(path).with_context(|| format!("Could not read {:?}", path))?;
+    toml::from_str(&content).with_context(|| format!("Could not parse budgets in {:?}", path))
+}
+
+/// One line of the budget table
+pub(crate) struct BudgetCheck<'a> {
+    /// Name of the filter
+    pub(crate) filter: &'a str,
+    /// Which budget, `mean`, `p95`, `p99` or `count`
+    pub(crate) kind: &'static str,
+    /// The budget
+    pub(crate) budget: f64,
+    /// The measured value, None if the filter had no results
+    pub(crate) actual: Option<f64>,
+}
+
+impl BudgetCheck<'_> {
+    /// Budget minus actual value, negative if over budget
+    pub(crate) fn margin(&self) -> Option<f64> {
+        self.actual.map(|actual| self.budget - actual)
+    }
+
+    /// A budget passes if the value is within it. Missing values fail.
+    pub(crate) fn passed(&self) -> bool {
+        self.margin().is_some_and(|m| m >= 0.0)
+    }
+}
+
+/// A p99 budget that was not checked as the filter has too few durations for the tail
+pub(crate) struct SkippedBudget<'a> {
+    pub(crate) filter: &'a str,
+    pub(crate) samples: usize,
+}
+
+/// Check every budget against the results.
+/// The p99 budgets of filters with fewer than `tail_min_samples` durations are skipped.
+pub(crate) fn check_budgets<'a>(
+    budgets: &'a Budgets,
+    results: &RunResults,
+    runs: &[RunMetrics],
+    tail_min_samples: usize,
+) -> (Vec<BudgetCheck<'a>>, Vec<SkippedBudget<'a>>) {
+    let mut checks = Vec::new();
+    let mut skipped = Vec::new();
+    for (filter, budget) in budgets.iter() {
+        let durations = results.get(filter.as_str());
+        if let Some(mean) = budget.mean {
+            checks.push(BudgetCheck {
+                filter,
+                kind: "mean",
+                budget: mean,
+                actual: durations
+                    .and_then(|d| avg_min_max(d).ok())
+                    .map(|a| a.avg.as_seconds_f64() * 1000.0),
+            });
+        }
+        if let Some(p95) = budget.p95 {
+            checks.push(BudgetCheck {
+                filter,
+                kind: "p95",
+                budget: p95,
+                actual: durations
+                    .and_then(|d| percentile(d, 95.0))
+                    .map(|p| p.as_seconds_f64() * 1000.0),
+            });
+        }
+        if let Some(p99) = budget.p99 {
+            let samples = durations.map_or(0, |d| d.len());
+            if samples < tail_min_samples {
+                skipped.push(SkippedBudget { filter, samples });
+            } else {
+                checks.push(BudgetCheck {
+                    filter,
+                    kind: "p99",
+                    budget: p99,
+                    actual: durations
+                        .and_then(|d| percentile(d, 99.0))
+                        .map(|p| p.as_seconds_f64() * 1000.0),
+                });
+            }
+        }
+        if let Some(count) = budget.count {
+            let key = format!("{} count", filter);
+            let counts = runs.iter().filter_map(|r| r.get(&key)).collect::<Vec<_>>();
+            checks.push(BudgetCheck {
+                filter,
+                kind: "count",
+                budget: count,
+                actual: (!counts.is_empty())
+                    .then(|| counts.iter().copied().sum::<f64>() / counts.len() as f64),
+            });
+        }
+    }
+    checks.sort_by_key(|c| (c.filter, c.kind));
+    skipped.sort_by_key(|s| s.filter);
+    (checks, skipped)
+}
@@ -0,0 +1,82 @@
+//! Attribution of span time to cpu clusters, i.e., little, big and prime cores
+use std::{collections::HashMap, fmt::Display};
+use time::Duration;
+
+use crate::{sched::SchedIndex, span::Span};
+
+#[derive(Debug, Clone)]
+/// A named set of cpus
+pub(crate) struct Cluster {
+    /// Name of the cluster
+    pub(crate) name: String,
+    /// The cpus in the cluster
+    pub(crate) cpus: Vec<u64>,
+}
+
+impl Display for Cluster {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let cpus = self.cpus.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+        write!(f, "{}={}", self.name, cpus.join(","))
+    }
+}
+
+/// Parse a list of cpus like `0-3,5`
+fn parse_cpus(s: &str) -> Result<Vec<u64>, String> {
+    let mut cpus = Vec::new();
+    for part in s.split(',') {
+        let parse = |n: &str| n.trim().parse::<u64>().map_err(|e| format!("{}: {}", s, e));
+        match part.split_once('-') {
+            Some((from, to)) => cpus.extend(parse(from)?..=parse(to)?),
+            None => cpus.push(parse(part)?),
+        }
+    }
+    Ok(cpus)
+}
+
+/// Parse a cluster of the form `name=0-3`
+pub(crate) fn parse_cluster(s: &str) -> Result<Cluster, String> {
+    let (name, cpus) = s
+        .split_once('=')
+        .ok_or(format!("Cluster {} is missing a `=`", s))?;
+    Ok(Cluster {
+        name: name.to_owned(),
+        cpus: parse_cpus(cpus)?,
+    })
+}
+
+/// The name of the cluster of the cpu, cpus that are in no cluster are `unknown`
+fn cluster_of(clusters: &[Cluster], cpu: u64) -> &str {
+    clusters
+        .iter()
+        .find(|c| c.cpus.contains(&cpu))
+        .map_or("unknown", |c| c.name.as_str())
+}
+
+/// Sum the durations of the spans per cluster.
+/// With the scheduler events of `--sched-stats` the time the thread of a span was running on a cpu goes to
+/// the cluster of that cpu and the time off cpu is left out. Without them the whole span is attributed to
+/// the cluster of the cpu it started on.
+pub(crate) fn cluster_shares(
+    spans: &[&Span],
+    clusters: &[Cluster],
+    sched: Option<&SchedIndex>,
+) -> HashMap<String, Duration> {
+    let mut shares: HashMap<String, Duration> = HashMap::new();
+    for span in spans {
+        match sched {
+            Some(sched) => {
+                for (cpu, running) in sched.running_on_cpus(span) {
+                    *shares
+                        .entry(cluster_of(clusters, cpu).to_owned())
+                        .or_default() += running;
+                }
+            }
+            None => {
+                *shares
+                    .entry(cluster_of(clusters, span.start.cpu).to_owned())
+                    .or_default() += span.duration()
+            }
+        }
+    }
+    shares
+}
@@ -0,0 +1,356 @@
+//! The `compare` subcommand, testing whether the results of two `--json` files differ by more than noise
+//!
+//! Durations and points are tested with Welch's t-test. The number of spans per run is compared separately,
+//! as a change that halves the duration of a span but triples how often it runs is a loss. Counts are
+//! small integers, so they are tested with the Mann-Whitney U test.
+use anyhow::{Result, anyhow};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+    path::Path,
+};
+use time::Duration;
+use yansi::{Condition, Paint};
+
+use crate::{baseline, samples, significance};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, ValueEnum)]
+/// How the comparison is printed
+pub(crate) enum CompareFormat {
+    /// Plain text lines
+    #[default]
+    Text,
+    /// A markdown table
+    #[value(alias = "markdown")]
+    Md,
+    /// A json document
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+/// What was compared
+pub(crate) enum Kind {
+    /// The durations of a filter in microseconds
+    Filter,
+    /// The values of a point
+    Point,
+    /// The number of spans of a span filter per run
+    Count,
+}
+
+#[derive(Debug, Serialize)]
+/// One line of the comparison, the statistics are missing if a side has fewer than two samples
+pub(crate) struct Difference {
+    name: String,
+    kind: Kind,
+    /// Mean of the old samples, null if the name is only in the new file
+    old: Option<f64>,
+    /// Mean of the new samples, null if the name is only in the old file
+    new: Option<f64>,
+    /// Mean of the new samples minus the mean of the old samples
+    difference: Option<f64>,
+    /// Change in percent of the old mean
+    percent: Option<f64>,
+    /// Confidence interval of the difference
+    interval: Option<(f64, f64)>,
+    welch_p: Option<f64>,
+    mann_whitney_p: Option<f64>,
+    /// The p-value of the test of the kind is below 1 - confidence
+    significant: bool,
+    /// A count that grew significantly by more than `--count-threshold` percent
+    regressed: bool,
+}
+
+impl Difference {
+    /// The value in the unit of the kind
+    fn format(&self, value: f64) -> String {
+        match self.kind {
+            Kind::Filter => Duration::microseconds(value.round() as i64).to_string(),
+            Kind::Point | Kind::Count => format!("{:.1}", value),
+        }
+    }
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+/// Compare the samples of one name
+fn difference(
+    name: &str,
+    kind: Kind,
+    old: Option<&[f64]>,
+    new: Option<&[f64]>,
+    confidence: f64,
+    count_threshold: Option<f64>,
+) -> Difference {
+    let welch = old
+        .zip(new)
+        .and_then(|(old, new)| significance::welch(old, new, confidence));
+    let mann_whitney_p = old
+        .zip(new)
+        .filter(|_| welch.is_some())
+        .and_then(|(old, new)| significance::mann_whitney(old, new));
+    let (old, new) = (
+        old.filter(|v| !v.is_empty()).map(mean),
+        new.filter(|v| !v.is_empty()).map(mean),
+    );
+    let percent = old
+        .zip(new)
+        .filter(|(old, _new)| *old != 0.0)
+        .map(|(old, new)| (new - old) / old.abs() * 100.0);
+    let p = match kind {
+        Kind::Filter | Kind::Point => welch.as_ref().map(|w| w.p),
+        Kind::Count => mann_whitney_p,
+    };
+    let significant = p.is_some_and(|p| p < 1.0 - confidence);
+    Difference {
+        name: name.to_owned(),
+        kind,
+        old,
+        new,
+        difference: welch.as_ref().map(|w| w.difference),
+        percent,
+        interval: welch.as_ref().map(|w| w.interval),
+        welch_p: welch.as_ref().map(|w| w.p),
+        mann_whitney_p,
+        significant,
+        regressed: kind == Kind::Count
+            && significant
+            && count_threshold
+                .zip(percent)
+                .is_some_and(|(threshold, percent)| percent > threshold),
+    }
+}
+
+fn floats<'a>(values: BTreeMap<&'a str, &[i64]>) -> BTreeMap<&'a str, Vec<f64>> {
+    values
+        .into_iter()
+        .map(|(name, v)| (name, v.iter().map(|v| *v as f64).collect()))
+        .collect()
+}
+
+/// Compare every name of both files, names only in one of them have the other side missing.
+/// Filters come before points and counts, each sorted by name.
+pub(crate) fn compare(
+    old: &baseline::Baseline,
+    new: &baseline::Baseline,
+    confidence: f64,
+    count_threshold: Option<f64>,
+) -> Vec<Difference> {
+    let mut differences = Vec::new();
+    for (kind, old, new) in [
+        (
+            Kind::Filter,
+            floats(old.filter_samples()),
+            floats(new.filter_samples()),
+        ),
+        (
+            Kind::Point,
+            floats(old.point_values()),
+            floats(new.point_values()),
+        ),
+        (Kind::Count, old.span_counts(), new.span_counts()),
+    ] {
+        let names = old
+            .keys()
+            .chain(new.keys())
+            .copied()
+            .collect::<BTreeSet<_>>();
+        for name in names {
+            differences.push(difference(
+                name,
+                kind,
+                old.get(name).map(Vec::as_slice),
+                new.get(name).map(Vec::as_slice),
+                confidence,
+                count_threshold,
+            ));
+        }
+    }
+    differences
+}
+
+/// Print the differences as text lines
+fn print_text(differences: &[Difference], confidence: f64) {
+    println!(
+        "----compare (name: old new difference [{}% interval] welch p | mann-whitney p)------------------------",
+        confidence * 100.0
+    );
+    let mut kind = Kind::Filter;
+    for d in differences {
+        if d.kind == Kind::Count && kind != Kind::Count {
+            println!(
+                "----span counts per run (significant by mann-whitney p)------------------------"
+            );
+        }
+        kind = d.kind;
+        let (Some(old), Some(new)) = (d.old, d.new) else {
+            println!(
+                "{}: {}",
+                d.name,
+                if d.old.is_some() { "removed" } else { "added" }
+            );
+            continue;
+        };
+        let (Some(difference), Some((low, high)), Some(welch_p)) =
+            (d.difference, d.interval, d.welch_p)
+        else {
+            println!("{}: need at least two samples on both sides", d.name);
+            continue;
+        };
+        let line = format!(
+            "{}: {} {} {} [{}, {}] p {:.4} | p {:.4}",
+            d.name,
+            d.format(old),
+            d.format(new),
+            d.format(difference),
+            d.format(low),
+            d.format(high),
+            welch_p,
+            d.mann_whitney_p.unwrap_or(1.0)
+        );
+        if d.regressed {
+            println!(
+                "{} {}",
+                line,
+                "count regression".red().whenever(Condition::TTY_AND_COLOR)
+            );
+        } else if d.significant {
+            println!(
+                "{} {}",
+                line,
+                "significant".red().whenever(Condition::TTY_AND_COLOR)
+            );
+        } else {
+            println!("{}", line);
+        }
+    }
+}
+
+/// The differences as a markdown table, durations are in microseconds
+fn markdown(differences: &[Difference], confidence: f64) -> String {
+    let mut out = format!(
+        "| name | kind | old | new | difference | {}% interval | change % | welch p | mann-whitney p | |\n|---|---|--:|--:|--:|--:|--:|--:|--:|---|\n",
+        confidence * 100.0
+    );
+    let number = |v: Option<f64>| v.map_or(String::new(), |v| format!("{:.1}", v));
+    for d in differences {
+        let kind = match d.kind {
+            Kind::Filter => "filter (µs)",
+            Kind::Point => "point",
+            Kind::Count => "count",
+        };
+        let verdict = match (d.old, d.new) {
+            (None, _) => "added",
+            (_, None) => "removed",
+            _ if d.regressed => "count regression",
+            _ if d.significant => "significant",
+            _ => "",
+        };
+        writeln!(
+            out,
+            "| {} | {} | {} | {} | {} | {} | {} | {} | {} | {} |",
+            d.name,
+            kind,
+            number(d.old),
+            number(d.new),
+            number(d.difference),
+            d.interval.map_or(String::new(), |(low, high)| format!(
+                "[{:.1}, {:.1}]",
+                low, high
+            )),
+            d.percent.map_or(String::new(), |p| format!("{:+.1}", p)),
+            d.welch_p.map_or(String::new(), |p| format!("{:.4}", p)),
+            d.mann_whitney_p
+                .map_or(String::new(), |p| format!("{:.4}", p)),
+            verdict
+        )
+        .unwrap();
+    }
+    out
+}
+
+/// Print the significance of the differences between two result files of `--json`, writing the samples of
+/// both with `samples`. Fails if a span count grew beyond the threshold
+pub(crate) fn compare_files(
+    old: &Path,
+    new: &Path,
+    confidence: f64,
+    count_threshold: Option<f64>,
+    format: CompareFormat,
+    samples: Option<&Path>,
+    sample_limit: Option<usize>,
+) -> Result<()> {
+    let old = baseline::read_baseline(old)?;
+    let new = baseline::read_baseline(new)?;
+    if let Some(path) = samples {
+        let (old_runs, old_numbers) = old.run_results();
+        let (new_runs, new_numbers) = new.run_results();
+        let sides = [
+            ("old", &old, &old_runs, &old_numbers),
+            ("new", &new, &new_runs, &new_numbers),
+        ]
+        .map(|(name, baseline, runs, numbers)| samples::Side {
+            name,
+            label: baseline.label(),
+            runs,
+            points: &[],
+            spilled: &[],
+            numbers,
+        });
+        samples::write_samples(path, &sides, sample_limit)?;
+    }
+    let differences = compare(&old, &new, confidence, count_threshold);
+    match format {
+        CompareFormat::Text => print_text(&differences, confidence),
+        CompareFormat::Md => print!("{}", markdown(&differences, confidence)),
+        CompareFormat::Json => println!("{}", serde_json::to_string_pretty(&differences)?),
+    }
+    let regressions = differences.iter().filter(|d| d.regressed).count();
+    if regressions > 0 {
+        return Err(anyhow!(
+            "{} span counts grew by more than {}%",
+            regressions,
+            count_threshold.unwrap_or_default()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grown_counts_are_regressions() {
+        let old = [10.0, 11.0, 10.0, 9.0, 10.0, 11.0];
+        let new = [30.0, 31.0, 29.0, 30.0, 32.0, 30.0];
+        let d = difference(
+            "Layout",
+            Kind::Count,
+            Some(&old),
+            Some(&new),
+            0.95,
+            Some(50.0),
+        );
+        assert!(d.significant);
+        assert!(d.regressed);
+        assert!(d.percent.unwrap() > 190.0);
+        let d = difference(
+            "Layout",
+            Kind::Count,
+            Some(&old),
+            Some(&new),
+            0.95,
+            Some(500.0),
+        );
+        assert!(!d.regressed);
+        let d = difference("Layout", Kind::Count, None, Some(&new), 0.95, Some(50.0));
+        assert!(!d.regressed);
+        assert!(markdown(&[d], 0.95).contains("| Layout | count |  | 30.3 |"));
+    }
+}
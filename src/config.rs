@@ -0,0 +1,160 @@
+//! Benchmark definitions in a toml file given with `--config`
+//!
+//! The keys are the long names of the command line options. Top level keys apply to every benchmark,
+//! the tables under `benchmarks` define named benchmarks selected with `--benchmark`:
+//! ```toml
+//! tries = 10
+//! bundle-name = "org.servo.servo"
+//!
+//! [benchmarks.layout]
+//! homepage = "https://servo.org"
+//! span = ["re:^Layout", "PerformUpdates"]
+//! fail-threshold = 5
+//!
+//! [benchmarks.pages]
+//! target = ["servo=https://servo.org", "webgpu=https://servo.org --ps=--pref dom_webgpu_enabled=true"]
+//!
+//! [[benchmarks.pages.score]]
+//! filter = "LoadPage"
+//! weight = 2
+//! reference-ms = 800
+//! ```
+//! Score components are tables like above or strings of `--score`.
+//! The options of a benchmark replace the top level ones, options given on the command line override the file.
+use anyhow::{Context, Result, anyhow};
+use clap::{Command, CommandFactory, FromArgMatches, Parser, parser::ValueSource};
+use std::{ffi::OsString, path::Path};
+use toml::{Table, Value};
+
+use crate::{args::Args, score};
+
+/// Read the options of the benchmark from the file
+fn read_config(path: &Path, benchmark: Option<&str>) -> Result<Table> {
+    let content =
+        std::fs::read_to_string(path).with_context(|| format!("Could not read {:?}", path))?;
+    let mut table: Table =
+        toml::from_str(&content).with_context(|| format!("Could not parse config {:?}", path))?;
+    let benchmarks = table.remove("benchmarks");
+    if let Some(name) = benchmark {
+        let options = benchmarks
+            .as_ref()
+            .and_then(|b| b.get(name))
+            .and_then(|b| b.as_table())
+            .ok_or(anyhow!("{:?} does not define the benchmark {}", path, name))?;
+        table.extend(options.clone());
+    }
+    Ok(table)
+}
+
+/// The value of an option on the command line
+fn to_arg_value(key: &str, value: &Value) -> Result<String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Integer(i) => Ok(i.to_string()),
+        Value::Float(f) => Ok(f.to_string()),
+        Value::Table(t) if key == "score" => score::component_arg(t).map_err(|e| anyhow!(e)),
+        _ => Err(anyhow!(
+            "The option {} has an unsupported value {}",
+            key,
+            value
+        )),
+    }
+}
+
+/// Turn the options into command line arguments, skipping the options for which `given` is true
+fn to_args(cmd: &Command, options: &Table, given: impl Fn(&str) -> bool) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    for (key, value) in options {
+        let arg = cmd
+            .get_arguments()
+            .find(|a| a.get_long() == Some(key.as_str()))
+            .ok_or(anyhow!("The config has the unknown option {}", key))?;
+        if matches!(key.as_str(), "config" | "benchmark") {
+            return Err(anyhow!("The config cannot set {}", key));
+        }
+        if given(arg.get_id().as_str()) {
+            continue;
+        }
+        if !arg.get_action().takes_values() {
+            match value {
+                Value::Boolean(true) => args.push(format!("--{}", key)),
+                Value::Boolean(false) => {}
+                _ => return Err(anyhow!("The flag {} needs true or false", key)),
+            }
+            continue;
+        }
+        let values = match value {
+            Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        for value in values {
+            args.push(format!("--{}={}", key, to_arg_value(key, value)?));
+        }
+    }
+    Ok(args)
+}
+
+/// Parse the command line, with the options of the config file if one is given
+pub(crate) fn parse_args() -> Result<Args> {
+    let cli = std::env::args_os().collect::<Vec<_>>();
+    let matches = Args::command().get_matches_from(&cli);
+    let args = Args::from_arg_matches(&matches)?;
+    let Some(path) = &args.config else {
+        return Ok(args);
+    };
+    let options = read_config(path, args.benchmark.as_deref())?;
+    let config_args = to_args(&Args::command(), &options, |id| {
+        matches.value_source(id) == Some(ValueSource::CommandLine)
+    })?;
+    let mut argv: Vec<OsString> = cli[..1].to_vec();
+    argv.extend(config_args.into_iter().map(OsString::from));
+    argv.extend(cli[1..].iter().cloned());
+    Ok(Args::parse_from(argv))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_line_overrides_config() {
+        let options: Table = toml::from_str(
+            r#"
+            tries = 10
+            homepage = "https://servo.org"
+            span = ["re:^Layout", "PerformUpdates"]
+            per-thread = true
+            all-traces = false
+            score = ["Layout:1:16", { filter = "PerformUpdates", weight = 2, reference-ms = 0.5 }]
+            "#,
+        )
+        .unwrap();
+        let cli = ["hitrace-bench", "--homepage", "https://example.com"];
+        let matches = Args::command().get_matches_from(cli);
+        let config_args = to_args(&Args::command(), &options, |id| {
+            matches.value_source(id) == Some(ValueSource::CommandLine)
+        })
+        .unwrap();
+        let args = Args::parse_from(
+            cli[..1]
+                .iter()
+                .map(|s| s.to_string())
+                .chain(config_args)
+                .chain(cli[1..].iter().map(|s| s.to_string())),
+        );
+        assert_eq!(args.tries, 10);
+        assert_eq!(args.homepage, "https://example.com");
+        assert_eq!(args.spans.len(), 2);
+        assert!(args.per_thread);
+        assert!(!args.all_traces);
+        assert_eq!(args.score.len(), 2);
+        assert_eq!(args.score[1].filter, "PerformUpdates");
+        assert_eq!(args.score[1].reference_ms, 0.5);
+    }
+
+    #[test]
+    fn unknown_options_are_rejected() {
+        let options: Table = toml::from_str("runs = 3").unwrap();
+        assert!(to_args(&Args::command(), &options, |_| false).is_err());
+    }
+}
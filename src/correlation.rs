@@ -0,0 +1,80 @@
+//! Correlation between the per run values of two metrics
+use std::collections::HashMap;
+
+/// The value of every metric in one run. Durations are in seconds.
+/// A pair filter gives its duration, a span filter gives its summed duration and `<name> count`.
+/// The integrity of the trace adds `unmatched ends`, `unterminated starts` and `lost events`.
+pub(crate) type RunMetrics = HashMap<String, f64>;
+
+/// The correlation of two metrics over the runs
+pub(crate) struct Correlation {
+    /// Pearson correlation coefficient
+    pub(crate) pearson: Option<f64>,
+    /// Spearman rank correlation coefficient
+    pub(crate) spearman: Option<f64>,
+    /// Number of runs that had both values
+    pub(crate) samples: usize,
+    /// Number of runs skipped because one of the values was missing
+    pub(crate) skipped: usize,
+}
+
+/// Pearson correlation, None if there are less than two values or one side is constant
+fn pearson(xs: &[f64], ys: &[f64]) -> Option<f64> {
+    if xs.len() < 2 {
+        return None;
+    }
+    let n = xs.len() as f64;
+    let mean_x = xs.iter().sum::<f64>() / n;
+    let mean_y = ys.iter().sum::<f64>() / n;
+    let covariance: f64 = xs
+        .iter()
+        .zip(ys)
+        .map(|(x, y)| (x - mean_x) * (y - mean_y))
+        .sum();
+    let var_x: f64 = xs.iter().map(|x| (x - mean_x).powi(2)).sum();
+    let var_y: f64 = ys.iter().map(|y| (y - mean_y).powi(2)).sum();
+    if var_x == 0.0 || var_y == 0.0 {
+        None
+    } else {
+        Some(covariance / (var_x * var_y).sqrt())
+    }
+}
+
+/// Ranks of the values starting at 1, ties get the average of their ranks
+fn ranks(values: &[f64]) -> Vec<f64> {
+    let mut order = (0..values.len()).collect::<Vec<_>>();
+    order.sort_by(|a, b| values[*a].total_cmp(&values[*b]));
+    let mut ranks = vec![0.0; values.len()];
+    let mut i = 0;
+    while i < order.len() {
+        let mut j = i;
+        while j + 1 < order.len() && values[order[j + 1]] == values[order[i]] {
+            j += 1;
+        }
+        let rank = (i + j) as f64 / 2.0 + 1.0;
+        for index in &order[i..=j] {
+            ranks[*index] = rank;
+        }
+        i = j + 1;
+    }
+    ranks
+}
+
+/// Correlate the metrics `a` and `b` over the runs, skipping runs missing one of them
+pub(crate) fn correlate(runs: &[RunMetrics], a: &str, b: &str) -> Correlation {
+    let (xs, ys): (Vec<f64>, Vec<f64>) = runs
+        .iter()
+        .filter_map(|run| run.get(a).zip(run.get(b)))
+        .unzip();
+    correlate_values(&xs, &ys, runs.len() - xs.len())
+}
+
+/// Correlate the pairs of values, `skipped` only gets reported
+pub(crate) fn correlate_values(xs: &[f64], ys: &[f64], skipped: usize) -> Correlation {
+    Correlation {
+        pearson: pearson(xs, ys),
+        spearman: pearson(&ranks(xs), &ranks(ys)),
+        samples: xs.len(),
+        skipped,
+    }
+}
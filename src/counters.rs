@@ -0,0 +1,120 @@
+//! Values of counter traces over the run and within the window of a span
+use anyhow::{Result, anyhow};
+
+use crate::{
+    span::Span,
+    trace::{TimeStamp, Trace, difference_of_timestamps},
+};
+
+#[derive(Debug, Clone)]
+/// The values of a counter while the first span of a span filter was open
+pub(crate) struct CounterWindow {
+    /// Name of the metric in the output
+    pub(crate) name: String,
+    /// Name of the counter
+    pub(crate) counter: String,
+    /// Function name of the span giving the window
+    pub(crate) span: String,
+}
+
+/// Parse a counter window of the form `name=counter@span`
+pub(crate) fn parse_counter_window(s: &str) -> Result<CounterWindow, String> {
+    let (name, rest) = s
+        .split_once('=')
+        .ok_or(format!("Counter window {} is missing a `=`", s))?;
+    let (counter, span) = rest
+        .split_once('@')
+        .ok_or(format!("Counter window {} is missing a `@`", s))?;
+    Ok(CounterWindow {
+        name: name.to_owned(),
+        counter: counter.to_owned(),
+        span: span.to_owned(),
+    })
+}
+
+/// The counter values within a window
+pub(crate) struct WindowValues {
+    /// Highest value
+    pub(crate) peak: i64,
+    /// Average over the window weighted by the time each value was held
+    pub(crate) avg: i64,
+    /// Value at the end minus the value at the start of the window
+    pub(crate) delta: i64,
+}
+
+/// Every value of the counter with the time it was set
+pub(crate) fn counter_series(traces: &[Trace], counter: &str) -> Vec<(TimeStamp, i64)> {
+    traces
+        .iter()
+        .filter_map(|t| {
+            t.counter()
+                .filter(|(name, _value)| *name == counter)
+                .map(|(_name, value)| (t.timestamp, value))
+        })
+        .collect()
+}
+
+/// The counter values held during the window. The value at the start is the last value set before the window.
+fn values_in_window(
+    series: &[(TimeStamp, i64)],
+    start: TimeStamp,
+    end: TimeStamp,
+) -> Vec<(TimeStamp, i64)> {
+    let before = series
+        .iter()
+        .rev()
+        .find(|(ts, _value)| *ts <= start)
+        .map(|(_ts, value)| (start, *value));
+    before
+        .into_iter()
+        .chain(
+            series
+                .iter()
+                .filter(|(ts, _value)| *ts > start && *ts <= end)
+                .copied(),
+        )
+        .collect()
+}
+
+/// Compute the counter values while the first span of the window filter was open
+pub(crate) fn counter_in_window(
+    traces: &[Trace],
+    spans: &[Span],
+    window: &CounterWindow,
+) -> Result<WindowValues> {
+    let span = spans
+        .iter()
+        .filter(|s| s.name() == window.span)
+        .min_by_key(|s| s.start.timestamp)
+        .ok_or(anyhow!("Could not find span {}", window.span))?;
+    let series = counter_series(traces, &window.counter);
+    if series.is_empty() {
+        return Err(anyhow!("Could not find counter {}", window.counter));
+    }
+    let values = values_in_window(&series, span.start.timestamp, span.end.timestamp);
+    let (first, last) = values.first().zip(values.last()).ok_or(anyhow!(
+        "Counter {} has no value in the span",
+        window.counter
+    ))?;
+
+    let peak = values.iter().map(|(_ts, value)| *value).max().unwrap();
+    let end = span.end.timestamp;
+    let length = difference_of_timestamps(&end, &first.0).whole_nanoseconds();
+    let avg = if length == 0 {
+        first.1
+    } else {
+        let weighted: i128 = values
+            .iter()
+            .zip(values.iter().skip(1).map(|(ts, _value)| ts).chain([&end]))
+            .map(|((ts, value), next)| {
+                *value as i128 * difference_of_timestamps(next, ts).whole_nanoseconds()
+            })
+            .sum();
+        (weighted / length) as i64
+    };
+    Ok(WindowValues {
+        peak,
+        avg,
+        delta: last.1 - first.1,
+    })
+}
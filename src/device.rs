@@ -1,209 +1,317 @@
 //! Functions to handle the device
 use anyhow::{Context, Result, anyhow};
-use regex::Regex;
 use std::{
+    fmt,
     fs::File,
-    io::{BufRead, BufReader},
+    io::BufReader,
     path::{Path, PathBuf},
-    process::Command,
+    process::{Child, Command, Output, Stdio},
+    time::{Duration, Instant},
 };
 
 use crate::{
-    Args, Trace,
-    trace::{TimeStamp, TraceMarker},
+    Args,
+    backend::{Backend, Device, REMOTE_TRACE},
+    clusters::Cluster,
+    matrix::Target,
+    memory::{self, MemorySample, Sampler},
+    parse::{self, ParseReport, TraceReader},
+    trace,
 };
 
-/// We test if the device is reachable, i.e., the list of hdc list targets is non empty.
+/// The serials of all connected devices of the backend
+pub(crate) fn list_targets(backend: Backend) -> Result<Vec<String>> {
+    let device = Device::new(backend, None);
+    let cmd = device
+        .command()?
+        .args(device.backend.list_command())
+        .output()?;
+    Ok(device
+        .backend
+        .parse_targets(&String::from_utf8_lossy(&cmd.stdout)))
+}
+
+/// We test if the device is reachable, i.e., the list of connected devices is non empty and has the device.
 /// It can happen that another IDE is connected to it and then we cannot reach it (and no command fails)
-pub(crate) fn is_device_reachable() -> Result<bool> {
-    let hdc = which::which("hdc").context("Is hdc in the path?")?;
-    let cmd = Command::new(&hdc).args(["list", "targets"]).output()?;
-    Ok(!cmd.stdout.is_empty())
+pub(crate) fn is_device_reachable(backend: Backend, device: Option<&str>) -> Result<bool> {
+    let targets = list_targets(backend)?;
+    Ok(match device {
+        Some(serial) => targets.iter().any(|t| t == serial),
+        None => !targets.is_empty(),
+    })
+}
+
+/// Read the cpu clusters from the cpufreq policies of the device, every policy is one cluster
+pub(crate) fn detect_clusters(device: Device) -> Result<Vec<Cluster>> {
+    let cmd = device
+        .shell(&[
+            "for p in /sys/devices/system/cpu/cpufreq/policy*; do echo ${p##*/} $(cat $p/related_cpus); done",
+        ])?
+        .output()?;
+    let clusters = String::from_utf8_lossy(&cmd.stdout)
+        .lines()
+        .filter_map(|l| {
+            let mut words = l.split_whitespace();
+            let name = words.next()?;
+            let cpus = words
+                .map(|c| c.parse())
+                .collect::<Result<Vec<u64>, _>>()
+                .ok()?;
+            (!cpus.is_empty()).then(|| Cluster {
+                name: name.to_owned(),
+                cpus,
+            })
+        })
+        .collect::<Vec<_>>();
+    if clusters.is_empty() {
+        Err(anyhow!("Could not read the cpufreq policies of the device"))
+    } else {
+        Ok(clusters)
+    }
+}
+
+/// Start tracing the categories we read into the buffer of the arguments
+pub(crate) fn start_tracing(args: &Args, device: Device, deadline: Option<Instant>) -> Result<()> {
+    let start = device
+        .backend
+        .start_trace(args.trace_buffer, &args.bundle_name, args.sched_stats);
+    output(&mut device.shell(&start)?, deadline)?;
+    Ok(())
+}
+
+/// Read the trace of the device while it is written. The pipe consumes what it reads and blocks until
+/// there is more, it runs until killed
+pub(crate) fn trace_pipe(device: Device) -> Result<Child> {
+    Ok(device
+        .shell(&["cat", &format!("{}/trace_pipe", device.backend.tracefs())])?
+        .stdout(Stdio::piped())
+        .spawn()?)
 }
 
 /// We sometimes want to stop the trace because we interrupted the program
-pub(crate) fn stop_tracing(buffer: u64) -> Result<()> {
-    let hdc = which::which("hdc").context("Is hdc in the path?")?;
-    // stop trace
-    Command::new(&hdc)
-        .args([
-            "shell",
-            "hitrace",
-            "-b",
-            &buffer.to_string(),
-            "--trace_finish",
-            "-o",
-            "/data/local/tmp/ohtrace.txt",
-        ])
+pub(crate) fn stop_tracing(buffer: u64, device: Device) -> Result<()> {
+    device
+        .shell(&device.backend.stop_trace(buffer))?
         .output()
         .map(|_| ())
         .map_err(|_| anyhow!("Could not stop trace"))
 }
 
-/// Execute the hdc commands on the device.
-pub(crate) fn exec_hdc_commands(args: &crate::Args) -> Result<PathBuf> {
-    if !args.computer_output && !args.bencher {
-        println!("Executing hdc commands");
-    }
-    let hdc = which::which("hdc").context("Is hdc in the path?")?;
-    // stop the app before starting the test
-    Command::new(&hdc)
-        .args(["shell", "aa", "force-stop", &args.bundle_name])
-        .output()?;
-    // start trace
-    Command::new(&hdc)
-        .args([
-            "shell",
-            "hitrace",
-            "-b",
-            &args.trace_buffer.to_string(),
-            "app",
-            "graphic",
-            "ohos",
-            "freq",
-            "idle",
-            "memory",
-            "--trace_begin",
-        ])
+/// The memory of the running app, None if it does not run
+pub(crate) fn memory_sample(bundle_name: &str, device: Device) -> Result<Option<MemorySample>> {
+    let pidof = device.shell(&["pidof", bundle_name])?.output()?;
+    let pidof = String::from_utf8_lossy(&pidof.stdout);
+    let Some(pid) = pidof.split_whitespace().next() else {
+        return Ok(None);
+    };
+    // smaps_rollup is cheaper and has the rss, but is not readable on every device
+    let rollup = device
+        .shell(&["cat", &format!("/proc/{}/smaps_rollup", pid)])?
         .output()?;
-    // start the ability
-    Command::new(&hdc)
-        .args([
-            "shell",
-            "aa",
-            "start",
-            "-a",
-            "EntryAbility",
-            "-b",
-            &args.bundle_name,
-            "-U",
-            &args.homepage,
-            "--ps=--pref",
-            "js_disable_jit=true",
-        ])
+    if let Some(sample) = memory::parse_smaps_rollup(&String::from_utf8_lossy(&rollup.stdout)) {
+        return Ok(Some(sample));
+    }
+    let Some(dump) = device.backend.memory_dump(pid) else {
+        return Ok(None);
+    };
+    let dump = device.shell(&dump)?.output()?;
+    Ok(memory::parse_hidumper(&String::from_utf8_lossy(
+        &dump.stdout,
+    )))
+}
+
+/// The model of the device, i.e., `ALN-AL00`
+pub(crate) fn model(device: Device) -> Result<String> {
+    let output = device.shell(&device.backend.model())?.output()?;
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+/// The version name of the installed app, None if it has none
+pub(crate) fn app_version(bundle_name: &str, device: Device) -> Result<Option<String>> {
+    let output = device
+        .shell(&device.backend.app_info(bundle_name))?
         .output()?;
+    Ok(device
+        .backend
+        .parse_app_version(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Run the hook commands one after the other in the shell of the device, stopping at the first one that fails
+pub(crate) fn run_hooks(commands: &[String], device: Device) -> Result<()> {
+    for command in commands {
+        let output = device
+            .shell(&[command])?
+            .output()
+            .with_context(|| format!("Could not run `{}`", command))?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "`{}` failed with {}: {}",
+                command,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+    }
+    Ok(())
+}
 
-    if !args.computer_output && !args.bencher {
+/// Stop the app, i.e., one that hangs after a timeout
+pub(crate) fn force_stop(bundle_name: &str, device: Device) -> Result<()> {
+    device
+        .shell(&device.backend.force_stop(bundle_name))?
+        .output()
+        .map(|_| ())
+        .map_err(|_| anyhow!("Could not stop {}", bundle_name))
+}
+
+#[derive(Debug)]
+/// A run took longer than `--timeout`
+pub(crate) struct TimedOut;
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "The run timed out")
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// Run the command to completion, killing it if it is still running at the deadline
+fn output(cmd: &mut Command, deadline: Option<Instant>) -> Result<Output> {
+    let Some(deadline) = deadline else {
+        return Ok(cmd.output()?);
+    };
+    let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    while child.try_wait()?.is_none() {
+        if Instant::now() >= deadline {
+            child.kill()?;
+            child.wait()?;
+            return Err(TimedOut.into());
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    Ok(child.wait_with_output()?)
+}
+
+/// Trace a run of the app on the device.
+/// Returns the trace file and the memory samples of `--memory-interval`, taken while the app ran.
+/// Fails with [`TimedOut`] if they take longer than the timeout of the arguments.
+pub(crate) fn capture_trace(
+    args: &crate::Args,
+    device: Device,
+    target: Option<&Target>,
+) -> Result<(PathBuf, Vec<MemorySample>)> {
+    let deadline = args
+        .timeout
+        .map(|secs| Instant::now() + Duration::from_secs(secs));
+    if !args.computer_output && !args.quiet() {
+        println!("Executing {} commands", device.backend.tool());
+    }
+    // stop the app before starting the test
+    output(
+        &mut device.shell(&device.backend.force_stop(&args.bundle_name))?,
+        deadline,
+    )?;
+    start_tracing(args, device, deadline)?;
+    // start the app
+    let homepage = target.map_or(&args.homepage, |t| &t.homepage);
+    output(
+        device
+            .shell(&device.backend.start_app(&args.bundle_name, homepage))?
+            .args(target.into_iter().flat_map(|t| &t.launch_args)),
+        deadline,
+    )?;
+    let sampler = args.memory_interval.map(|millis| {
+        Sampler::start(
+            args.bundle_name.clone(),
+            args.backend,
+            device.serial.map(str::to_owned),
+            Duration::from_millis(millis),
+        )
+    });
+
+    if !args.computer_output && !args.quiet() {
         println!("Sleeping for {}", args.sleep);
     }
-    std::thread::sleep(std::time::Duration::from_secs(args.sleep));
+    std::thread::sleep(Duration::from_secs(args.sleep));
+    let memory = sampler.map(Sampler::finish).unwrap_or_default();
+    if deadline.is_some_and(|d| Instant::now() >= d) {
+        stop_tracing(args.trace_buffer, device)?;
+        return Err(TimedOut.into());
+    }
 
     // Getting app pid is a simple test if the app perhaps crashed during the benchmark / test.
-    let cmd = Command::new(&hdc)
-        .args(["shell", "pidof", &args.bundle_name])
-        .output()
+    let cmd = output(&mut device.shell(&["pidof", &args.bundle_name])?, deadline)
         .with_context(|| format!("Is `{}` installed?", args.bundle_name))?;
     if cmd.stdout.is_empty() {
-        Command::new(&hdc)
-            .args([
-                "shell",
-                "hitrace",
-                "-b",
-                &args.trace_buffer.to_string(),
-                "--trace_finish",
-                "-o",
-                "/data/local/tmp/ohtrace.txt",
-            ])
-            .output()?;
+        stop_tracing(args.trace_buffer, device)?;
         return Err(anyhow!(
             "{} did not start or crashed. Please check the application logs.",
             args.bundle_name
         ));
     }
-    stop_tracing(args.trace_buffer)?;
+    stop_tracing(args.trace_buffer, device)?;
     let mut tmp_path = std::env::temp_dir();
-    tmp_path.push("app.ftrace");
-    if !args.computer_output && !args.bencher {
-        println!("Writing ftrace to {}", tmp_path.to_str().unwrap());
+    // every device gets its own file so devices can run in parallel
+    tmp_path.push(match device.serial {
+        Some(serial) => format!("app-{}.ftrace", serial),
+        None => String::from("app.ftrace"),
+    });
+    if !args.computer_output && !args.quiet() {
+        println!("Writing ftrace to {}", tmp_path.display());
     }
     // Receive trace
-    Command::new(&hdc)
-        .args([
-            "file",
-            "recv",
-            "/data/local/tmp/ohtrace.txt",
-            tmp_path.to_str().unwrap(),
-        ])
-        .output()?;
-    Ok(tmp_path)
-}
-
-/// There is always one trace per line
-/// This means that having no matched lines is ok and returns None. Having a parsing error returns Some(Err)
-fn line_to_trace(regex: &Regex, line: &str) -> Option<Result<Trace>> {
-    regex
-        .captures_iter(line)
-        .map(|c| c.extract())
-        .map(match_to_trace)
-        .next()
-}
-
-/// Read a regex matched line into a trace
-fn match_to_trace(
-    (
-        _line,
-        [
-            name,
-            pid,
-            cpu,
-            time1,
-            time2,
-            trace_marker,
-            number,
-            shorthand,
-            msg,
-        ],
-    ): (&str, [&str; 9]),
-) -> Result<Trace> {
-    let seconds = time1.parse()?;
-    let microseconds = time2.parse()?;
-    let timestamp = TimeStamp {
-        seconds,
-        micro: microseconds,
-    };
-    let trace_marker = TraceMarker::from(trace_marker)?;
-    Ok(Trace {
-        name: name.to_owned(),
-        pid: pid.parse().unwrap(),
-        cpu: cpu.parse().unwrap(),
-        trace_marker,
-        number: number.to_string(),
-        timestamp,
-        shorthand: shorthand.to_owned(),
-        function: msg.to_owned(),
-    })
+    output(
+        device
+            .command()?
+            .args(device.backend.receive(REMOTE_TRACE))
+            .arg(&tmp_path),
+        deadline,
+    )?;
+    Ok((tmp_path, memory))
+}
+
+/// The end of the bundle name, hitrace sometimes does not show the whole bundle name as thread name
+pub(crate) fn bundle_short(args: &Args) -> Result<&str> {
+    args.bundle_name.rsplit('.').next().ok_or(anyhow!("Your bundle name does not have a dot. We need a dot because hitrace sometimes does not show the whole bundle name"))
 }
 
 /// Read a file into traces
-pub(crate) fn read_file(args: &Args, f: &Path) -> Result<Vec<Trace>> {
+pub(crate) fn read_file(args: &Args, f: &Path) -> Result<ParseReport> {
     // This is more specific servo tracing with the tracing_mark_write
-    // Example trace: `org.servo.servo-44962   (  44682) [010] .... 17864.716645: tracing_mark_write: B|44682|ML: do_single_part3_compilation`
-    let bundle_short = args.bundle_name.rsplit('.').next().ok_or(anyhow!("Your bundle name does not have a dot. We need a dot because hitrace sometimes does not show the whole bundle name"))?;
-    let regex = Regex::new(&format!(
-        r"^.(.*?{}.*?)\-(\d+)\s*\(\s*(\d+)\).*?(\d+)\.(\d+): tracing_mark_write: (.)\|(\d+?)\|(.*?):(.*?)\s*$",
-        &bundle_short
-    ))?;
-    let f = File::open(f)?;
-    let reader = BufReader::new(f);
-
-    let (valid_lines, invalid_lines): (Vec<_>, Vec<_>) = reader
-        .lines()
-        .enumerate()
-        .partition(|(_index, l)| l.is_ok());
-
-    if !invalid_lines.is_empty() {
-        println!(
-            "Could not read lines {:?}",
-            invalid_lines
-                .iter()
-                .map(|(index, _l)| index)
-                .collect::<Vec<_>>()
-        );
+    let file = File::open(f)?;
+    let mut report = parse::parse_report_with_format(
+        BufReader::new(file),
+        bundle_short(args)?,
+        args.backend.capture().trace_format(),
+    )?;
+    if let Some(process) = &args.process {
+        let pids = trace::resolve_pids(&report.traces, &process.matcher);
+        if pids.is_empty() && !args.quiet() {
+            eprintln!("{:?}: no process matches {}", f, process.name);
+        }
+        report.retain_processes(&pids);
+    }
+    if !args.quiet() {
+        if !report.unreadable_lines.is_empty() {
+            eprintln!(
+                "{:?}: could not read lines {:?}",
+                f, report.unreadable_lines
+            );
+        }
+        for warning in &report.warnings {
+            eprintln!("{:?}: {}", f, warning);
+        }
     }
+    Ok(report)
+}
 
-    valid_lines
-        .into_iter()
-        .filter_map(|(_index, l)| line_to_trace(&regex, &l.unwrap()))
-        .collect::<Result<Vec<Trace>>>()
-        .context("Could not parse one thing")
+/// Read the traces of a file one at a time
+pub(crate) fn stream_file(args: &Args, f: &Path) -> Result<TraceReader<BufReader<File>>> {
+    let file = File::open(f)?;
+    Ok(TraceReader::with_format(
+        BufReader::new(file),
+        bundle_short(args)?,
+        args.backend.capture().trace_format(),
+    )?)
 }
@@ -0,0 +1,21 @@
+//! The errors of the library, so the binary can report which line of which run failed and carry on
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+/// Why parsing, matching or statistics failed
+pub enum BenchError {
+    /// The thread filter did not give a valid regex
+    #[error("Invalid thread filter: {0}")]
+    Regex(#[from] regex::Error),
+    /// A trace line matched but one of its fields is invalid. Lines are counted from 1
+    #[error("Line {line}: {message}")]
+    Line {
+        /// The line of the dump
+        line: usize,
+        /// What is wrong with the field
+        message: String,
+    },
+    /// Statistics were asked for without any values, i.e., a filter that matched no span
+    #[error("There are no values")]
+    NoValues,
+}
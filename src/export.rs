@@ -0,0 +1,226 @@
+//! Export of the matched spans as Chrome trace events, which load in Perfetto and chrome://tracing
+//!
+//! Sync spans are complete events, async spans are begin and end pairs with the cookie as id.
+//! Counter traces become counter events and all other instant traces instant events.
+//! Timestamps are in microseconds of the trace clock.
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::{fs::File, io::BufWriter, path::Path};
+
+use crate::{
+    span::Span,
+    trace::{TimeStamp, Trace, TraceMarker, process_names},
+};
+
+/// Category of all events, async begin and end are matched by category, name and id
+const CATEGORY: &str = "hitrace";
+
+#[derive(Debug, Serialize)]
+/// One Chrome trace event
+struct Event {
+    name: String,
+    cat: &'static str,
+    /// The phase, i.e., the kind of the event
+    ph: &'static str,
+    ts: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dur: Option<i64>,
+    pid: u64,
+    tid: u64,
+    /// The cookie of async events
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    /// The scope of instant events
+    #[serde(skip_serializing_if = "Option::is_none")]
+    s: Option<&'static str>,
+    args: Value,
+}
+
+fn micros(ts: &TimeStamp) -> i64 {
+    ts.seconds as i64 * 1_000_000 + ts.micro as i64
+}
+
+#[derive(Debug, Default)]
+/// The events of all exported runs
+pub(crate) struct ChromeTrace {
+    events: Vec<Event>,
+}
+
+impl ChromeTrace {
+    /// Add the spans matched by the span filters and all instant traces of one run.
+    /// The spans are named by the filter that matched them.
+    pub(crate) fn add_run(&mut self, run: usize, spans: &[(&str, &Span)], traces: &[Trace]) {
+        let mut events = Vec::new();
+        for (pid, process) in process_names(traces) {
+            events.push(Event {
+                name: String::from("process_name"),
+                cat: CATEGORY,
+                ph: "M",
+                ts: 0,
+                dur: None,
+                pid,
+                tid: pid,
+                id: None,
+                s: None,
+                args: json!({ "name": process }),
+            });
+        }
+        for (filter, span) in spans {
+            let event = |ph, ts| Event {
+                name: filter.to_string(),
+                cat: CATEGORY,
+                ph,
+                ts,
+                dur: None,
+                pid: span.start.pid,
+                tid: span.start.tid,
+                id: None,
+                s: None,
+                args: json!({ "run": run, "function": span.name() }),
+            };
+            let start = micros(&span.start.timestamp);
+            if span.is_async() {
+                let id = Some(span.start.number.clone());
+                events.push(Event {
+                    id: id.clone(),
+                    ..event("b", start)
+                });
+                events.push(Event {
+                    id,
+                    tid: span.end.tid,
+                    ..event("e", micros(&span.end.timestamp))
+                });
+            } else {
+                events.push(Event {
+                    dur: Some(span.duration().whole_microseconds() as i64),
+                    ..event("X", start)
+                });
+            }
+        }
+        for trace in traces
+            .iter()
+            .filter(|t| matches!(t.trace_marker, TraceMarker::Dot))
+        {
+            let (name, ph, s, args) = match trace.counter() {
+                Some((name, value)) => (name, "C", None, json!({ name: value })),
+                None => (trace.function.trim(), "i", Some("t"), json!({ "run": run })),
+            };
+            events.push(Event {
+                name: name.to_owned(),
+                cat: CATEGORY,
+                ph,
+                ts: micros(&trace.timestamp),
+                dur: None,
+                pid: trace.pid,
+                tid: trace.tid,
+                id: None,
+                s,
+                args,
+            });
+        }
+        // metadata first, then by time. The sort is stable so a begin stays before its end
+        events.sort_by_key(|e| (e.ph != "M", e.ts));
+        self.events.extend(events);
+    }
+
+    /// Append the events of the other trace, i.e., of the next runs
+    pub(crate) fn append(&mut self, other: ChromeTrace) {
+        self.events.extend(other.events);
+    }
+
+    /// The document in the json object format
+    fn to_json(&self) -> Value {
+        json!({ "traceEvents": self.events, "displayTimeUnit": "ms" })
+    }
+
+    /// Write the events to the path
+    pub(crate) fn write(&self, path: &Path) -> Result<()> {
+        let file = File::create(path).with_context(|| format!("Could not create {:?}", path))?;
+        serde_json::to_writer(BufWriter::new(file), &self.to_json())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::span::find_all_spans;
+
+    fn trace(trace_marker: TraceMarker, number: &str, function: &str, micro: u64) -> Trace {
+        Trace {
+            name: String::from("org.servo.servo"),
+            pid: 7,
+            tid: 7,
+            cpu: 0,
+            timestamp: TimeStamp { seconds: 2, micro },
+            trace_marker,
+            number: number.to_owned(),
+            shorthand: String::from("H"),
+            function: function.to_owned(),
+        }
+    }
+
+    fn exported(traces: &[Trace]) -> Vec<Value> {
+        let (spans, _integrity) = find_all_spans(traces);
+        let named = spans.iter().map(|s| (s.name(), s)).collect::<Vec<_>>();
+        let mut chrome = ChromeTrace::default();
+        chrome.add_run(1, &named, traces);
+        let text = chrome.to_json().to_string();
+        let parsed: Value = serde_json::from_str(&text).unwrap();
+        parsed["traceEvents"].as_array().unwrap().clone()
+    }
+
+    #[test]
+    fn sync_spans_are_complete_events() {
+        let traces = vec![
+            trace(TraceMarker::StartSync, "7", " LoadPage", 10),
+            trace(TraceMarker::StartSync, "7", " Layout", 12),
+            trace(TraceMarker::EndSync, "7", "", 15),
+            trace(TraceMarker::Dot, "7", " FirstPaint", 16),
+            trace(TraceMarker::EndSync, "7", "", 20),
+        ];
+        let events = exported(&traces);
+        let phases = events
+            .iter()
+            .map(|e| e["ph"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(phases, ["M", "X", "X", "i"]);
+        assert_eq!(events[1]["name"], "LoadPage");
+        assert_eq!(events[1]["ts"], 2_000_010);
+        assert_eq!(events[1]["dur"], 10);
+        assert_eq!(events[2]["name"], "Layout");
+        assert_eq!(events[2]["dur"], 3);
+        assert_eq!(events[3]["name"], "FirstPaint");
+    }
+
+    #[test]
+    fn async_spans_are_begin_end_pairs() {
+        let traces = vec![
+            trace(TraceMarker::StartAsync, "1", " Decode", 1),
+            trace(TraceMarker::StartAsync, "2", " Decode", 2),
+            trace(TraceMarker::EndAsync, "1", " Decode", 3),
+            trace(TraceMarker::Dot, "7", " mem 42", 4),
+            trace(TraceMarker::EndAsync, "2", " Decode", 5),
+        ];
+        let events = exported(&traces);
+        let phases = events
+            .iter()
+            .map(|e| (e["ph"].as_str().unwrap(), e["ts"].as_i64().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            phases,
+            [
+                ("M", 0),
+                ("b", 2_000_001),
+                ("b", 2_000_002),
+                ("e", 2_000_003),
+                ("C", 2_000_004),
+                ("e", 2_000_005)
+            ]
+        );
+        assert_eq!(events[1]["id"], "1");
+        assert_eq!(events[3]["id"], "1");
+        assert_eq!(events[4]["args"]["mem"], 42);
+    }
+}
@@ -2,7 +2,10 @@ use anyhow::{Result, anyhow};
 use std::collections::HashMap;
 use time::Duration;
 
-use crate::{Trace, trace::difference_of_traces};
+use crate::{
+    Trace,
+    trace::{TimeStamp, difference_of_timestamps, difference_of_traces},
+};
 
 /// Way to construct filters
 pub(crate) struct Filter<'a> {
@@ -14,8 +17,8 @@ pub(crate) struct Filter<'a> {
     pub(crate) last: fn(&Trace) -> bool,
 }
 
-/// Turn a filter into a str and Result<Duration>
-fn filter_to_duration<'a>(v: &[Trace], filter: &'a Filter) -> (&'a str, Result<Duration>) {
+/// Find the trace starting and the trace ending the window of the filter
+pub(crate) fn filter_window<'b>(v: &'b [Trace], filter: &Filter) -> Result<(&'b Trace, &'b Trace)> {
     let first = v
         .iter()
         .filter(|t| (filter.first)(t))
@@ -25,27 +28,61 @@ fn filter_to_duration<'a>(v: &[Trace], filter: &'a Filter) -> (&'a str, Result<D
         .filter(|t| (filter.last)(t))
         .collect::<Vec<&Trace>>();
 
-    let result = if first.len() != 1 || last.len() != 1 {
-        Err(anyhow!(
-            "Your filter functions are not specific or over specific, we got the following number of results: name: {}, first: {}, last: {}",
-            filter.name,
-            first.len(),
-            last.len()
-        ))
+    if first.len() != 1 || last.len() != 1 {
+        Err(window_error(filter, first.len(), last.len()))
     } else {
-        let first_trace = first.first().unwrap();
-        let last_trace = last.first().unwrap();
+        Ok((first[0], last[0]))
+    }
+}
 
-        Ok(difference_of_traces(last_trace, first_trace))
-    };
+/// The error of a filter whose functions did not match exactly once
+fn window_error(filter: &Filter, first: usize, last: usize) -> anyhow::Error {
+    anyhow!(
+        "Your filter functions are not specific or over specific, we got the following number of results: name: {}, first: {}, last: {}",
+        filter.name,
+        first,
+        last
+    )
+}
+
+#[derive(Debug, Default)]
+/// The times of the traces a filter matched so far, to find its window without keeping the traces
+pub(crate) struct WindowMatches {
+    first: Vec<TimeStamp>,
+    last: Vec<TimeStamp>,
+}
 
+impl WindowMatches {
+    /// Look at the next trace
+    pub(crate) fn push(&mut self, filter: &Filter, trace: &Trace) {
+        if (filter.first)(trace) {
+            self.first.push(trace.timestamp);
+        }
+        if (filter.last)(trace) {
+            self.last.push(trace.timestamp);
+        }
+    }
+
+    /// The duration of the window, like [`filter_window`] it needs exactly one first and one last trace
+    pub(crate) fn duration(&self, filter: &Filter) -> Result<Duration> {
+        match (self.first.as_slice(), self.last.as_slice()) {
+            ([first], [last]) => Ok(difference_of_timestamps(last, first)),
+            (first, last) => Err(window_error(filter, first.len(), last.len())),
+        }
+    }
+}
+
+/// Turn a filter into a str and Result<Duration>
+fn filter_to_duration<'a>(v: &[Trace], filter: &'a Filter) -> (&'a str, Result<Duration>) {
+    let result = filter_window(v, filter)
+        .map(|(first_trace, last_trace)| difference_of_traces(last_trace, first_trace));
     (filter.name, result)
 }
 
 /// Look through the traces and find all timing differences coming from the filters
 pub(crate) fn find_notable_differences<'a>(
     v: &[Trace],
-    filters: &'a Vec<Filter>,
+    filters: &'a [Filter],
 ) -> HashMap<&'a str, Result<Duration>> {
     filters
         .iter()
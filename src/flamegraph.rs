@@ -0,0 +1,124 @@
+//! Folding the sync spans into stacks for a flamegraph given with `--flamegraph`
+use anyhow::{Context, Result};
+use inferno::flamegraph::{self, Options};
+use std::{collections::HashMap, fs::File, io::BufWriter, io::Write, path::Path};
+
+use crate::span::Span;
+
+/// The self time in microseconds of every stack, frames are separated by `;`
+pub(crate) type Folded = HashMap<String, u64>;
+
+/// Fold the sync spans into stacks of the process and the spans open on the thread.
+/// Async spans overlap the sync spans of their thread and are left out.
+pub(crate) fn fold(spans: &[Span], process_names: &HashMap<u64, &str>) -> Folded {
+    let mut by_thread: HashMap<(u64, u64), Vec<&Span>> = HashMap::new();
+    for span in spans.iter().filter(|s| !s.is_async()) {
+        by_thread
+            .entry((span.start.pid, span.start.tid))
+            .or_default()
+            .push(span);
+    }
+    let mut folded = Folded::new();
+    for ((pid, _tid), mut thread) in by_thread {
+        // a parent starts before its children, with equal starts the parent ends later
+        thread.sort_by_key(|s| (s.start.timestamp, std::cmp::Reverse(s.end.timestamp)));
+        let process = process_names
+            .get(&pid)
+            .map_or(pid.to_string(), |p| p.to_string());
+        let mut open: Vec<&Span> = Vec::new();
+        for span in thread {
+            while open
+                .last()
+                .is_some_and(|parent| parent.end.timestamp <= span.start.timestamp)
+            {
+                open.pop();
+            }
+            open.push(span);
+            let stack = std::iter::once(process.as_str())
+                .chain(open.iter().map(|s| s.name()))
+                .collect::<Vec<_>>()
+                .join(";");
+            *folded.entry(stack).or_default() += span.self_duration.whole_microseconds() as u64;
+        }
+    }
+    folded
+}
+
+/// Add the stacks of a run
+pub(crate) fn append(into: &mut Folded, from: Folded) {
+    for (stack, micros) in from {
+        *into.entry(stack).or_default() += micros;
+    }
+}
+
+/// Write the stacks as svg if the path ends in `.svg` and as collapsed stack lines otherwise
+pub(crate) fn write(path: &Path, folded: &Folded) -> Result<()> {
+    let mut lines = folded
+        .iter()
+        .filter(|(_stack, micros)| **micros > 0)
+        .map(|(stack, micros)| format!("{} {}", stack, micros))
+        .collect::<Vec<_>>();
+    lines.sort();
+    let file = File::create(path).with_context(|| format!("Could not create {:?}", path))?;
+    let mut writer = BufWriter::new(file);
+    if path.extension().is_some_and(|e| e == "svg") {
+        let mut options = Options::default();
+        options.title = String::from("hitrace-bench spans");
+        options.count_name = String::from("µs");
+        flamegraph::from_lines(&mut options, lines.iter().map(String::as_str), writer)
+            .with_context(|| format!("Could not write flamegraph {:?}", path))?;
+    } else {
+        for line in lines {
+            writeln!(writer, "{}", line)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        span::find_all_spans,
+        trace::{TimeStamp, Trace, TraceMarker},
+    };
+
+    fn trace(trace_marker: TraceMarker, function: &str, micro: u64) -> Trace {
+        Trace {
+            name: String::from("org.servo.servo"),
+            pid: 1,
+            tid: 1,
+            cpu: 0,
+            timestamp: TimeStamp { seconds: 1, micro },
+            trace_marker,
+            number: String::from("1"),
+            shorthand: String::from("H"),
+            function: function.to_owned(),
+        }
+    }
+
+    #[test]
+    fn nested_spans_fold_into_stacks() {
+        let traces = vec![
+            trace(TraceMarker::StartSync, " LoadPage", 0),
+            trace(TraceMarker::StartSync, " Layout", 0),
+            trace(TraceMarker::EndSync, "", 10),
+            trace(TraceMarker::StartSync, " Paint", 20),
+            trace(TraceMarker::EndSync, "", 25),
+            trace(TraceMarker::EndSync, "", 30),
+            trace(TraceMarker::StartSync, " Layout", 40),
+            trace(TraceMarker::EndSync, "", 45),
+        ];
+        let (spans, _integrity) = find_all_spans(&traces);
+        let folded = fold(&spans, &HashMap::from([(1, "servo")]));
+        assert_eq!(
+            folded,
+            Folded::from([
+                (String::from("servo;LoadPage"), 15),
+                (String::from("servo;LoadPage;Layout"), 10),
+                (String::from("servo;LoadPage;Paint"), 5),
+                (String::from("servo;Layout"), 5),
+            ])
+        );
+    }
+}
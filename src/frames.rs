@@ -0,0 +1,80 @@
+//! Smoothness of the frames of a run from the spans of `--frames`, i.e., the vsync or composition spans
+//!
+//! The frame time is the time between the ends of consecutive frame spans, as that is when a frame is presented.
+use time::Duration;
+
+use crate::{span::Span, trace::difference_of_timestamps};
+
+#[derive(Debug, Clone, PartialEq)]
+/// The frames of one run
+pub(crate) struct FrameStats {
+    /// Frames per second between the first and the last frame
+    pub(crate) fps: f64,
+    /// Time between consecutive frames in the order they were presented
+    pub(crate) frame_times: Vec<Duration>,
+    /// Frames that took longer than the budget
+    pub(crate) janky: u32,
+    /// Vsyncs without a new frame, a frame taking two and a half budgets dropped two frames
+    pub(crate) dropped: u32,
+    /// The longest frame time
+    pub(crate) longest: Duration,
+}
+
+/// The frame statistics of the spans against the budget, None with fewer than two frames
+pub(crate) fn frame_stats(spans: &[&Span], budget: Duration) -> Option<FrameStats> {
+    let mut ends = spans.iter().map(|s| s.end.timestamp).collect::<Vec<_>>();
+    ends.sort();
+    let frame_times = ends
+        .windows(2)
+        .map(|w| difference_of_timestamps(&w[1], &w[0]))
+        .collect::<Vec<_>>();
+    let longest = frame_times.iter().max().copied()?;
+    let total = difference_of_timestamps(ends.last()?, ends.first()?);
+    let over = frame_times
+        .iter()
+        .filter(|t| **t > budget)
+        .map(|t| (*t / budget).round() as u32)
+        .collect::<Vec<_>>();
+    Some(FrameStats {
+        fps: frame_times.len() as f64 / total.as_seconds_f64(),
+        janky: over.len() as u32,
+        dropped: over.iter().map(|missed| missed.saturating_sub(1)).sum(),
+        longest,
+        frame_times,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse::parse_report, span::find_all_spans};
+
+    #[test]
+    fn slow_frames_are_janky() {
+        // frames end after 16ms, 16ms, 50ms and 16ms with a budget of 16.6ms
+        let dump = " RSMainThread-1   (  1) [000] .... 100.000000: tracing_mark_write: B|1|H: Frame
+ RSMainThread-1   (  1) [000] .... 100.004000: tracing_mark_write: E|1|H:
+ RSMainThread-1   (  1) [000] .... 100.016000: tracing_mark_write: B|1|H: Frame
+ RSMainThread-1   (  1) [000] .... 100.020000: tracing_mark_write: E|1|H:
+ RSMainThread-1   (  1) [000] .... 100.032000: tracing_mark_write: B|1|H: Frame
+ RSMainThread-1   (  1) [000] .... 100.036000: tracing_mark_write: E|1|H:
+ RSMainThread-1   (  1) [000] .... 100.082000: tracing_mark_write: B|1|H: Frame
+ RSMainThread-1   (  1) [000] .... 100.086000: tracing_mark_write: E|1|H:
+ RSMainThread-1   (  1) [000] .... 100.098000: tracing_mark_write: B|1|H: Frame
+ RSMainThread-1   (  1) [000] .... 100.102000: tracing_mark_write: E|1|H:
+";
+        let report = parse_report(dump.as_bytes(), "").unwrap();
+        let (spans, _integrity) = find_all_spans(&report.traces);
+        let spans = spans.iter().collect::<Vec<_>>();
+        let stats = frame_stats(&spans, Duration::microseconds(16_600)).unwrap();
+        assert_eq!(
+            stats.frame_times,
+            [16, 16, 50, 16].map(Duration::milliseconds)
+        );
+        assert_eq!(stats.janky, 1);
+        assert_eq!(stats.dropped, 2);
+        assert_eq!(stats.longest, Duration::milliseconds(50));
+        assert!((stats.fps - 4.0 / 0.098).abs() < 1e-9);
+        assert_eq!(frame_stats(&spans[..1], Duration::milliseconds(16)), None);
+    }
+}
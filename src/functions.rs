@@ -0,0 +1,158 @@
+//! A profile of every function over all spans of a run with total and self time
+use std::{cmp::Reverse, collections::HashMap};
+use time::Duration;
+
+use crate::{
+    span::Span,
+    trace::{Trace, difference_of_traces},
+};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+/// The spans of one function
+pub(crate) struct FunctionTime {
+    /// Number of spans
+    pub(crate) count: usize,
+    /// Wall time of the outermost calls, recursive calls are inside them and not counted again
+    pub(crate) total: Duration,
+    /// Summed self time of all spans
+    pub(crate) self_time: Duration,
+}
+
+/// The time of every function in one run by function name
+pub(crate) type FunctionTimes = HashMap<String, FunctionTime>;
+
+/// The functions of one run
+pub(crate) struct Profile {
+    /// Time from the first to the last trace of the run
+    pub(crate) run_time: Duration,
+    pub(crate) functions: FunctionTimes,
+}
+
+/// Sum up the time of every function
+pub(crate) fn profile(traces: &[Trace], spans: &[Span]) -> Profile {
+    let run_time = traces
+        .first()
+        .zip(traces.last())
+        .map_or(Duration::ZERO, |(first, last)| {
+            difference_of_traces(last, first)
+        });
+    Profile {
+        run_time,
+        functions: function_times(spans),
+    }
+}
+
+/// Sum up the time of every function
+fn function_times(spans: &[Span]) -> FunctionTimes {
+    let mut times = FunctionTimes::new();
+    for span in spans {
+        let time = times.entry(span.name().to_owned()).or_default();
+        time.count += 1;
+        if span.recursion == 1 {
+            time.total += span.duration();
+        }
+        time.self_time += span.self_duration;
+    }
+    times
+}
+
+/// A function averaged over all runs
+pub(crate) struct FunctionSummary<'a> {
+    pub(crate) name: &'a str,
+    /// Average number of calls per run
+    pub(crate) calls: f64,
+    /// Average total time per run
+    pub(crate) total: Duration,
+    /// Average duration of a call
+    pub(crate) mean: Duration,
+    /// Average self time per run
+    pub(crate) self_time: Duration,
+    /// Total time as a percentage of the run time
+    pub(crate) share: f64,
+}
+
+/// The `top` functions with the highest self time, averaged over the runs
+pub(crate) fn top_functions(runs: &[Profile], top: usize) -> Vec<FunctionSummary<'_>> {
+    let mut summed: HashMap<&str, FunctionTime> = HashMap::new();
+    for run in runs {
+        for (name, time) in run.functions.iter() {
+            let entry = summed.entry(name).or_default();
+            entry.count += time.count;
+            entry.total += time.total;
+            entry.self_time += time.self_time;
+        }
+    }
+    let run_time = runs.iter().map(|r| r.run_time).sum::<Duration>();
+    let runs = runs.len().max(1) as u32;
+    let mut functions = summed
+        .into_iter()
+        .map(|(name, time)| FunctionSummary {
+            name,
+            calls: time.count as f64 / runs as f64,
+            total: time.total / runs,
+            mean: time.total / time.count as u32,
+            self_time: time.self_time / runs,
+            share: if run_time.is_zero() {
+                0.0
+            } else {
+                time.total / run_time * 100.0
+            },
+        })
+        .collect::<Vec<_>>();
+    functions.sort_by_key(|f| (Reverse(f.self_time), f.name));
+    functions.truncate(top);
+    functions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        span::find_all_spans,
+        trace::{TimeStamp, Trace, TraceMarker},
+    };
+
+    fn trace(trace_marker: TraceMarker, function: &str, micro: u64) -> Trace {
+        Trace {
+            name: String::from("org.servo.servo"),
+            pid: 1,
+            tid: 1,
+            cpu: 0,
+            timestamp: TimeStamp { seconds: 1, micro },
+            trace_marker,
+            number: String::from("1"),
+            shorthand: String::from("H"),
+            function: function.to_owned(),
+        }
+    }
+
+    #[test]
+    fn recursive_calls_count_once_in_total() {
+        let traces = vec![
+            trace(TraceMarker::StartSync, " perform_updates", 0),
+            trace(TraceMarker::StartSync, " Layout", 10),
+            trace(TraceMarker::StartSync, " Layout", 20),
+            trace(TraceMarker::EndSync, "", 50),
+            trace(TraceMarker::EndSync, "", 60),
+            trace(TraceMarker::EndSync, "", 70),
+        ];
+        let (spans, _integrity) = find_all_spans(&traces);
+        let times = function_times(&spans);
+        let layout = times["Layout"];
+        assert_eq!(layout.count, 2);
+        assert_eq!(layout.total, Duration::microseconds(50));
+        assert_eq!(layout.self_time, Duration::microseconds(50));
+        let updates = times["perform_updates"];
+        assert_eq!(updates.total, Duration::microseconds(70));
+        assert_eq!(updates.self_time, Duration::microseconds(20));
+
+        let runs = [Profile {
+            run_time: Duration::microseconds(100),
+            functions: times,
+        }];
+        let top = top_functions(&runs, 1);
+        assert_eq!(top[0].name, "Layout");
+        assert_eq!(top[0].mean, Duration::microseconds(25));
+        assert_eq!(top[0].share, 50.0);
+    }
+}
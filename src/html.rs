@@ -0,0 +1,322 @@
+//! A self-contained html report given with `--html-report`, for sharing the results with people who do not run the tool
+//!
+//! The charts are inline svg with tooltips and the tables sort by a click on the header, so the file
+//! works offline and can be sent around on its own.
+use anyhow::{Context, Result};
+use std::{collections::BTreeMap, fmt::Write, path::Path};
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+
+use crate::{
+    RunResults,
+    args::Args,
+    backend::Device,
+    device,
+    report::{ReportData, Row},
+    utils::{histogram, percentile},
+};
+
+const WIDTH: f64 = 640.0;
+const BUCKETS: usize = 20;
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em;color:#222}
+table{border-collapse:collapse;margin-bottom:2em}
+th,td{border:1px solid #ccc;padding:4px 8px;text-align:right}
+th{background:#eee;cursor:pointer}
+td:first-child,th:first-child{text-align:left}
+.regression{color:#b00}.improvement{color:#070}
+rect.bar{fill:#4a7fb5}rect.bar:hover,rect.box:hover{fill:#f0a030}
+rect.box{fill:#9cc0e6;stroke:#222}line{stroke:#222}";
+
+/// Sorts the rows of a table by the clicked column, numerically if the cells are numbers
+const SCRIPT: &str = "document.querySelectorAll('th').forEach(th => th.addEventListener('click', () => {
+  const table = th.closest('table'), index = [...th.parentNode.children].indexOf(th);
+  const rows = [...table.querySelectorAll('tr')].slice(1);
+  const key = r => { const t = r.children[index].textContent; const n = parseFloat(t); return isNaN(n) ? t : n; };
+  const asc = table.dataset.sorted !== String(index);
+  rows.sort((a, b) => (key(a) > key(b) ? 1 : key(a) < key(b) ? -1 : 0) * (asc ? 1 : -1));
+  table.dataset.sorted = asc ? index : '';
+  rows.forEach(r => table.appendChild(r));
+}));";
+
+/// When and where the session ran, the device fields are missing for trace files
+pub(crate) fn metadata(args: &Args) -> Vec<(&'static str, String)> {
+    let now = OffsetDateTime::now_utc();
+    let now = now.replace_nanosecond(0).unwrap_or(now);
+    let mut metadata = vec![("date", now.format(&Rfc3339).unwrap_or_default())];
+    if !args.label.is_empty() {
+        metadata.push(("label", args.label.clone()));
+    }
+    if args.from_files.is_empty() {
+        let device = Device::new(args.backend, args.device.as_deref());
+        metadata.push(("homepage", args.homepage.clone()));
+        metadata.push(("bundle", args.bundle_name.clone()));
+        if let Ok(model) = device::model(device) {
+            metadata.push(("device model", model));
+        }
+        if let Ok(Some(version)) = device::app_version(&args.bundle_name, device) {
+            metadata.push(("app version", version));
+        }
+    }
+    metadata
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The horizontal position of a value between min and max
+fn scale(value: i64, min: i64, max: i64) -> f64 {
+    if max == min {
+        WIDTH / 2.0
+    } else {
+        (value - min) as f64 / (max - min) as f64 * WIDTH
+    }
+}
+
+/// A box plot of the quartiles with whiskers at the minimum and maximum
+fn box_plot(out: &mut String, values: &[i64]) {
+    let (Some(min), Some(q1), Some(median), Some(q3), Some(max)) = (
+        values.iter().min().copied(),
+        percentile(values, 25.0),
+        percentile(values, 50.0),
+        percentile(values, 75.0),
+        values.iter().max().copied(),
+    ) else {
+        return;
+    };
+    let x = |v| scale(v, min, max);
+    writeln!(
+        out,
+        "<svg width=\"{w}\" height=\"50\" viewBox=\"-5 0 {vw} 50\">
+<line x1=\"{}\" y1=\"25\" x2=\"{}\" y2=\"25\"/>
+<line x1=\"{}\" y1=\"10\" x2=\"{}\" y2=\"40\"/><line x1=\"{}\" y1=\"10\" x2=\"{}\" y2=\"40\"/>
+<rect class=\"box\" x=\"{}\" y=\"5\" width=\"{}\" height=\"40\"><title>min {} µs, q1 {} µs, median {} µs, q3 {} µs, max {} µs</title></rect>
+<line x1=\"{}\" y1=\"5\" x2=\"{}\" y2=\"45\"/>
+</svg>",
+        x(min),
+        x(max),
+        x(min),
+        x(min),
+        x(max),
+        x(max),
+        x(q1),
+        (x(q3) - x(q1)).max(1.0),
+        min,
+        q1,
+        median,
+        q3,
+        max,
+        x(median),
+        x(median),
+        w = WIDTH + 10.0,
+        vw = WIDTH + 10.0,
+    )
+    .unwrap();
+}
+
+/// A histogram with the range and count of every bucket as tooltip
+fn histogram_plot(out: &mut String, values: &[i64]) {
+    let buckets = histogram(values, BUCKETS, false);
+    let Some(highest) = buckets.iter().map(|b| b.count).max() else {
+        return;
+    };
+    let width = WIDTH / buckets.len() as f64;
+    writeln!(out, "<svg width=\"{}\" height=\"120\">", WIDTH).unwrap();
+    for (i, bucket) in buckets.iter().enumerate() {
+        let height = bucket.count as f64 / highest as f64 * 110.0;
+        writeln!(
+            out,
+            "<rect class=\"bar\" x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\"><title>{}–{} µs: {} runs</title></rect>",
+            i as f64 * width,
+            120.0 - height,
+            (width - 1.0).max(1.0),
+            height,
+            bucket.low,
+            bucket.high,
+            bucket.count
+        )
+        .unwrap();
+    }
+    out.push_str("</svg>\n");
+}
+
+fn table(out: &mut String, title: &str, unit: &str, rows: &[Row]) {
+    if rows.is_empty() {
+        return;
+    }
+    writeln!(
+        out,
+        "<h2>{}</h2>\n<table>\n<tr><th>name</th><th>runs</th><th>avg{unit}</th><th>min{unit}</th><th>max{unit}</th><th>median{unit}</th><th>p90{unit}</th><th>p95{unit}</th><th>p99{unit}</th><th>stddev{unit}</th></tr>",
+        title
+    )
+    .unwrap();
+    for r in rows {
+        writeln!(
+            out,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape(r.name),
+            r.runs,
+            r.avg,
+            r.min,
+            r.max,
+            r.median,
+            r.p90,
+            r.p95,
+            r.p99,
+            r.std_dev
+        )
+        .unwrap();
+    }
+    out.push_str("</table>\n");
+}
+
+/// The means against the baseline, like the markdown report
+fn baseline(out: &mut String, data: &ReportData) {
+    if data.comparisons.is_empty() {
+        return;
+    }
+    out.push_str("<h2>Baseline</h2>\n<table>\n<tr><th>name</th><th>baseline avg</th><th>avg</th><th>delta</th><th>change %</th><th></th></tr>\n");
+    for c in data.comparisons {
+        let name = escape(&c.name);
+        match (c.baseline, c.current, c.percent()) {
+            (Some((base, _)), Some((mean, _)), Some((percent, _))) => {
+                let marker = match data.threshold {
+                    Some(t) if !c.point && percent > t => {
+                        "<td class=\"regression\">regression</td>"
+                    }
+                    Some(t) if !c.point && percent < -t => {
+                        "<td class=\"improvement\">improvement</td>"
+                    }
+                    _ => "<td></td>",
+                };
+                writeln!(
+                    out,
+                    "<tr><td>{}</td><td>{}</td><td>{}</td><td>{:+}</td><td>{:+.1}</td>{}</tr>",
+                    name,
+                    base,
+                    mean,
+                    mean - base,
+                    percent,
+                    marker
+                )
+                .unwrap();
+            }
+            (None, Some((mean, _)), _) => writeln!(
+                out,
+                "<tr><td>{}</td><td></td><td>{}</td><td></td><td></td><td>added</td></tr>",
+                name, mean
+            )
+            .unwrap(),
+            (Some((base, _)), None, _) => writeln!(
+                out,
+                "<tr><td>{}</td><td>{}</td><td></td><td></td><td></td><td>removed</td></tr>",
+                name, base
+            )
+            .unwrap(),
+            _ => writeln!(
+                out,
+                "<tr><td>{}</td><td></td><td></td><td></td><td></td><td>no values</td></tr>",
+                name
+            )
+            .unwrap(),
+        }
+    }
+    out.push_str("</table>\n");
+}
+
+/// The whole page, the charts are drawn from the raw durations of every filter
+pub(crate) fn render(
+    metadata: &[(&str, String)],
+    results: &RunResults,
+    data: &ReportData,
+) -> String {
+    let mut out = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>hitrace-bench report</title>\n<style>\n{}\n</style>\n</head>\n<body>\n<h1>hitrace-bench report</h1>\n<table>\n",
+        STYLE
+    );
+    for (key, value) in metadata {
+        writeln!(
+            out,
+            "<tr><td>{}</td><td>{}</td></tr>",
+            escape(key),
+            escape(value)
+        )
+        .unwrap();
+    }
+    out.push_str("</table>\n");
+    table(&mut out, "Filters", " (µs)", &data.filters);
+    table(&mut out, "Points", "", &data.points);
+    baseline(&mut out, data);
+    if !data.errors.is_empty() {
+        out.push_str("<h2>Errors</h2>\n<table>\n<tr><th>name</th><th>errors</th></tr>\n");
+        for (name, count) in &data.errors {
+            writeln!(out, "<tr><td>{}</td><td>{}</td></tr>", escape(name), count).unwrap();
+        }
+        out.push_str("</table>\n");
+    }
+    out.push_str("<h2>Distributions</h2>\n");
+    for (name, durations) in results.iter().collect::<BTreeMap<_, _>>() {
+        let micros = durations
+            .iter()
+            .map(|d| d.whole_microseconds() as i64)
+            .collect::<Vec<_>>();
+        if micros.is_empty() {
+            continue;
+        }
+        writeln!(out, "<h3>{}</h3>", escape(name)).unwrap();
+        box_plot(&mut out, &micros);
+        histogram_plot(&mut out, &micros);
+    }
+    writeln!(out, "<script>\n{}\n</script>\n</body>\n</html>", SCRIPT).unwrap();
+    out
+}
+
+/// Write the report to `index.html` in the directory, creating the directory if needed
+pub(crate) fn write(
+    dir: &Path,
+    metadata: &[(&str, String)],
+    results: &RunResults,
+    data: &ReportData,
+) -> Result<()> {
+    std::fs::create_dir_all(dir).with_context(|| format!("Could not create {:?}", dir))?;
+    let path = dir.join("index.html");
+    std::fs::write(&path, render(metadata, results, data))
+        .with_context(|| format!("Could not write {:?}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FilterErrors, PointResults};
+    use time::Duration;
+
+    #[test]
+    fn report_has_tables_and_charts() {
+        let results = RunResults::from([(
+            String::from("<Layout>"),
+            vec![
+                Duration::microseconds(100),
+                Duration::microseconds(200),
+                Duration::microseconds(400),
+            ],
+        )]);
+        let points = PointResults::new();
+        let errors = FilterErrors::new();
+        let data = ReportData::new(&results, &points, &errors);
+        let html = render(
+            &[("device model", String::from("ALN-AL00"))],
+            &results,
+            &data,
+        );
+        assert!(html.contains("<td>device model</td><td>ALN-AL00</td>"));
+        assert!(html.contains("<td>&lt;Layout&gt;</td><td>3</td>"));
+        assert!(html.contains(
+            "<title>min 100 µs, q1 100 µs, median 200 µs, q3 400 µs, max 400 µs</title>"
+        ));
+        assert_eq!(html.matches("class=\"bar\"").count(), BUCKETS);
+        assert!(!html.contains("<h2>Baseline</h2>"));
+    }
+}
@@ -0,0 +1,77 @@
+//! Gaps inside a parent span where no descendant span was open
+use std::ptr;
+use time::Duration;
+
+use crate::{
+    matcher::SpanFilter,
+    span::Span,
+    trace::{TimeStamp, difference_of_timestamps},
+};
+
+#[derive(Debug)]
+/// The idle gaps of all spans of a parent filter in one run
+pub(crate) struct IdleGaps {
+    /// Summed duration of the parent spans
+    pub(crate) parent: Duration,
+    /// Summed duration of all gaps
+    pub(crate) total: Duration,
+    /// Duration of the longest gap
+    pub(crate) longest: Duration,
+}
+
+impl IdleGaps {
+    /// The idle time as a percentage of the parent spans
+    pub(crate) fn percentage(&self) -> f64 {
+        self.total / self.parent * 100.0
+    }
+}
+
+/// The gaps between the descendants of the parent on the same thread
+fn gaps_in(parent: &Span, spans: &[Span]) -> Vec<Duration> {
+    let mut children = spans
+        .iter()
+        .filter(|s| !ptr::eq(s.start, parent.start) && !s.is_async())
+        .filter(|s| s.start.pid == parent.start.pid && s.start.tid == parent.start.tid)
+        .filter(|s| {
+            s.start.timestamp >= parent.start.timestamp && s.end.timestamp <= parent.end.timestamp
+        })
+        .map(|s| (s.start.timestamp, s.end.timestamp))
+        .collect::<Vec<(TimeStamp, TimeStamp)>>();
+    children.sort();
+
+    let mut gaps = Vec::new();
+    let mut covered_until = parent.start.timestamp;
+    for (start, end) in children {
+        if start > covered_until {
+            gaps.push(difference_of_timestamps(&start, &covered_until));
+        }
+        covered_until = covered_until.max(end);
+    }
+    if parent.end.timestamp > covered_until {
+        gaps.push(difference_of_timestamps(
+            &parent.end.timestamp,
+            &covered_until,
+        ));
+    }
+    gaps
+}
+
+/// Find the idle gaps in every span matching the parent filter, None if there is no such span
+pub(crate) fn idle_gaps(spans: &[Span], parent: &SpanFilter) -> Option<IdleGaps> {
+    let parents = spans
+        .iter()
+        .filter(|s| parent.matcher.matches(s.name()))
+        .collect::<Vec<_>>();
+    if parents.is_empty() {
+        return None;
+    }
+    let gaps = parents
+        .iter()
+        .flat_map(|p| gaps_in(p, spans))
+        .collect::<Vec<_>>();
+    Some(IdleGaps {
+        parent: parents.iter().map(|p| p.duration()).sum(),
+        total: gaps.iter().sum(),
+        longest: gaps.iter().max().copied().unwrap_or(Duration::ZERO),
+    })
+}
@@ -0,0 +1,470 @@
+//! Json document with all results of a benchmark for dashboards
+//!
+//! Durations are always integers of microseconds and carry a `_us` suffix.
+//! Maps are sorted by key so the same results give the same document.
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+use time::Duration;
+
+use crate::{
+    CounterResults, FilterErrors, PointResults, RunResults, args::Args, bins::Bin,
+    clusters::Cluster, correlation, correlation::RunMetrics, phases, ranking::Rank,
+    utils::AvgMinMax, utils::avg_min_max, utils::percentile,
+};
+
+/// Version of the document, increased on incompatible changes
+const VERSION: u32 = 1;
+
+fn micros(duration: Duration) -> i64 {
+    duration.whole_microseconds() as i64
+}
+
+/// The percentiles of the values by name
+fn percentiles<T: Copy + Ord>(values: &[T], percentiles: &[f64]) -> BTreeMap<String, T> {
+    percentiles
+        .iter()
+        .filter_map(|p| percentile(values, *p).map(|v| (format!("p{}", p), v)))
+        .collect()
+}
+
+#[derive(Debug, Serialize)]
+/// The values of a duration filter over all runs
+struct FilterJson {
+    /// Every duration of all runs in run order
+    samples_us: Vec<i64>,
+    /// Statistics over the samples, null if there are none
+    stats_us: Option<AvgMinMax<i64>>,
+    /// The percentiles given by `--percentile` by name, i.e., `p95`
+    percentiles_us: BTreeMap<String, i64>,
+}
+
+#[derive(Debug, Serialize)]
+/// The values of a point metric over all runs
+struct PointJson {
+    /// Every value of all runs in run order
+    values: Vec<i64>,
+    /// Statistics over the values, null if there are none
+    stats: Option<AvgMinMax<i64>>,
+    /// The percentiles given by `--percentile` by name, i.e., `p95`
+    percentiles: BTreeMap<String, i64>,
+}
+
+#[derive(Debug, Serialize)]
+/// The raw values of one run
+struct RunJson {
+    /// The run starting at 1
+    run: usize,
+    /// The durations of every filter in the run
+    results_us: BTreeMap<String, Vec<i64>>,
+    /// The per run metrics used for correlations, durations in seconds
+    metrics: BTreeMap<String, f64>,
+    /// The trace file the run was analyzed from, null if it was not kept
+    trace: Option<PathBuf>,
+    /// The run is far away from the median, see `--mad-threshold`
+    flagged: bool,
+}
+
+#[derive(Debug, Serialize)]
+/// One time bin of a span filter
+struct BinJson {
+    /// Number of spans starting in the bin
+    count: usize,
+    /// Summed duration of the spans starting in the bin
+    total_us: i64,
+}
+
+#[derive(Debug, Serialize)]
+/// One value of a counter
+struct CounterValueJson {
+    /// Time the value was set in microseconds of the trace clock
+    timestamp_us: i64,
+    value: i64,
+}
+
+#[derive(Debug, Serialize)]
+/// The phases of one run
+struct PhaseRunJson {
+    /// Duration of every segment, null if a milestone was missing
+    durations_us: Option<Vec<i64>>,
+    /// Why the phases could not be found
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+/// The phases between the milestones given on the command line
+struct PhasesJson {
+    /// Names of the segments between consecutive milestones
+    segments: Vec<String>,
+    /// The phases of every run
+    runs: Vec<PhaseRunJson>,
+}
+
+#[derive(Debug, Serialize)]
+/// The correlation of two metrics over the runs
+struct CorrelationJson {
+    a: String,
+    b: String,
+    pearson: Option<f64>,
+    spearman: Option<f64>,
+    /// Number of runs that had both values
+    samples: usize,
+    /// Number of runs skipped because one of the values was missing
+    skipped: usize,
+}
+
+#[derive(Debug, Serialize)]
+/// The document written by `--json`
+pub(crate) struct JsonResults {
+    version: u32,
+    /// The build label given on the command line
+    label: String,
+    /// Number of runs
+    tries: usize,
+    /// Duration filters by name
+    filters: BTreeMap<String, FilterJson>,
+    /// Point metrics by name
+    points: BTreeMap<String, PointJson>,
+    /// Number of errors by filter name
+    errors: BTreeMap<String, u32>,
+    /// The raw values of every run
+    runs: Vec<RunJson>,
+    /// The runs that lost trace events
+    lossy_runs: Vec<usize>,
+    /// The lossy runs are in the results, i.e., `--allow-lossy` was given
+    lossy_included: bool,
+    /// Number of durations discarded as outliers by filter, empty without `--outliers`
+    discarded: BTreeMap<String, usize>,
+    /// Durations of the warmup runs by filter, they are not in the filters
+    warmup_us: BTreeMap<String, Vec<i64>>,
+    /// Values of the warmup runs by point metric, they are not in the points
+    warmup_points: BTreeMap<String, Vec<i64>>,
+    /// Time bins of every run by span filter
+    bins: BTreeMap<String, Vec<Vec<BinJson>>>,
+    /// Values of every run by counter
+    counters: BTreeMap<String, Vec<Vec<CounterValueJson>>>,
+    /// Phases between the milestones, null if none were given
+    phases: Option<PhasesJson>,
+    /// Correlations of every pair of the per run metrics, sorted by name
+    correlations: Vec<CorrelationJson>,
+    /// The score of `--score`, null without components or if one of them has no results
+    score: Option<f64>,
+    /// The cpus of every cluster by name, empty without clusters
+    clusters: BTreeMap<String, Vec<u64>>,
+    /// Time of every span filter on every cluster summed over the runs
+    cluster_time_us: BTreeMap<String, BTreeMap<String, i64>>,
+}
+
+impl JsonResults {
+    /// Collect the results that every benchmark has
+    pub(crate) fn new(
+        args: &Args,
+        results: &RunResults,
+        points: &PointResults,
+        errors: &FilterErrors,
+        runs: &[RunResults],
+        numbers: &[usize],
+        metrics: &[RunMetrics],
+    ) -> Self {
+        let filters = results
+            .iter()
+            .map(|(name, durations)| {
+                let filter = FilterJson {
+                    samples_us: durations.iter().copied().map(micros).collect(),
+                    stats_us: avg_min_max(durations).ok().map(|s| s.map(micros)),
+                    percentiles_us: percentiles(durations, &args.percentiles)
+                        .into_iter()
+                        .map(|(name, d)| (name, micros(d)))
+                        .collect(),
+                };
+                (name.clone(), filter)
+            })
+            .collect();
+        let points = points
+            .iter()
+            .map(|(name, values)| {
+                let point = PointJson {
+                    values: values.clone(),
+                    stats: avg_min_max(values).ok(),
+                    percentiles: percentiles(values, &args.percentiles),
+                };
+                (name.clone(), point)
+            })
+            .collect();
+        let errors = errors
+            .iter()
+            .map(|(name, count)| (name.to_string(), *count))
+            .collect();
+        let runs = runs
+            .iter()
+            .zip(numbers)
+            .zip(metrics)
+            .map(|((results, number), metrics)| RunJson {
+                run: *number,
+                results_us: results
+                    .iter()
+                    .map(|(name, d)| (name.clone(), d.iter().copied().map(micros).collect()))
+                    .collect(),
+                metrics: metrics.iter().map(|(k, v)| (k.clone(), *v)).collect(),
+                trace: None,
+                flagged: false,
+            })
+            .collect();
+
+        let names = metrics
+            .iter()
+            .flat_map(|run| run.keys())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        let mut correlations = Vec::new();
+        for (i, a) in names.iter().enumerate() {
+            for b in &names[i + 1..] {
+                let c = correlation::correlate(metrics, a, b);
+                correlations.push(CorrelationJson {
+                    a: (*a).clone(),
+                    b: (*b).clone(),
+                    pearson: c.pearson,
+                    spearman: c.spearman,
+                    samples: c.samples,
+                    skipped: c.skipped,
+                });
+            }
+        }
+
+        JsonResults {
+            version: VERSION,
+            label: args.label.clone(),
+            tries: args.runs() - args.warmup,
+            filters,
+            points,
+            errors,
+            runs,
+            lossy_runs: Vec::new(),
+            lossy_included: args.allow_lossy,
+            discarded: BTreeMap::new(),
+            warmup_us: BTreeMap::new(),
+            warmup_points: BTreeMap::new(),
+            bins: BTreeMap::new(),
+            counters: BTreeMap::new(),
+            phases: None,
+            correlations,
+            score: None,
+            clusters: BTreeMap::new(),
+            cluster_time_us: BTreeMap::new(),
+        }
+    }
+
+    /// Add the clusters and the time of the span filters on them
+    pub(crate) fn clusters(
+        &mut self,
+        clusters: &[Cluster],
+        time: &HashMap<&str, HashMap<String, Duration>>,
+    ) {
+        self.clusters = clusters
+            .iter()
+            .map(|c| (c.name.clone(), c.cpus.clone()))
+            .collect();
+        self.cluster_time_us = time
+            .iter()
+            .map(|(name, shares)| {
+                let shares = shares
+                    .iter()
+                    .map(|(cluster, d)| (cluster.clone(), micros(*d)))
+                    .collect();
+                (name.to_string(), shares)
+            })
+            .collect();
+    }
+
+    /// Add the score of the session
+    pub(crate) fn score(&mut self, score: Option<f64>) {
+        self.score = score;
+    }
+
+    /// Flag the runs far away from the median, as indices into the runs
+    pub(crate) fn flagged(&mut self, flagged: &BTreeSet<usize>) {
+        for (i, run) in self.runs.iter_mut().enumerate() {
+            run.flagged = flagged.contains(&i);
+        }
+    }
+
+    /// Add the trace file of every run, in the order of the runs
+    pub(crate) fn traces(&mut self, traces: &[Option<PathBuf>]) {
+        for (run, trace) in self.runs.iter_mut().zip(traces) {
+            run.trace = trace.clone();
+        }
+    }
+
+    /// Add the runs that lost trace events
+    pub(crate) fn lossy_runs(&mut self, runs: &[usize]) {
+        self.lossy_runs = runs.to_vec();
+    }
+
+    /// Add the number of durations discarded as outliers
+    pub(crate) fn discarded(&mut self, discarded: &HashMap<String, usize>) {
+        self.discarded = discarded
+            .iter()
+            .map(|(name, count)| (name.clone(), *count))
+            .collect();
+    }
+
+    /// Add the values of the warmup runs
+    pub(crate) fn warmup(&mut self, results: &RunResults, points: &PointResults) {
+        self.warmup_us = results
+            .iter()
+            .map(|(name, d)| (name.clone(), d.iter().copied().map(micros).collect()))
+            .collect();
+        self.warmup_points = points
+            .iter()
+            .map(|(name, values)| (name.clone(), values.clone()))
+            .collect();
+    }
+
+    /// Add the time bins of the span filters
+    pub(crate) fn bins(&mut self, bins: &HashMap<&str, Vec<Vec<Bin>>>) {
+        self.bins = bins
+            .iter()
+            .map(|(name, runs)| {
+                let runs = runs
+                    .iter()
+                    .map(|run| {
+                        run.iter()
+                            .map(|b| BinJson {
+                                count: b.count,
+                                total_us: micros(b.total),
+                            })
+                            .collect()
+                    })
+                    .collect();
+                (name.to_string(), runs)
+            })
+            .collect();
+    }
+
+    /// Add the values of the counters, a counter missing in a run has no values in it
+    pub(crate) fn counters(&mut self, runs: &[CounterResults]) {
+        for (index, run) in runs.iter().enumerate() {
+            for (name, series) in run.iter() {
+                let entry = self.counters.entry(name.clone()).or_default();
+                entry.resize_with(index, Vec::new);
+                entry.push(
+                    series
+                        .iter()
+                        .map(|(ts, value)| CounterValueJson {
+                            timestamp_us: ts.seconds as i64 * 1_000_000 + ts.micro as i64,
+                            value: *value,
+                        })
+                        .collect(),
+                );
+            }
+        }
+    }
+
+    /// Add the phases between the milestones
+    pub(crate) fn phases(&mut self, milestones: &[String], runs: &[Result<Vec<Duration>>]) {
+        let runs = runs
+            .iter()
+            .map(|run| match run {
+                Ok(durations) => PhaseRunJson {
+                    durations_us: Some(durations.iter().copied().map(micros).collect()),
+                    error: None,
+                },
+                Err(e) => PhaseRunJson {
+                    durations_us: None,
+                    error: Some(e.to_string()),
+                },
+            })
+            .collect();
+        self.phases = Some(PhasesJson {
+            segments: phases::segment_names(milestones),
+            runs,
+        });
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// What the sessions of a [`GroupedJson`] benchmarked
+pub(crate) enum Group {
+    /// The targets of `--target` by name
+    Target,
+    /// The devices of `--all-devices` by serial
+    Device,
+}
+
+#[derive(Debug, Serialize)]
+/// The document written by `--json` with `--target` or `--all-devices`, the document of every target by name
+/// or of every device by serial
+pub(crate) struct GroupedJson {
+    version: u32,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    targets: BTreeMap<String, JsonResults>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    devices: BTreeMap<String, JsonResults>,
+    /// The ranking of `--rank`, null without it
+    ranking: Option<RankingJson>,
+}
+
+#[derive(Debug, Serialize)]
+/// The targets or devices ranked by a filter, the ones that regressed most against their baseline first
+struct RankingJson {
+    filter: String,
+    ranks: Vec<Rank>,
+}
+
+impl GroupedJson {
+    /// The documents of the targets or devices
+    pub(crate) fn new(
+        group: Group,
+        documents: impl IntoIterator<Item = (String, JsonResults)>,
+    ) -> Self {
+        let documents = documents.into_iter().collect();
+        let (targets, devices) = match group {
+            Group::Target => (documents, BTreeMap::new()),
+            Group::Device => (BTreeMap::new(), documents),
+        };
+        GroupedJson {
+            version: VERSION,
+            targets,
+            devices,
+            ranking: None,
+        }
+    }
+
+    /// Add the ranking by the filter
+    pub(crate) fn ranking(&mut self, filter: &str, ranks: Vec<Rank>) {
+        self.ranking = Some(RankingJson {
+            filter: filter.to_owned(),
+            ranks,
+        });
+    }
+}
+
+/// Write the document to the path, `-` prints it. It is written to a temporary file next to it first
+/// and then renamed, so readers never see a partially written file.
+pub(crate) fn write_json<T: Serialize>(path: &Path, results: &T) -> Result<()> {
+    if path.as_os_str() == "-" {
+        let mut stdout = io::stdout().lock();
+        serde_json::to_writer_pretty(&mut stdout, results)?;
+        writeln!(stdout)?;
+        return Ok(());
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = Path::new(&tmp);
+    let file = File::create(tmp).with_context(|| format!("Could not create {:?}", tmp))?;
+    let mut writer = BufWriter::new(file);
+    serde_json::to_writer_pretty(&mut writer, results)?;
+    writer.flush()?;
+    writer
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .sync_all()?;
+    fs::rename(tmp, path).with_context(|| format!("Could not move {:?} to {:?}", tmp, path))?;
+    Ok(())
+}
@@ -0,0 +1,195 @@
+//! The raw traces of the runs kept with `--keep-traces`, with a manifest tying the results of every run to its trace
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+
+use crate::{analysis::RunAnalysis, args::Args, matrix::Target, utils::avg_min_max};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, ValueEnum)]
+/// Which traces stay in the directory after the session
+pub(crate) enum KeepPolicy {
+    /// The traces of all runs
+    #[default]
+    All,
+    /// Only the traces of the runs flagged by `--mad-threshold`
+    Flagged,
+}
+
+#[derive(Debug, Serialize)]
+/// One kept trace in the manifest
+struct ManifestRun {
+    /// The run starting at 1
+    run: usize,
+    /// The trace file, relative to the manifest
+    file: String,
+    started: String,
+    finished: String,
+    /// A warmup run, its results are not in the statistics
+    warmup: bool,
+    /// The run lost trace events
+    lossy: bool,
+    /// The run is far away from the median, see `--mad-threshold`
+    flagged: bool,
+    /// The mean of every filter in the run
+    results_us: BTreeMap<String, i64>,
+    /// The mean of every point in the run
+    points: BTreeMap<String, i64>,
+    /// Number of errors by filter name
+    errors: BTreeMap<String, u32>,
+}
+
+#[derive(Debug, Serialize)]
+/// The `manifest.json` next to the kept traces
+struct Manifest<'a> {
+    label: &'a str,
+    bundle: &'a str,
+    homepage: &'a str,
+    device: Option<&'a str>,
+    target: Option<&'a str>,
+    runs: Vec<ManifestRun>,
+}
+
+/// A kept trace and when its run was captured
+struct Kept {
+    run: usize,
+    file: String,
+    started: OffsetDateTime,
+    finished: OffsetDateTime,
+}
+
+/// Copies the trace of every run into the directory before the next run overwrites it
+pub(crate) struct TraceKeeper<'a> {
+    dir: &'a Path,
+    /// Prefix of the file names, so the devices and targets of one invocation can share the directory
+    prefix: String,
+    device: Option<&'a str>,
+    target: Option<&'a Target>,
+    kept: Vec<Kept>,
+}
+
+fn timestamp(t: OffsetDateTime) -> String {
+    t.replace_nanosecond(0)
+        .unwrap_or(t)
+        .format(&Rfc3339)
+        .unwrap_or_default()
+}
+
+impl<'a> TraceKeeper<'a> {
+    pub(crate) fn new(
+        dir: &'a Path,
+        device: Option<&'a str>,
+        target: Option<&'a Target>,
+    ) -> Result<Self> {
+        std::fs::create_dir_all(dir).with_context(|| format!("Could not create {:?}", dir))?;
+        let prefix = device
+            .into_iter()
+            .chain(target.map(|t| t.name.as_str()))
+            .map(|p| format!("{}-", p))
+            .collect();
+        Ok(TraceKeeper {
+            dir,
+            prefix,
+            device,
+            target,
+            kept: Vec::new(),
+        })
+    }
+
+    /// Copy the trace of the run that started at `started`, returns the kept file
+    pub(crate) fn keep(
+        &mut self,
+        run: usize,
+        trace: &Path,
+        started: OffsetDateTime,
+    ) -> Result<PathBuf> {
+        let file = format!("{}run-{}.ftrace", self.prefix, run);
+        let path = self.dir.join(&file);
+        std::fs::copy(trace, &path)
+            .with_context(|| format!("Could not keep the trace of run {} in {:?}", run, path))?;
+        self.kept.push(Kept {
+            run,
+            file,
+            started,
+            finished: OffsetDateTime::now_utc(),
+        });
+        Ok(path)
+    }
+
+    /// Delete the kept traces of the runs that are not flagged, the runs are numbered from 1
+    pub(crate) fn retain_flagged(&mut self, flagged: &BTreeSet<usize>) -> Result<()> {
+        for kept in self.kept.iter().filter(|k| !flagged.contains(&k.run)) {
+            let path = self.dir.join(&kept.file);
+            std::fs::remove_file(&path).with_context(|| format!("Could not delete {:?}", path))?;
+        }
+        self.kept.retain(|k| flagged.contains(&k.run));
+        Ok(())
+    }
+
+    /// Write the manifest with the kept traces and a summary of the results of their runs.
+    /// The flagged runs are numbered from 1
+    pub(crate) fn write_manifest(
+        &self,
+        args: &Args,
+        runs: &[RunAnalysis],
+        flagged: &BTreeSet<usize>,
+    ) -> Result<()> {
+        let runs = self
+            .kept
+            .iter()
+            .map(|kept| {
+                let analysis = runs.iter().find(|r| r.run == kept.run);
+                let results_us = analysis
+                    .into_iter()
+                    .flat_map(|a| &a.results)
+                    .filter_map(|(name, durations)| {
+                        let micros = durations
+                            .iter()
+                            .map(|d| d.whole_microseconds() as i64)
+                            .collect::<Vec<_>>();
+                        Some((name.clone(), avg_min_max(&micros).ok()?.avg))
+                    })
+                    .collect();
+                let points = analysis
+                    .into_iter()
+                    .flat_map(|a| &a.points)
+                    .filter_map(|(name, values)| {
+                        Some((name.clone(), avg_min_max(values).ok()?.avg))
+                    })
+                    .collect();
+                let errors = analysis
+                    .into_iter()
+                    .flat_map(|a| &a.errors)
+                    .map(|(name, count)| (name.to_string(), *count))
+                    .collect();
+                ManifestRun {
+                    run: kept.run,
+                    file: kept.file.clone(),
+                    started: timestamp(kept.started),
+                    finished: timestamp(kept.finished),
+                    warmup: kept.run <= args.warmup,
+                    lossy: analysis.is_some_and(|a| a.lossy),
+                    flagged: flagged.contains(&kept.run),
+                    results_us,
+                    points,
+                    errors,
+                }
+            })
+            .collect();
+        let manifest = Manifest {
+            label: &args.label,
+            bundle: &args.bundle_name,
+            homepage: self.target.map_or(&args.homepage, |t| &t.homepage),
+            device: self.device,
+            target: self.target.map(|t| t.name.as_str()),
+            runs,
+        };
+        let path = self.dir.join(format!("{}manifest.json", self.prefix));
+        let json = serde_json::to_string_pretty(&manifest)?;
+        std::fs::write(&path, json).with_context(|| format!("Could not write {:?}", path))
+    }
+}
@@ -0,0 +1,122 @@
+//! Latency between two instant markers, either once per run or every time they occur
+use std::collections::{HashMap, VecDeque};
+use time::Duration;
+
+use crate::trace::{Trace, difference_of_traces};
+
+#[derive(Debug, Clone)]
+/// Pairs every start marker with the next end marker, optionally only if their id argument is equal
+pub(crate) struct LatencyFilter {
+    /// Name of the filter in the output
+    pub(crate) name: String,
+    /// Name of the start marker
+    pub(crate) start: String,
+    /// Name of the end marker
+    pub(crate) end: String,
+    /// Argument whose value has to be equal in start and end
+    pub(crate) id: Option<String>,
+}
+
+/// Parse a latency filter of the form `name=Start..End` or `name=Start..End:id`
+pub(crate) fn parse_latency_filter(s: &str) -> Result<LatencyFilter, String> {
+    let (name, markers) = s
+        .split_once('=')
+        .ok_or(format!("Latency filter {} is missing a `=`", s))?;
+    let (markers, id) = match markers.split_once(':') {
+        Some((markers, id)) => (markers, Some(id.to_owned())),
+        None => (markers, None),
+    };
+    let (start, end) = markers
+        .split_once("..")
+        .ok_or(format!("Latency filter {} is missing a `..`", s))?;
+    Ok(LatencyFilter {
+        name: name.to_owned(),
+        start: start.to_owned(),
+        end: end.to_owned(),
+        id,
+    })
+}
+
+/// Find the latencies of all pairs and the number of starts that had no end.
+/// An end marker closes the oldest open start, ends without an open start are ignored.
+pub(crate) fn find_latencies(traces: &[Trace], filter: &LatencyFilter) -> (Vec<Duration>, u32) {
+    let mut open: HashMap<Option<&str>, VecDeque<&Trace>> = HashMap::new();
+    let mut latencies = Vec::new();
+    for trace in traces {
+        let id = filter.id.as_ref().and_then(|id| trace.argument(id));
+        if trace.is_marker(&filter.start) {
+            open.entry(id).or_default().push_back(trace);
+        } else if trace.is_marker(&filter.end)
+            && let Some(start) = open.get_mut(&id).and_then(|q| q.pop_front())
+        {
+            latencies.push(difference_of_traces(trace, start));
+        }
+    }
+    let unpaired = open.values().map(|q| q.len() as u32).sum();
+    (latencies, unpaired)
+}
+
+/// The time from the first start marker to the first end marker after it in the same process,
+/// None if there is no such pair. The id of the filter is respected like for latencies.
+pub(crate) fn find_interval(traces: &[Trace], filter: &LatencyFilter) -> Option<Duration> {
+    let start = traces.iter().find(|t| t.is_marker(&filter.start))?;
+    let id = filter.id.as_ref().and_then(|id| start.argument(id));
+    traces
+        .iter()
+        .skip_while(|t| !std::ptr::eq(*t, start))
+        .skip(1)
+        .find(|t| {
+            t.pid == start.pid
+                && t.is_marker(&filter.end)
+                && id == filter.id.as_ref().and_then(|id| t.argument(id))
+        })
+        .map(|end| difference_of_traces(end, start))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::{TimeStamp, TraceMarker};
+
+    fn marker(function: &str, pid: u64, seconds: u64, micro: u64) -> Trace {
+        Trace {
+            name: String::from("org.servo.servo"),
+            pid,
+            tid: pid,
+            cpu: 0,
+            timestamp: TimeStamp { seconds, micro },
+            trace_marker: TraceMarker::Dot,
+            number: String::from("1"),
+            shorthand: String::from("H"),
+            function: function.to_owned(),
+        }
+    }
+
+    fn filter() -> LatencyFilter {
+        parse_latency_filter("fcp=navigationStart..firstContentfulPaint").unwrap()
+    }
+
+    #[test]
+    fn interval_borrows_from_the_seconds() {
+        let traces = vec![
+            marker(" firstContentfulPaint", 1, 9, 0),
+            marker(" navigationStart", 1, 10, 900_000),
+            marker(" firstContentfulPaint", 2, 11, 0),
+            marker(" firstContentfulPaint", 1, 11, 200_000),
+            marker(" firstContentfulPaint", 1, 12, 0),
+        ];
+        assert_eq!(
+            find_interval(&traces, &filter()),
+            Some(Duration::milliseconds(300))
+        );
+    }
+
+    #[test]
+    fn interval_without_end_after_start() {
+        let traces = vec![
+            marker(" firstContentfulPaint", 1, 9, 0),
+            marker(" navigationStart", 1, 10, 0),
+        ];
+        assert_eq!(find_interval(&traces, &filter()), None);
+    }
+}
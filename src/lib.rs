@@ -0,0 +1,41 @@
+//! Parsing hitrace dumps, matching the traces into spans and statistics over the results
+//!
+//! ```
+//! use hitrace_bench::{matcher::Matcher, parse_traces, span::{SpanIndex, find_all_spans}, utils::avg_min_max};
+//!
+//! let dump = " org.servo.servo-44962   (  44682) [010] .... 17864.716645: tracing_mark_write: B|44682|H: Layout
+//!  org.servo.servo-44962   (  44682) [010] .... 17864.716945: tracing_mark_write: E|44682|H:
+//!  org.servo.servo-44962   (  44682) [010] .... 17864.717000: tracing_mark_write: B|44682|H: Layout
+//!  org.servo.servo-44962   (  44682) [010] .... 17864.717100: tracing_mark_write: E|44682|H:
+//! ";
+//! let traces = parse_traces(dump.as_bytes())?;
+//! let (spans, integrity) = find_all_spans(&traces);
+//! assert_eq!(integrity.unterminated_starts(), 0);
+//! let layout = SpanIndex::new(&spans)
+//!     .matching(&Matcher::Exact(String::from("Layout")))
+//!     .iter()
+//!     .map(|s| s.duration())
+//!     .collect::<Vec<_>>();
+//! let stats = avg_min_max(&layout)?;
+//! assert_eq!(stats.avg, time::Duration::microseconds(200));
+//! assert_eq!(stats.max, time::Duration::microseconds(300));
+//! # Ok::<(), hitrace_bench::BenchError>(())
+//! ```
+//!
+//! The types used most are re-exported at the top level, the modules have the rest.
+#![warn(missing_docs)]
+
+pub mod error;
+pub mod matcher;
+pub mod parse;
+pub mod predicate;
+pub mod sched;
+pub mod span;
+pub mod trace;
+pub mod utils;
+
+pub use error::BenchError;
+pub use parse::{ParseReport, parse_report, parse_traces};
+pub use span::{OwnedSpan, Span, SpanIntegrity, find_all_spans};
+pub use trace::{TimeStamp, Trace, TraceMarker};
+pub use utils::{AvgMinMax, avg_min_max};
@@ -1,77 +1,1420 @@
 use anyhow::{Context, Result, anyhow};
 use args::Args;
-use clap::Parser;
+use backend::Device;
+use baseline::Comparison;
+use bins::Bin;
+use breakdown::Breakdown;
+use budget::BudgetCheck;
+use clusters::Cluster;
+use correlation::RunMetrics;
 use filter::Filter;
+use functions::Profile;
+use hitrace_bench::{error, matcher, parse, predicate, sched, span, trace, utils};
+use idle::IdleGaps;
+use rayon::prelude::*;
 use rust_decimal::Decimal;
 use serde::Serialize;
-use std::{collections::HashMap, fs::File, io::BufWriter};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+use threads::ThreadBreakdown;
 use time::Duration;
-use trace::Trace;
+use trace::{TimeStamp, Trace};
+use utils::{Average, avg_min_max, histogram, percentile};
 use yansi::{Condition, Paint};
 
+mod analysis;
+mod anomaly;
 mod args;
+mod backend;
+mod baseline;
+mod bins;
+mod breakdown;
+mod budget;
+mod clusters;
+mod compare;
+mod config;
+mod correlation;
+mod counters;
 mod device;
+mod export;
 mod filter;
-mod trace;
-
-struct AvgMingMax {
-    avg: Duration,
-    min: Duration,
-    max: Duration,
-    number: usize,
-}
-
-fn avg_min_max(durations: &[Duration]) -> Option<AvgMingMax> {
-    let number = durations.len();
-    durations
-        .iter()
-        .min()
-        .zip(durations.iter().max())
-        .map(|(min, max)| AvgMingMax {
-            avg: durations.iter().sum::<Duration>() / number as f64,
-            min: *min,
-            max: *max,
-            number,
-        })
-}
+mod flamegraph;
+mod frames;
+mod functions;
+mod html;
+mod idle;
+mod json;
+mod keep;
+mod latency;
+mod matrix;
+mod memory;
+mod monitor;
+mod nesting;
+mod outliers;
+mod payload;
+mod phases;
+mod ranking;
+mod report;
+mod samples;
+mod score;
+mod significance;
+mod spill;
+mod store;
+mod threads;
+mod trend;
 
 /// Print the differences
-fn print_differences(args: &Args, results: RunResults, errors: HashMap<&str, u32>) {
+fn print_differences(args: &Args, results: &RunResults, errors: &FilterErrors) {
     println!("The following things broke with errors");
     for (key, val) in errors.iter() {
         println!("{}: {} errors", key, val);
     }
 
     println!(
-        "----name {} {} {}------({}) runs (hp:{})------------------------",
+        "----name {} {} {} median p90 p95 p99 ±stddev------({}) runs (hp:{})------------------------",
         "avg".yellow(),
         "min".green(),
         "max".red(),
-        args.tries,
+        args.runs() - args.warmup,
         args.homepage
     );
     for (key, val) in results.iter() {
-        if let Some(avg_min_max) = avg_min_max(val) {
+        if let Ok(avg_min_max) = avg_min_max(val) {
             println!(
-                "{}: {} {} {}  ({} runs)",
+                "{}: {} {} {} {} {} {} {} ±{}  ({} runs)",
                 key,
                 avg_min_max.avg.yellow().whenever(Condition::TTY_AND_COLOR),
                 avg_min_max.min.green().whenever(Condition::TTY_AND_COLOR),
                 avg_min_max.max.red().whenever(Condition::TTY_AND_COLOR),
+                avg_min_max.median,
+                avg_min_max.p90,
+                avg_min_max.p95,
+                avg_min_max.p99,
+                avg_min_max.std_dev,
                 avg_min_max.number,
             );
+            print_percentiles(args, val);
+            print_histogram(args, val);
+        } else {
+            println!("{}: _ _ _ _ _ _ _ _  (0 runs)", key);
+        }
+    }
+}
+
+/// Print the histogram of the values if asked for, one bar per bucket
+fn print_histogram<T: Average + std::fmt::Display>(args: &Args, values: &[T]) {
+    const WIDTH: usize = 40;
+    if !args.histogram {
+        return;
+    }
+    let buckets = histogram(values, args.histogram_buckets as usize, args.histogram_log);
+    let most = buckets.iter().map(|b| b.count).max().unwrap_or(0).max(1);
+    for bucket in buckets {
+        println!(
+            "    {} - {}: {} {}",
+            bucket.low,
+            bucket.high,
+            "#".repeat(bucket.count * WIDTH / most),
+            bucket.count
+        );
+    }
+}
+
+/// Print the percentiles given by `--percentile`
+fn print_percentiles<T: Copy + Ord + std::fmt::Display>(args: &Args, values: &[T]) {
+    let percentiles = args
+        .percentiles
+        .iter()
+        .filter_map(|p| percentile(values, *p).map(|v| format!("p{} {}", p, v)))
+        .collect::<Vec<_>>();
+    if !percentiles.is_empty() {
+        println!("    {}", percentiles.join(" "));
+    }
+}
+
+/// Print the values of the point metrics
+fn print_points(args: &Args, points: &PointResults) {
+    for (key, val) in points.iter() {
+        if let Ok(avg_min_max) = avg_min_max(val) {
+            println!(
+                "{}: {} {} {} {} {} {} {} ±{}  ({} runs)",
+                key,
+                avg_min_max.avg.yellow().whenever(Condition::TTY_AND_COLOR),
+                avg_min_max.min.green().whenever(Condition::TTY_AND_COLOR),
+                avg_min_max.max.red().whenever(Condition::TTY_AND_COLOR),
+                avg_min_max.median,
+                avg_min_max.p90,
+                avg_min_max.p95,
+                avg_min_max.p99,
+                avg_min_max.std_dev,
+                avg_min_max.number,
+            );
+            print_percentiles(args, val);
+            print_histogram(args, val);
+        } else {
+            println!("{}: _ _ _ _ _ _ _ _  (0 runs)", key);
+        }
+    }
+}
+
+/// Print the statistics over all values of every counter and the average of the last value of every run
+fn print_counters(names: &[String], runs: &[CounterResults]) {
+    println!("----counters (avg min max last)------------------------");
+    for name in names {
+        let series = runs
+            .iter()
+            .map(|run| run.get(name).map_or(&[][..], |s| s.as_slice()))
+            .collect::<Vec<_>>();
+        let values = series
+            .iter()
+            .flat_map(|s| s.iter().map(|(_ts, value)| *value))
+            .collect::<Vec<_>>();
+        let lasts = series
+            .iter()
+            .filter_map(|s| s.last().map(|(_ts, value)| *value))
+            .collect::<Vec<_>>();
+        match avg_min_max(&values).ok().zip(avg_min_max(&lasts).ok()) {
+            Some((stats, last)) => println!(
+                "{}: {} {} {} {}  ({} samples)",
+                name,
+                stats.avg.yellow().whenever(Condition::TTY_AND_COLOR),
+                stats.min.green().whenever(Condition::TTY_AND_COLOR),
+                stats.max.red().whenever(Condition::TTY_AND_COLOR),
+                last.avg,
+                stats.number
+            ),
+            None => println!("{}: _ _ _ _  (0 samples)", name),
+        }
+    }
+}
+
+/// Print every run's phases, flagged runs and the average of every phase stacked over the phases before
+fn print_phases(milestones: &[String], runs: &[Result<Vec<Duration>>]) {
+    let names = phases::segment_names(milestones);
+    println!("----phases------------------------");
+    for (run, phases) in runs.iter().enumerate() {
+        match phases {
+            Ok(phases) => println!(
+                "run {}: {}",
+                run + 1,
+                names
+                    .iter()
+                    .zip(phases)
+                    .map(|(name, dur)| format!("{} {}", name, dur))
+                    .collect::<Vec<_>>()
+                    .join(" | ")
+            ),
+            Err(e) => println!("run {}: {}", run + 1, e.red()),
+        }
+    }
+    let complete = runs.iter().flatten().collect::<Vec<_>>();
+    if complete.is_empty() {
+        return;
+    }
+    let mut stacked = Duration::ZERO;
+    for (index, name) in names.iter().enumerate() {
+        let phase = complete.iter().map(|p| p[index]).sum::<Duration>() / complete.len() as f64;
+        stacked += phase;
+        println!(
+            "{}: {} (ends at {})  ({} runs)",
+            name,
+            phase,
+            stacked,
+            complete.len()
+        );
+    }
+}
+
+/// Print every run's phases in computer format
+fn print_phases_computer(milestones: &[String], runs: &[Result<Vec<Duration>>]) {
+    for (index, name) in phases::segment_names(milestones).iter().enumerate() {
+        print!("{name}: ");
+        for phases in runs.iter().flatten() {
+            print!(
+                "{}.{}, ",
+                phases[index].whole_seconds(),
+                phases[index].whole_microseconds()
+            );
+        }
+        println!();
+    }
+}
+
+/// Print the value of the metrics in every run, highlighting the runs far away from the median
+fn print_anomalies(metrics: &[String], runs: &[RunMetrics], threshold: f64) {
+    let anomalies = metrics
+        .iter()
+        .map(|m| (m, anomaly::find_anomalies(runs, m, threshold)))
+        .collect::<Vec<_>>();
+    let flagged = anomaly::flagged_runs(runs, metrics, threshold);
+    println!(
+        "----runs (flagged beyond {} MADs)------------------------",
+        threshold
+    );
+    for (run, values) in runs.iter().enumerate() {
+        let line = anomalies
+            .iter()
+            .map(|(metric, anomalies)| {
+                let value = values
+                    .get(*metric)
+                    .map_or(String::from("_"), |v| format!("{:.6}", v));
+                match anomalies.iter().find(|a| a.run == run) {
+                    Some(a) => format!(
+                        "{} {} (median {:.6}, {:.1} MADs)",
+                        metric,
+                        value.red().whenever(Condition::TTY_AND_COLOR),
+                        a.median,
+                        a.mads
+                    ),
+                    None => format!("{} {}", metric, value),
+                }
+            })
+            .collect::<Vec<_>>();
+        let flagged = flagged.contains(&run);
+        println!(
+            "run {}{}: {}",
+            run + 1,
+            if flagged { " (flagged)" } else { "" },
+            line.join(" | ")
+        );
+    }
+}
+
+/// Print the tail percentiles of the span and latency filters, which have one sample per span
+fn print_tails(args: &Args, results: &RunResults) {
+    let names = args
+        .spans
+        .iter()
+        .map(|s| &s.name)
+        .chain(args.latencies.iter().map(|l| &l.name));
+    for name in names {
+        let samples = results.get(name.as_str()).map_or(&[][..], |v| v.as_slice());
+        if samples.len() < args.tail_min_samples {
+            println!(
+                "{}: p99 _ p99.9 _  (insufficient samples (n={}))",
+                name,
+                samples.len()
+            );
+        } else if let Some((p99, p999)) = percentile(samples, 99.0).zip(percentile(samples, 99.9)) {
+            println!(
+                "{}: p99 {} p99.9 {}  ({} samples)",
+                name,
+                p99,
+                p999,
+                samples.len()
+            );
+        }
+    }
+}
+
+/// Print the idle gaps of the parent filters for every run
+fn print_idle_gaps(idle_gaps: &HashMap<&str, Vec<Option<IdleGaps>>>) {
+    for (key, runs) in idle_gaps.iter() {
+        println!("----idle gaps in {}------------------------", key);
+        for (run, gaps) in runs.iter().enumerate() {
+            match gaps {
+                Some(gaps) => println!(
+                    "run {}: idle {} ({:.1}% of {}), longest gap {}",
+                    run + 1,
+                    gaps.total,
+                    gaps.percentage(),
+                    gaps.parent,
+                    gaps.longest
+                ),
+                None => println!("run {}: no {} span", run + 1, key),
+            }
+        }
+    }
+}
+
+/// Print the budget table
+fn print_budgets(checks: &[BudgetCheck]) {
+    fn format(value: Option<f64>) -> String {
+        value.map_or(String::from("_"), |v| format!("{:.3}", v))
+    }
+
+    println!("----budgets (filter budget actual margin)------------------------");
+    for check in checks {
+        let verdict = if check.passed() {
+            "pass".green().whenever(Condition::TTY_AND_COLOR)
+        } else {
+            "fail".red().whenever(Condition::TTY_AND_COLOR)
+        };
+        println!(
+            "{} {}: {:.3} {} {} {}",
+            check.filter,
+            check.kind,
+            check.budget,
+            format(check.actual),
+            format(check.margin()),
+            verdict
+        );
+    }
+}
+
+/// Print the comparison against the baseline, filters that regressed beyond the threshold are marked
+fn print_comparison(comparisons: &[Comparison], threshold: Option<f64>) {
+    fn format(c: &Comparison, value: i64) -> String {
+        if c.point {
+            value.to_string()
         } else {
-            println!("{}: _ _ _  (0 runs)", key);
+            Duration::microseconds(value).to_string()
+        }
+    }
+
+    println!(
+        "----baseline (name mean: baseline current delta | median: baseline current delta)------------------------"
+    );
+    for c in comparisons {
+        match (c.baseline, c.current, c.percent()) {
+            (
+                Some((base_mean, base_median)),
+                Some((mean, median)),
+                Some((mean_pct, median_pct)),
+            ) => {
+                let line = format!(
+                    "{}: {} {} {} ({:+.1}%) | {} {} {} ({:+.1}%)",
+                    c.name,
+                    format(c, base_mean),
+                    format(c, mean),
+                    format(c, mean - base_mean),
+                    mean_pct,
+                    format(c, base_median),
+                    format(c, median),
+                    format(c, median - base_median),
+                    median_pct,
+                );
+                if threshold.is_some_and(|t| c.regressed(t)) {
+                    println!(
+                        "{} {}",
+                        line,
+                        "regressed".red().whenever(Condition::TTY_AND_COLOR)
+                    );
+                } else {
+                    println!("{}", line);
+                }
+            }
+            (None, Some(_), _) => println!("{}: added", c.name),
+            (Some(_), None, _) => println!("{}: removed", c.name),
+            _ => println!("{}: no values", c.name),
         }
     }
 }
 
+/// Print the number and total duration of the spans of every filter per run
+fn print_summaries(summaries: &HashMap<&str, Vec<RunFilterSummary>>) {
+    for (name, runs) in summaries.iter() {
+        let counts = runs.iter().map(|s| s.count).collect::<Vec<_>>();
+        let totals = runs.iter().map(|s| s.total).collect::<Vec<_>>();
+        if let (Ok(count), Ok(total)) = (avg_min_max(&counts), avg_min_max(&totals)) {
+            println!(
+                "{}: count: avg {} (min {}, max {}), total: avg {} (min {}, max {})",
+                name, count.avg, count.min, count.max, total.avg, total.min, total.max
+            );
+        }
+    }
+}
+
+/// Print the time of the child filters under their parent filter, averaged per run
+fn print_nested(nested: &HashMap<&str, (Duration, HashMap<&str, Duration>)>, runs: usize) {
+    println!("----nested spans (per run, share of parent)------------------------");
+    for (parent, (parent_total, children)) in nested.iter() {
+        println!("{}: {}", parent, *parent_total / runs as f64);
+        let mut children = children.iter().collect::<Vec<_>>();
+        children.sort_by_key(|(_name, total)| Reverse(**total));
+        for (child, total) in children {
+            let share = if parent_total.is_zero() {
+                0.0
+            } else {
+                *total / *parent_total * 100.0
+            };
+            println!("  {}: {} ({:.1}%)", child, *total / runs as f64, share);
+        }
+    }
+}
+
+/// Print the share of every cluster in the time of the span filters
+fn print_clusters(clusters: &[Cluster], cluster_time: &HashMap<&str, HashMap<String, Duration>>) {
+    println!(
+        "----clusters ({})------------------------",
+        clusters
+            .iter()
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>()
+            .join(" ")
+    );
+    for (key, time) in cluster_time.iter() {
+        let total = time.values().sum::<Duration>();
+        let shares = clusters
+            .iter()
+            .map(|c| c.name.as_str())
+            .chain(["unknown"])
+            .filter_map(|name| time.get(name).map(|dur| (name, dur)))
+            .map(|(name, dur)| format!("{} {:.1}%", name, *dur / total * 100.0))
+            .collect::<Vec<_>>();
+        println!("{}: {}", key, shares.join(" | "));
+    }
+}
+
+/// Print the maximum and average recursion depth of the span filters, each averaged over the runs
+fn print_depths(depths: &HashMap<&str, Vec<(usize, f64)>>) {
+    for (key, runs) in depths.iter() {
+        let max = runs.iter().map(|(max, _avg)| *max).max().unwrap_or(0);
+        let avg_max = runs.iter().map(|(max, _avg)| *max as f64).sum::<f64>() / runs.len() as f64;
+        let avg = runs.iter().map(|(_max, avg)| avg).sum::<f64>() / runs.len() as f64;
+        println!(
+            "{} depth: max {:.2} (highest {}), avg {:.2}  ({} runs)",
+            key,
+            avg_max,
+            max,
+            avg,
+            runs.len()
+        );
+    }
+}
+
+/// Print the breakdown of the filter window
+fn print_breakdown(name: &str, breakdowns: &[Breakdown]) {
+    println!(
+        "----breakdown of {} ({} runs)------------------------",
+        name,
+        breakdowns.len()
+    );
+    for (span, avg, percentage) in breakdown::summarize(breakdowns) {
+        println!("{}: {} ({:.1}%)", span, avg, percentage);
+    }
+}
+
+/// Print the split of the span filters over the threads
+fn print_threads(args: &Args, threads: &HashMap<&str, Vec<ThreadBreakdown>>) {
+    for (key, runs) in threads.iter() {
+        println!("----threads of {}------------------------", key);
+        for thread in threads::summarize(runs, args.top_threads) {
+            println!(
+                "{}: {:.1} spans, total {}, mean {}",
+                thread.name, thread.count, thread.total, thread.mean
+            );
+        }
+    }
+}
+
+/// Print the functions with the highest self time
+fn print_functions(runs: &[Profile], top: usize) {
+    println!(
+        "----functions by self time (avg per run: calls, total, mean, self, share of run)------------------------"
+    );
+    for f in functions::top_functions(runs, top) {
+        println!(
+            "{}: {:.1} calls, total {}, mean {}, self {}, {:.1}% of run",
+            f.name, f.calls, f.total, f.mean, f.self_time, f.share
+        );
+    }
+}
+
+/// Print the full split of the span filters over the threads for every run in computer format
+fn print_threads_computer(threads: &HashMap<&str, Vec<ThreadBreakdown>>) {
+    for (key, runs) in threads.iter() {
+        for (run, breakdown) in runs.iter().enumerate() {
+            for ((pid, name), share) in breakdown.iter() {
+                println!(
+                    "{key} [{pid} {name}] run {}: {} spans, {}.{}",
+                    run + 1,
+                    share.count,
+                    share.total.whole_seconds(),
+                    share.total.whole_microseconds()
+                );
+            }
+        }
+    }
+}
+
+/// Print the averaged time bins of the span filters as sparklines
+fn print_bins(args: &Args, bins: &HashMap<&str, Vec<Vec<Bin>>>) {
+    for (key, runs) in bins.iter() {
+        let averaged = bins::average_bins(runs);
+        let counts = averaged.iter().map(|(count, _)| *count).collect::<Vec<_>>();
+        let totals = averaged
+            .iter()
+            .map(|(_, total)| total.as_seconds_f64())
+            .collect::<Vec<_>>();
+        println!(
+            "----bins of {} ({}ms, {} bins)------------------------",
+            key,
+            args.bin_width,
+            averaged.len()
+        );
+        println!("count: {}", bins::sparkline(&counts));
+        println!("total: {}", bins::sparkline(&totals));
+    }
+}
+
+/// Print the time bins of the span filters for every run in computer format
+fn print_bins_computer(bins: &HashMap<&str, Vec<Vec<Bin>>>) {
+    for (key, runs) in bins.iter() {
+        for (run, run_bins) in runs.iter().enumerate() {
+            print!("{key} bins run {}: ", run + 1);
+            for bin in run_bins {
+                print!(
+                    "{} {}.{}, ",
+                    bin.count,
+                    bin.total.whole_seconds(),
+                    bin.total.whole_microseconds()
+                );
+            }
+            println!();
+        }
+    }
+}
+
+/// Print the correlation matrix of the metrics, each cell gives pearson/spearman
+fn print_correlations(metrics: &[String], runs: &[RunMetrics]) {
+    fn format(value: Option<f64>) -> String {
+        value.map_or(String::from("_"), |v| format!("{:.2}", v))
+    }
+
+    println!("----correlation (pearson/spearman)------------------------");
+    println!("{}", metrics.join(" | "));
+    let mut skips = Vec::new();
+    for a in metrics {
+        let row = metrics
+            .iter()
+            .map(|b| {
+                let correlation = correlation::correlate(runs, a, b);
+                if correlation.skipped > 0 && a < b {
+                    skips.push(format!(
+                        "{} ~ {}: {} runs, {} skipped",
+                        a, b, correlation.samples, correlation.skipped
+                    ));
+                }
+                format!(
+                    "{}/{}",
+                    format(correlation.pearson),
+                    format(correlation.spearman)
+                )
+            })
+            .collect::<Vec<_>>();
+        println!("{}: {}", a, row.join(" | "));
+    }
+    for skip in skips {
+        println!("{}", skip);
+    }
+}
+
+/// Print how the numbers of the span argument filters correlate with the durations of their spans
+fn print_span_args(args: &Args, span_args: &HashMap<&str, Vec<(i64, Duration)>>) {
+    fn format(value: Option<f64>) -> String {
+        value.map_or(String::from("_"), |v| format!("{:.2}", v))
+    }
+
+    println!(
+        "----span arguments (name: spans, pearson/spearman with the duration)------------------------"
+    );
+    for filter in &args.span_args {
+        let (values, durations): (Vec<f64>, Vec<f64>) = span_args
+            .get(filter.name.as_str())
+            .into_iter()
+            .flatten()
+            .map(|(value, duration)| (*value as f64, duration.as_seconds_f64()))
+            .unzip();
+        let correlation = correlation::correlate_values(&values, &durations, 0);
+        println!(
+            "{}: {} spans, {}/{}",
+            filter.name,
+            correlation.samples,
+            format(correlation.pearson),
+            format(correlation.spearman)
+        );
+    }
+}
+
 /// The results of a run given by filter.name, Vec<duration>
 /// Notice that not all vectors will have the same length as some runs might fail.
-type RunResults<'a> = HashMap<&'a str, Vec<Duration>>;
+type RunResults = HashMap<String, Vec<Duration>>;
+
+/// The number of errors of every filter given by name, i.e., failed windows or unterminated spans
+type FilterErrors<'a> = HashMap<&'a str, u32>;
+
+/// The traces of a run, the memory samples taken while it ran and the trace file
+type Capture = (parse::ParseReport, Vec<memory::MemorySample>, PathBuf);
+
+/// Trace one run on the device and retry up to `--retries` times with backoff if it failed, timed out or
+/// matched no filter. Returns the capture, None if every attempt failed, and the number of retries.
+/// Failed attempts count as errors.
+fn capture_run(
+    args: &Args,
+    device: Device,
+    target: Option<&matrix::Target>,
+    filters: &[Filter],
+    errors: &mut FilterErrors,
+) -> Result<(Option<Capture>, u32)> {
+    for attempt in 0..=args.retries {
+        if attempt > 0 {
+            // give a flaky device or connection more time with every attempt
+            let backoff = args
+                .retry_backoff
+                .saturating_mul(1 << (attempt - 1).min(16));
+            std::thread::sleep(std::time::Duration::from_secs(backoff));
+        }
+        let (report, memory, log_path) = match device::capture_trace(args, device, target) {
+            Ok((log_path, memory)) => match device::read_file(args, &log_path) {
+                Ok(report) => (report, memory, log_path),
+                Err(e) => {
+                    // a broken trace only loses this attempt, the session goes on
+                    eprintln!(
+                        "Could not read the trace of attempt {}: {:#}",
+                        attempt + 1,
+                        e
+                    );
+                    *errors.entry("unreadable trace").or_default() += 1;
+                    continue;
+                }
+            },
+            Err(e) if e.downcast_ref::<device::TimedOut>().is_some() => {
+                // the trace may still be running on the device and the app may hang
+                device::stop_tracing(args.trace_buffer, device).ok();
+                device::force_stop(&args.bundle_name, device).ok();
+                *errors.entry("timeout").or_default() += 1;
+                continue;
+            }
+            Err(e) => {
+                // i.e., the app crashed or the connection to the device broke
+                *errors.entry("failed run").or_default() += 1;
+                if !args.quiet() {
+                    println!("Attempt {} failed: {:#}", attempt + 1, e);
+                }
+                continue;
+            }
+        };
+        let matched_filter = filters
+            .iter()
+            .any(|f| filter::filter_window(&report.traces, f).is_ok());
+        let matched_span = report.traces.iter().any(|t| {
+            matches!(
+                t.trace_marker,
+                trace::TraceMarker::StartSync | trace::TraceMarker::StartAsync
+            ) && args
+                .spans
+                .iter()
+                .any(|s| s.matcher.matches(t.function.trim()))
+        });
+        if matched_filter || matched_span {
+            return Ok((Some((report, memory, log_path)), attempt));
+        }
+        *errors.entry("empty run").or_default() += 1;
+    }
+    Ok((None, args.retries))
+}
+
+/// The runs that needed retries or failed as (run, retries, succeeded)
+type Retries = Vec<(usize, u32, bool)>;
+
+/// Trace all runs on the device, with the target instead of the homepage, and analyze them on the pool
+fn capture_device<'a>(
+    analysis: &analysis::Analysis<'a>,
+    device: Device,
+    target: Option<&matrix::Target>,
+    pool: &rayon::ThreadPool,
+    errors: &mut FilterErrors<'a>,
+) -> Result<(Vec<analysis::RunAnalysis<'a>>, Retries)> {
+    let args = analysis.args;
+    let mut retries = Retries::new();
+    let mut reports = Vec::new();
+    let mut keeper = args
+        .keep_traces
+        .as_deref()
+        .map(|dir| keep::TraceKeeper::new(dir, device.serial, target))
+        .transpose()?;
+    for i in 1..args.runs() + 1 {
+        if !args.quiet() {
+            match device.serial {
+                Some(serial) => println!("Running test {} on {}", i, serial),
+                None => println!("Running test {}", i),
+            }
+        }
+        if let Err(e) = device::run_hooks(&args.pre_run_cmds, device) {
+            *errors.entry("pre-run-cmd").or_default() += 1;
+            if !args.quiet() {
+                println!("run {}: {:#}", i, e);
+            }
+            if args.abort_on_hook_failure {
+                continue;
+            }
+        }
+        let started = time::OffsetDateTime::now_utc();
+        let (mut report, retried) = capture_run(args, device, target, analysis.filters, errors)?;
+        if let Err(e) = device::run_hooks(&args.post_run_cmds, device) {
+            *errors.entry("post-run-cmd").or_default() += 1;
+            if !args.quiet() {
+                println!("run {}: {:#}", i, e);
+            }
+            if args.abort_on_hook_failure {
+                report = None;
+            }
+        }
+        if retried > 0 || report.is_none() {
+            retries.push((i, retried, report.is_some()));
+        }
+        if let Some((report, memory, log_path)) = report {
+            // the next run overwrites the trace file
+            let kept = keeper
+                .as_mut()
+                .map(|k| k.keep(i, &log_path, started))
+                .transpose()?;
+            reports.push((i, report, memory, kept));
+        }
+    }
+    let runs = pool.install(|| {
+        reports
+            .par_iter()
+            .map(|(i, report, memory, kept)| {
+                let mut run = analysis.analyze(*i, report);
+                if args.memory_interval.is_some() {
+                    memory::add_points(&mut run.points, &mut run.errors, memory);
+                }
+                run.trace = kept.clone();
+                run
+            })
+            .collect::<Vec<_>>()
+    });
+    if let Some(keeper) = &mut keeper {
+        let measured = runs
+            .iter()
+            .filter(|r| r.run > args.warmup)
+            .collect::<Vec<_>>();
+        let metrics = measured
+            .iter()
+            .map(|r| r.metrics.clone())
+            .collect::<Vec<_>>();
+        let flagged = args.mad_threshold.map_or_else(BTreeSet::new, |threshold| {
+            anomaly::flagged_runs(
+                &metrics,
+                &anomaly::considered_metrics(args, analysis.filters),
+                threshold,
+            )
+            .into_iter()
+            .map(|i| measured[i].run)
+            .collect()
+        });
+        if args.keep_policy == keep::KeepPolicy::Flagged {
+            keeper.retain_flagged(&flagged)?;
+        }
+        keeper.write_manifest(args, &runs, &flagged)?;
+    }
+    Ok((runs, retries))
+}
+
+/// Add the runs to the session, the warmup runs go to the returned warmup session
+fn split_warmup<'a>(
+    args: &'a Args,
+    session: &mut analysis::Session<'a>,
+    runs: Vec<analysis::RunAnalysis<'a>>,
+) -> analysis::Session<'a> {
+    let mut warmup = analysis::Session::new(args);
+    for run in runs {
+        if run.run <= args.warmup {
+            warmup.add(run);
+        } else {
+            session.add(run);
+        }
+    }
+    warmup
+}
+
+/// Run the benchmark on every device of `--all-devices`, print the results of each and compare them
+fn run_all_devices(
+    analysis: &analysis::Analysis,
+    devices: &[String],
+    baselines: Option<&baseline::Baselines>,
+    budgets: Option<&budget::Budgets>,
+    anomaly_metrics: &[String],
+    pool: &rayon::ThreadPool,
+) -> Result<()> {
+    let args = analysis.args;
+    let capture = |serial: &str| -> Result<GroupSession> {
+        let mut session = analysis::Session::new(args);
+        let device = Device::new(args.backend, Some(serial));
+        let (runs, retries) = capture_device(analysis, device, None, pool, &mut session.errors)?;
+        if !args.quiet() {
+            print_retries(&retries);
+        }
+        let heading = format!("device {}", serial);
+        Ok(GroupSession::new(args, serial, heading, session, runs))
+    };
+    let groups = if args.parallel_devices {
+        std::thread::scope(|s| {
+            let handles = devices
+                .iter()
+                .map(|device| s.spawn(|| capture(device)))
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|h| h.join().expect("A device thread panicked"))
+                .collect::<Result<Vec<_>>>()
+        })?
+    } else {
+        devices
+            .iter()
+            .map(|device| capture(device))
+            .collect::<Result<Vec<_>>>()?
+    };
+    let keys = devices
+        .iter()
+        .map(|serial| store::SessionKey {
+            target: None,
+            homepage: &args.homepage,
+            device: Some(serial),
+        })
+        .collect::<Vec<_>>();
+    finish_groups(
+        analysis,
+        json::Group::Device,
+        &groups,
+        &keys,
+        baselines,
+        budgets,
+        anomaly_metrics,
+    )
+}
+
+/// The session of a target of `--target` or a device of `--all-devices`
+struct GroupSession<'a> {
+    /// The name of the target or the serial of the device
+    name: String,
+    /// The heading of its results
+    heading: String,
+    session: analysis::Session<'a>,
+    warmup: analysis::Session<'a>,
+    discarded: HashMap<String, usize>,
+}
+
+impl<'a> GroupSession<'a> {
+    /// Split the warmup runs off and reject the outliers
+    fn new(
+        args: &'a Args,
+        name: &str,
+        heading: String,
+        mut session: analysis::Session<'a>,
+        runs: Vec<analysis::RunAnalysis<'a>>,
+    ) -> Self {
+        let warmup = split_warmup(args, &mut session, runs);
+        let discarded =
+            outliers::reject_outliers(&mut session.results, args.outliers, args.trim_fraction);
+        GroupSession {
+            name: name.to_owned(),
+            heading,
+            session,
+            warmup,
+            discarded,
+        }
+    }
+
+    /// The document of `--json` of the target or device
+    fn json(&self, analysis: &analysis::Analysis, anomaly_metrics: &[String]) -> json::JsonResults {
+        let args = analysis.args;
+        let s = &self.session;
+        let mut json = json::JsonResults::new(
+            args,
+            &s.results,
+            &s.points,
+            &s.errors,
+            &s.run_samples,
+            &s.run_numbers,
+            &s.run_metrics,
+        );
+        json.traces(&s.run_traces);
+        json.score(self.score(args));
+        json.clusters(analysis.clusters, &s.cluster_time);
+        if let Some(threshold) = args.mad_threshold {
+            json.flagged(&anomaly::flagged_runs(
+                &s.run_metrics,
+                anomaly_metrics,
+                threshold,
+            ));
+        }
+        json.lossy_runs(&s.lossy_runs);
+        json.discarded(&self.discarded);
+        json.warmup(&self.warmup.results, &self.warmup.points);
+        json.bins(&s.bins);
+        json.counters(&s.counters);
+        if args.phases.len() > 1 {
+            json.phases(&args.phases, &s.phases);
+        }
+        json
+    }
+
+    /// The score of `--score`, None without components or if one has no results
+    fn score(&self, args: &Args) -> Option<f64> {
+        (!args.score.is_empty())
+            .then(|| score::score(&self.session.results, &args.score).ok())
+            .flatten()
+    }
+}
+
+/// Write the rendered report to `--output-file` or stdout
+fn write_report(args: &Args, rendered: &str) -> Result<()> {
+    match &args.output_file {
+        Some(output) if output.as_os_str() != "-" => std::fs::write(output, rendered)
+            .with_context(|| format!("Could not write {:?}", output)),
+        _ => {
+            print!("{}", rendered);
+            Ok(())
+        }
+    }
+}
+
+/// Print the sections of a session from the tails to the breakdown, the comparison only with a baseline
+fn print_details(
+    analysis: &analysis::Analysis,
+    s: &analysis::Session,
+    budget_checks: &[BudgetCheck],
+    anomaly_metrics: &[String],
+    comparisons: Option<&[Comparison]>,
+) {
+    let args = analysis.args;
+    if args.tail {
+        print_tails(args, &s.results);
+    }
+    print_summaries(&s.summaries);
+    print_depths(&s.depths);
+    if let Some(top) = args.functions {
+        print_functions(&s.functions, top);
+    }
+    if !s.nested.is_empty() {
+        print_nested(&s.nested, s.run_metrics.len());
+    }
+    if !analysis.clusters.is_empty() {
+        print_clusters(analysis.clusters, &s.cluster_time);
+    }
+    print_idle_gaps(&s.idle_gaps);
+    print_points(args, &s.points);
+    if !args.counters.is_empty() {
+        print_counters(&args.counters, &s.counters);
+    }
+    if args.phases.len() > 1 {
+        print_phases(&args.phases, &s.phases);
+    }
+    if !budget_checks.is_empty() {
+        print_budgets(budget_checks);
+    }
+    if let Some(comparisons) = comparisons {
+        print_comparison(comparisons, args.fail_threshold);
+    }
+    if let Some(threshold) = args.mad_threshold {
+        print_anomalies(anomaly_metrics, &s.run_metrics, threshold);
+    }
+    print_threads(args, &s.threads);
+    print_bins(args, &s.bins);
+    if !args.correlate.is_empty() {
+        print_correlations(&args.correlate, &s.run_metrics);
+    }
+    if !args.span_args.is_empty() {
+        print_span_args(args, &s.span_args);
+    }
+    if let Some(filter) = analysis.breakdown_filter {
+        if s.breakdowns.is_empty() {
+            println!(
+                "No run had a valid window for the breakdown of {}",
+                filter.name
+            );
+        } else {
+            print_breakdown(filter.name, &s.breakdowns);
+        }
+    }
+}
+
+/// The report of all targets or devices in one, every name is prefixed with the name of its target or device
+fn render_grouped(
+    args: &Args,
+    report: &dyn report::Report,
+    groups: &[GroupSession],
+    comparisons: &[Vec<Comparison>],
+) -> String {
+    let mut results = RunResults::new();
+    let mut points = PointResults::new();
+    let mut error_names = Vec::new();
+    let mut prefixed = Vec::new();
+    for (group, comparisons) in groups.iter().zip(comparisons) {
+        let name = |key: &str| format!("{}/{}", group.name, key);
+        let s = &group.session;
+        results.extend(s.results.iter().map(|(k, v)| (name(k), v.clone())));
+        points.extend(s.points.iter().map(|(k, v)| (name(k), v.clone())));
+        error_names.extend(s.errors.iter().map(|(k, count)| (name(k), *count)));
+        prefixed.extend(comparisons.iter().map(|c| Comparison {
+            name: name(&c.name),
+            point: c.point,
+            baseline: c.baseline,
+            current: c.current,
+        }));
+    }
+    let errors = error_names
+        .iter()
+        .map(|(name, count)| (name.as_str(), *count))
+        .collect::<FilterErrors>();
+    let mut data = report::ReportData::new(&results, &points, &errors);
+    data.baseline(&prefixed, args.fail_threshold);
+    report.render(&data)
+}
+
+/// Store, write and compare the sessions of the targets or devices against their baselines, then print
+/// them unless stdout is a document. Fails if a filter regressed against the baseline of its target or device
+fn finish_groups(
+    analysis: &analysis::Analysis,
+    group: json::Group,
+    groups: &[GroupSession],
+    keys: &[store::SessionKey],
+    baselines: Option<&baseline::Baselines>,
+    budgets: Option<&budget::Budgets>,
+    anomaly_metrics: &[String],
+) -> Result<()> {
+    let args = analysis.args;
+    if let Some(path) = &args.store {
+        for (group, key) in groups.iter().zip(keys) {
+            let s = &group.session;
+            store::store(path, args, key, &s.results, &s.points, group.score(args))?;
+        }
+    }
+    let comparisons = groups
+        .iter()
+        .map(|g| {
+            baselines
+                .and_then(|b| b.get(&g.name))
+                .map(|b| baseline::compare(b, &g.session.results, &g.session.points))
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>();
+    let regressions = args.fail_threshold.map_or(0, |threshold| {
+        comparisons
+            .iter()
+            .flatten()
+            .filter(|c| c.regressed(threshold))
+            .count()
+    });
+    let budget_checks = groups
+        .iter()
+        .map(|g| {
+            let s = &g.session;
+            let (checks, skipped) = budgets
+                .map(|b| budget::check_budgets(b, &s.results, &s.run_metrics, args.tail_min_samples))
+                .unwrap_or_default();
+            for skipped in &skipped {
+                eprintln!(
+                    "Warning: skipping the p99 budget of {} of {}, it has {} of the {} samples of --tail-min-samples",
+                    skipped.filter, g.name, skipped.samples, args.tail_min_samples
+                );
+            }
+            checks
+        })
+        .collect::<Vec<_>>();
+    let failed_budgets = budget_checks
+        .iter()
+        .flatten()
+        .filter(|c| !c.passed())
+        .count();
+    let lossy_runs = groups
+        .iter()
+        .map(|g| g.session.lossy_runs.len())
+        .sum::<usize>();
+    let ranking = args.rank.as_ref().map(|filter| {
+        let mut ranks = groups
+            .iter()
+            .zip(&comparisons)
+            .filter_map(|(g, comparisons)| {
+                let s = &g.session;
+                ranking::rank(&g.name, filter, &s.results, &s.points, comparisons)
+            })
+            .collect::<Vec<_>>();
+        ranking::sort_worst_first(&mut ranks);
+        (filter, ranks)
+    });
+    let json = args.writes_json().then(|| {
+        let documents = groups
+            .iter()
+            .map(|g| (g.name.clone(), g.json(analysis, anomaly_metrics)));
+        let mut json = json::GroupedJson::new(group, documents);
+        if let Some((filter, ranks)) = &ranking {
+            json.ranking(filter, ranks.clone());
+        }
+        json
+    });
+    if let (Some(path), Some(json)) = (&args.json, &json) {
+        json::write_json(path, json)?;
+    }
+
+    if args.json_to_stdout() {
+        // the json is the output
+    } else if let Some(format) = args.report {
+        match (format.report(), &json) {
+            (Some(report), _) => {
+                write_report(args, &render_grouped(args, &*report, groups, &comparisons))?
+            }
+            (None, Some(json)) => {
+                json::write_json(args.output_file.as_deref().unwrap_or(Path::new("-")), json)?
+            }
+            (None, None) => {}
+        }
+    } else {
+        for ((group, comparisons), budget_checks) in
+            groups.iter().zip(&comparisons).zip(&budget_checks)
+        {
+            println!("===={}====", group.heading);
+            let s = &group.session;
+            print_differences(args, &s.results, &s.errors);
+            if args.warmup > 0 {
+                print_warmup(&group.warmup.results, &group.warmup.points);
+            }
+            print_lossy_runs(&s.lossy_runs, args.allow_lossy);
+            if !group.discarded.is_empty() {
+                print_discarded(&group.discarded, &s.results);
+            }
+            if let Some(score) = group.score(args) {
+                println!("score: {:.3}", score);
+            }
+            if baselines.is_some() && comparisons.is_empty() {
+                println!("The baseline has no results of {}", group.name);
+            }
+            print_details(
+                analysis,
+                s,
+                budget_checks,
+                anomaly_metrics,
+                (!comparisons.is_empty()).then_some(comparisons.as_slice()),
+            );
+        }
+        let names = groups.iter().map(|g| g.name.clone()).collect::<Vec<_>>();
+        let results = groups
+            .iter()
+            .map(|g| &g.session.results)
+            .collect::<Vec<_>>();
+        let title = match group {
+            json::Group::Target => "targets",
+            json::Group::Device => "devices",
+        };
+        print_side_by_side(title, &names, &results);
+        if let Some((filter, ranks)) = &ranking {
+            ranking::print_ranking(title, filter, ranks, args.rank_top);
+        }
+    }
+    if args.fail_on_lost_events && lossy_runs > 0 {
+        return Err(anyhow!("{} runs lost trace events", lossy_runs));
+    }
+    if failed_budgets > 0 {
+        return Err(anyhow!("{} budgets were exceeded", failed_budgets));
+    }
+    if regressions > 0 {
+        return Err(anyhow!(
+            "{} filters regressed by more than {}% against the baseline",
+            regressions,
+            args.fail_threshold.unwrap_or_default()
+        ));
+    }
+    Ok(())
+}
+
+/// Run the full benchmark for every target of `--target`, print the results of each and compare them
+fn run_targets(
+    analysis: &analysis::Analysis,
+    baselines: Option<&baseline::Baselines>,
+    budgets: Option<&budget::Budgets>,
+    anomaly_metrics: &[String],
+    pool: &rayon::ThreadPool,
+) -> Result<()> {
+    let args = analysis.args;
+    let mut groups = Vec::new();
+    for target in &args.targets {
+        if !args.quiet() {
+            println!("Benchmarking target {} ({})", target.name, target.homepage);
+        }
+        let mut session = analysis::Session::new(args);
+        let (runs, retries) = capture_device(
+            analysis,
+            Device::new(args.backend, args.device.as_deref()),
+            Some(target),
+            pool,
+            &mut session.errors,
+        )?;
+        if !args.quiet() {
+            print_retries(&retries);
+        }
+        let heading = format!("target {} ({})", target.name, target.homepage);
+        groups.push(GroupSession::new(
+            args,
+            &target.name,
+            heading,
+            session,
+            runs,
+        ));
+    }
+    let keys = args
+        .targets
+        .iter()
+        .map(|t| store::SessionKey {
+            target: Some(&t.name),
+            homepage: &t.homepage,
+            device: args.device.as_deref(),
+        })
+        .collect::<Vec<_>>();
+    finish_groups(
+        analysis,
+        json::Group::Target,
+        &groups,
+        &keys,
+        baselines,
+        budgets,
+        anomaly_metrics,
+    )
+}
+
+/// Print the average of every filter in every column, i.e., device or target, side by side
+fn print_side_by_side(title: &str, columns: &[String], results: &[&RunResults]) {
+    println!(
+        "----{} ({} of name: {})------------------------",
+        title,
+        "avg".yellow(),
+        columns.join(" | ")
+    );
+    let names = results
+        .iter()
+        .flat_map(|r| r.keys())
+        .collect::<std::collections::BTreeSet<_>>();
+    for name in names {
+        let averages = results
+            .iter()
+            .map(|r| {
+                r.get(name)
+                    .and_then(|v| avg_min_max(v).ok())
+                    .map_or(String::from("_"), |s| s.avg.to_string())
+            })
+            .collect::<Vec<_>>();
+        println!("{}: {}", name, averages.join(" | "));
+    }
+}
+
+/// Read a trace file given with `--from-file`
+fn read_trace_file(args: &Args, path: &Path) -> Result<parse::ParseReport> {
+    if !args.quiet() {
+        println!("Reading {:?}", path);
+    }
+    let report = device::read_file(args, path)
+        .with_context(|| format!("Could not read trace file {:?}", path))?;
+    if report.traces.is_empty() {
+        return Err(anyhow!("{:?} does not contain any trace lines", path));
+    }
+    Ok(report)
+}
+
+/// Analyze a trace file given with `--from-file` while reading it
+fn stream_trace_file<'a>(
+    analysis: &analysis::Analysis<'a>,
+    run: usize,
+    path: &Path,
+) -> Result<analysis::RunAnalysis<'a>> {
+    if !analysis.args.quiet() {
+        println!("Streaming {:?}", path);
+    }
+    let reader = device::stream_file(analysis.args, path)
+        .with_context(|| format!("Could not read trace file {:?}", path))?;
+    let mut traces = 0;
+    let counted = reader.inspect(|event| {
+        if matches!(event, Ok(parse::Event::Trace(_))) {
+            traces += 1;
+        }
+    });
+    let run = analysis
+        .analyze_stream(run, counted)
+        .with_context(|| format!("Could not read trace file {:?}", path))?;
+    if traces == 0 {
+        return Err(anyhow!("{:?} does not contain any trace lines", path));
+    }
+    Ok(run)
+}
+
+/// Print the runs that needed retries or failed
+fn print_retries(retries: &[(usize, u32, bool)]) {
+    if retries.is_empty() {
+        return;
+    }
+    for (run, retried, succeeded) in retries {
+        if *succeeded {
+            println!("run {}: {} retries", run, retried);
+        } else {
+            println!("run {}: failed after {} retries", run, retried);
+        }
+    }
+    println!(
+        "{} retries in {} runs, {} runs failed",
+        retries
+            .iter()
+            .map(|(_run, retried, _s)| retried)
+            .sum::<u32>(),
+        retries.len(),
+        retries
+            .iter()
+            .filter(|(_run, _r, succeeded)| !succeeded)
+            .count()
+    );
+}
+
+/// Print the values of the warmup runs, they are not in the statistics
+fn print_warmup(results: &RunResults, points: &PointResults) {
+    println!("----warmup runs (not in the statistics)------------------------");
+    for (key, values) in results.iter().collect::<BTreeMap<_, _>>() {
+        let values = values.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+        println!("{}: {}", key, values.join(", "));
+    }
+    for (key, values) in points.iter().collect::<BTreeMap<_, _>>() {
+        let values = values.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+        println!("{}: {}", key, values.join(", "));
+    }
+}
+
+/// Print the number of durations discarded as outliers
+fn print_discarded(discarded: &HashMap<String, usize>, results: &RunResults) {
+    println!("----outliers discarded------------------------");
+    for (key, count) in discarded.iter().collect::<BTreeMap<_, _>>() {
+        let kept = results.get(key).map_or(0, |d| d.len());
+        println!("{}: {} of {}", key, count, count + kept);
+    }
+}
+
+/// Print the runs that lost trace events and whether they are in the results
+fn print_lossy_runs(runs: &[usize], allowed: bool) {
+    if runs.is_empty() {
+        return;
+    }
+    let runs = runs.iter().map(|r| r.to_string()).collect::<Vec<_>>();
+    if allowed {
+        println!(
+            "runs {} lost trace events, their results are included (lossy)",
+            runs.join(", ")
+        );
+    } else {
+        println!(
+            "runs {} lost trace events and are excluded, use --allow-lossy to include them",
+            runs.join(", ")
+        );
+    }
+}
+
+/// The values of every counter in one run with the time they were set
+type CounterResults = HashMap<String, Vec<(TimeStamp, i64)>>;
+
+#[derive(Debug, Clone, Copy)]
+/// Number and summed duration of the spans of a filter in one run
+struct RunFilterSummary {
+    count: i64,
+    total: Duration,
+}
+
+/// The values of the point metrics given by name, Vec<value>
+type PointResults = HashMap<String, Vec<i64>>;
 
 /// Print the differences in computer format
-fn print_computer(hash: RunResults) {
+fn print_computer(hash: RunResults, points: &PointResults) {
     for (key, items) in hash.iter() {
         print!("{key}: ");
         for i in items {
@@ -79,6 +1422,13 @@ fn print_computer(hash: RunResults) {
         }
         println!();
     }
+    for (key, items) in points.iter() {
+        print!("{key}: ");
+        for i in items {
+            print!("{}, ", i)
+        }
+        println!();
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -99,14 +1449,14 @@ fn difference_to_bencher_decimal(dur: &Duration) -> Decimal {
 }
 
 /// Output in bencher json format to bench.json
-fn write_bencher(result: RunResults) {
-    let b: HashMap<&str, HashMap<&str, Latency>> = result
+fn write_bencher(result: RunResults) -> Result<()> {
+    let b: HashMap<String, HashMap<&str, Latency>> = result
         .into_iter()
         .map(|(key, dur_vec)| {
             let avg_min_max = avg_min_max(&dur_vec);
             // yes we need this hashmap for the correct json
             let mut map = HashMap::new();
-            if let Some(avg_min_max) = avg_min_max {
+            if let Ok(avg_min_max) = avg_min_max {
                 map.insert(
                     "latency",
                     Latency {
@@ -119,10 +1469,10 @@ fn write_bencher(result: RunResults) {
             (key, map)
         })
         .collect();
-    let file = File::create("bench.json").expect("Could not create file");
+    let file = File::create("bench.json").context("Could not create bench.json")?;
     let writer = BufWriter::new(file);
-    print!("{:?}", b);
-    serde_json::to_writer_pretty(writer, &b).expect("Could not write json");
+    serde_json::to_writer_pretty(writer, &b).context("Could not write bench.json")?;
+    Ok(())
 }
 
 fn main() -> Result<()> {
@@ -144,52 +1494,472 @@ fn main() -> Result<()> {
         },
     ];
 
-    let args = Args::parse();
-
-    if !device::is_device_reachable().context("Testing reachability of device")? {
-        return Err(anyhow!("No phone seems to be reachable"));
+    let args = config::parse_args()?;
+    if let Some(args::Command::Compare {
+        old,
+        new,
+        confidence,
+        count_threshold,
+        format,
+        samples,
+        sample_limit,
+    }) = &args.command
+    {
+        return compare::compare_files(
+            old,
+            new,
+            *confidence,
+            *count_threshold,
+            *format,
+            samples.as_deref(),
+            *sample_limit,
+        );
+    }
+    if let Some(args::Command::History { store, name, last }) = &args.command {
+        return store::print_history(store, name, *last);
+    }
+    if let Some(args::Command::Trend {
+        store,
+        name,
+        last,
+        statistic,
+        label,
+        device,
+        target,
+        json,
+    }) = &args.command
+    {
+        let filter = store::SessionFilter {
+            label: label.as_deref(),
+            device: device.as_deref(),
+            target: target.as_deref(),
+        };
+        return trend::print_trend(store, name, *statistic, &filter, *last, *json);
+    }
+    if let Some(args::Command::Monitor { window, every }) = &args.command {
+        return monitor::monitor(&args, *window, *every);
+    }
+    let breakdown_filter = args
+        .breakdown
+        .as_ref()
+        .map(|name| {
+            filters
+                .iter()
+                .find(|f| f.name == name)
+                .ok_or(anyhow!("There is no filter named {}", name))
+        })
+        .transpose()?;
+    let is_span_filter = |name: &String| args.spans.iter().any(|s| s.name == *name);
+    if let Some((child, parent)) = args
+        .span_parents
+        .iter()
+        .find(|(child, parent)| !is_span_filter(child) || !is_span_filter(parent))
+    {
+        return Err(anyhow!(
+            "Span parents need span filters, but {} or {} is not one",
+            child,
+            parent
+        ));
+    }
+    if let Some(name) = args.bins.iter().find(|b| !is_span_filter(b)) {
+        return Err(anyhow!("Bins need a span filter, but {} is not one", name));
+    }
+    if let Some(name) = args.idle_gaps.iter().find(|b| !is_span_filter(b)) {
+        return Err(anyhow!(
+            "Idle gaps need a span filter, but {} is not one",
+            name
+        ));
+    }
+    if let Some(name) = args.split_processes.iter().find(|b| !is_span_filter(b)) {
+        return Err(anyhow!(
+            "Splitting by process needs a span filter, but {} is not one",
+            name
+        ));
+    }
+    if let Some(name) = args.start_offsets.iter().find(|b| !is_span_filter(b)) {
+        return Err(anyhow!(
+            "Start offsets need a span filter, but {} is not one",
+            name
+        ));
+    }
+    if args.rank.is_some() && args.targets.is_empty() && !args.all_devices {
+        return Err(anyhow!(
+            "--rank ranks the targets of --target or the devices of --all-devices"
+        ));
     }
+    let start_offset_names = args
+        .start_offsets
+        .iter()
+        .map(|name| (name.as_str(), format!("start@{}", name)))
+        .collect::<HashMap<_, _>>();
+    let baselines = args
+        .baseline
+        .as_ref()
+        .map(|path| baseline::read_baselines(path))
+        .transpose()?;
+    let budgets = args
+        .budgets
+        .as_ref()
+        .map(|path| budget::read_budgets(path))
+        .transpose()?;
+    if let Some(budgets) = &budgets {
+        for name in budgets.keys() {
+            let known = filters.iter().any(|f| f.name == name)
+                || is_span_filter(name)
+                || args
+                    .spans
+                    .iter()
+                    .any(|s| format!("{} (self)", s.name) == *name)
+                || args.latencies.iter().any(|l| l.name == *name)
+                || args.intervals.iter().any(|l| l.name == *name)
+                || start_offset_names.values().any(|n| n == name);
+            if !known {
+                eprintln!("Warning: the budgets name the unknown filter {}", name);
+            }
+        }
+    }
+    if args.phases.len() == 1 {
+        return Err(anyhow!("Phases need at least two milestones"));
+    }
+    if args.warmup >= args.runs() {
+        return Err(anyhow!(
+            "The {} warmup runs leave no run for the statistics",
+            args.warmup
+        ));
+    }
+    let bin_width = Duration::milliseconds(args.bin_width as i64);
+    let is_metric = |m: &String| {
+        [
+            "unmatched ends",
+            "unterminated starts",
+            "lost events",
+            "lost spans",
+        ]
+        .contains(&m.as_str())
+            || filters.iter().any(|f| f.name == m.as_str())
+            || args.intervals.iter().any(|i| i.name == *m)
+            || args.handoffs.iter().any(|h| h.name == *m)
+            || args.frames.iter().any(|f| {
+                format!("{} fps", f.name) == *m || format!("{} janky frames", f.name) == *m
+            })
+            || args
+                .spans
+                .iter()
+                .any(|s| s.name == *m || format!("{} count", s.name) == *m)
+    };
+    if let Some(name) = args.correlate.iter().find(|m| !is_metric(m)) {
+        return Err(anyhow!("There is no metric named {} to correlate", name));
+    }
+    if let Some(name) = args.anomaly_metrics.iter().find(|m| !is_metric(m)) {
+        return Err(anyhow!("There is no metric named {} to flag runs", name));
+    }
+    let anomaly_metrics = anomaly::considered_metrics(&args, &filters);
+
+    let offline = !args.from_files.is_empty();
+    let devices = if args.all_devices {
+        let devices = device::list_targets(args.backend).context("Listing the devices")?;
+        if devices.is_empty() {
+            return Err(anyhow!("No phone seems to be reachable"));
+        }
+        devices
+    } else {
+        if !offline
+            && !device::is_device_reachable(args.backend, args.device.as_deref())
+                .context("Testing reachability of device")?
+        {
+            return Err(match &args.device {
+                Some(serial) => anyhow!("The phone {} is not reachable", serial),
+                None => anyhow!("No phone seems to be reachable"),
+            });
+        }
+        args.device.iter().cloned().collect()
+    };
+
+    let clusters = if args.detect_clusters {
+        device::detect_clusters(Device::new(args.backend, args.device.as_deref()))?
+    } else {
+        args.clusters.clone()
+    };
 
-    ctrlc::set_handler(move || {
-        device::stop_tracing(args.trace_buffer).expect("Could not stop tracing");
-    })?;
+    if !offline {
+        let (trace_buffer, backend) = (args.trace_buffer, args.backend);
+        let stopped = if devices.is_empty() {
+            vec![None]
+        } else {
+            devices.iter().cloned().map(Some).collect()
+        };
+        ctrlc::set_handler(move || {
+            for device in &stopped {
+                device::stop_tracing(trace_buffer, Device::new(backend, device.as_deref()))
+                    .expect("Could not stop tracing");
+            }
+        })?;
+    }
 
-    let mut results: HashMap<&str, Vec<Duration>> = HashMap::new();
-    let mut errors: HashMap<&str, u32> = HashMap::new();
-    for i in 1..args.tries + 1 {
-        if !args.bencher {
-            println!("Running test {}", i);
+    let start_offset_names = &start_offset_names;
+    let analysis = analysis::Analysis {
+        args: &args,
+        filters: &filters,
+        breakdown_filter,
+        clusters: &clusters,
+        bin_width,
+        start_offset_names,
+    };
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.jobs.unwrap_or(0) as usize)
+        .build()?;
+    if args.all_devices {
+        return run_all_devices(
+            &analysis,
+            &devices,
+            baselines.as_ref(),
+            budgets.as_ref(),
+            &anomaly_metrics,
+            &pool,
+        );
+    }
+    if !args.targets.is_empty() {
+        return run_targets(
+            &analysis,
+            baselines.as_ref(),
+            budgets.as_ref(),
+            &anomaly_metrics,
+            &pool,
+        );
+    }
+    let baseline = match baselines {
+        Some(baseline::Baselines::ByName(_)) => {
+            return Err(anyhow!(
+                "The baseline {:?} has the results of targets or devices, it needs --target or --all-devices",
+                args.baseline.unwrap_or_default()
+            ));
         }
-        let log_path = device::exec_hdc_commands(&args)?;
-        let traces = device::read_file(&args, &log_path)?;
-        let differences = filter::find_notable_differences(&traces, &filters);
-        for (key, value) in differences.iter() {
-            if let Ok(d) = value {
-                results
-                    .entry(key)
-                    .and_modify(|v| v.push(*d))
-                    .or_insert(vec![(*d)]);
-            } else {
-                errors.entry(key).and_modify(|v| *v += 1).or_insert(1);
+        Some(baseline::Baselines::Shared(baseline)) => Some(baseline),
+        None => None,
+    };
+    let mut session = analysis::Session::new(&args);
+    let (runs, retries) = if offline {
+        // every file is read on the thread analyzing it, so only the traces of the running analyses are in memory
+        let analyzed = pool.install(|| {
+            args.from_files
+                .par_iter()
+                .enumerate()
+                .map(|(i, path)| -> Result<analysis::RunAnalysis> {
+                    let mut run = if args.stream {
+                        stream_trace_file(&analysis, i + 1, path)?
+                    } else {
+                        analysis.analyze(i + 1, &read_trace_file(&args, path)?)
+                    };
+                    run.trace = Some(path.clone());
+                    Ok(run)
+                })
+                .collect::<Vec<_>>()
+        });
+        // a broken file only loses its run, the error goes to stderr to keep machine readable output intact
+        let mut runs = Vec::new();
+        for (i, run) in analyzed.into_iter().enumerate() {
+            match run {
+                Ok(run) => runs.push(run),
+                Err(e) => {
+                    eprintln!("Skipping run {}: {:#}", i + 1, e);
+                    *session.errors.entry("unreadable file").or_default() += 1;
+                }
             }
         }
+        if runs.is_empty() {
+            return Err(anyhow!("None of the trace files could be read"));
+        }
+        (runs, Retries::new())
+    } else {
+        capture_device(
+            &analysis,
+            Device::new(args.backend, args.device.as_deref()),
+            None,
+            &pool,
+            &mut session.errors,
+        )?
+    };
+    let warmup = split_warmup(&args, &mut session, runs);
+    let discarded =
+        outliers::reject_outliers(&mut session.results, args.outliers, args.trim_fraction);
 
-        if args.tries == 1 && args.all_traces {
-            println!("Printing {} traces", &traces.len());
-            for i in &traces {
-                println!("{:?}", i);
-            }
-            println!("----------------------------------------------------------\n\n");
+    if let Some(path) = &args.samples {
+        let (baseline_runs, baseline_numbers) = baseline
+            .as_ref()
+            .map(baseline::Baseline::run_results)
+            .unwrap_or_default();
+        let mut sides = vec![samples::Side {
+            name: "current",
+            label: &args.label,
+            runs: &session.run_samples,
+            points: &session.run_points,
+            spilled: &session.run_spilled,
+            numbers: &session.run_numbers,
+        }];
+        if let Some(baseline) = &baseline {
+            sides.insert(
+                0,
+                samples::Side {
+                    name: "baseline",
+                    label: baseline.label(),
+                    runs: &baseline_runs,
+                    points: &[],
+                    spilled: &[],
+                    numbers: &baseline_numbers,
+                },
+            );
         }
+        samples::write_samples(path, &sides, args.sample_limit)?;
+    }
+    if let Some(path) = &args.chrome_trace {
+        session.chrome_trace.write(path)?;
+    }
+    if let Some(path) = &args.flamegraph {
+        flamegraph::write(path, &session.flamegraph)?;
+    }
+    let score = (!args.score.is_empty())
+        .then(|| score::score(&session.results, &args.score))
+        .transpose();
+    if let Some(path) = &args.store {
+        store::store(
+            path,
+            &args,
+            &store::SessionKey::new(&args),
+            &session.results,
+            &session.points,
+            score.as_ref().ok().copied().flatten(),
+        )?;
+    }
+    let json = args.writes_json().then(|| {
+        let mut json = json::JsonResults::new(
+            &args,
+            &session.results,
+            &session.points,
+            &session.errors,
+            &session.run_samples,
+            &session.run_numbers,
+            &session.run_metrics,
+        );
+        json.traces(&session.run_traces);
+        json.score(score.as_ref().ok().copied().flatten());
+        json.clusters(&clusters, &session.cluster_time);
+        if let Some(threshold) = args.mad_threshold {
+            json.flagged(&anomaly::flagged_runs(
+                &session.run_metrics,
+                &anomaly_metrics,
+                threshold,
+            ));
+        }
+        json.lossy_runs(&session.lossy_runs);
+        json.discarded(&discarded);
+        json.warmup(&warmup.results, &warmup.points);
+        json.bins(&session.bins);
+        json.counters(&session.counters);
+        if args.phases.len() > 1 {
+            json.phases(&args.phases, &session.phases);
+        }
+        json
+    });
+    if let (Some(path), Some(json)) = (&args.json, &json) {
+        json::write_json(path, json)?;
+    }
+
+    let (budget_checks, skipped_budgets) = budgets
+        .as_ref()
+        .map(|b| {
+            budget::check_budgets(
+                b,
+                &session.results,
+                &session.run_metrics,
+                args.tail_min_samples,
+            )
+        })
+        .unwrap_or_default();
+    for skipped in &skipped_budgets {
+        eprintln!(
+            "Warning: skipping the p99 budget of {}, it has {} of the {} samples of --tail-min-samples",
+            skipped.filter, skipped.samples, args.tail_min_samples
+        );
     }
+    let failed_budgets = budget_checks.iter().filter(|c| !c.passed()).count();
+    let comparisons = baseline
+        .as_ref()
+        .map(|b| baseline::compare(b, &session.results, &session.points))
+        .unwrap_or_default();
+    let regressions = args.fail_threshold.map_or(0, |threshold| {
+        comparisons
+            .iter()
+            .filter(|c| c.regressed(threshold))
+            .count()
+    });
 
-    if args.computer_output {
-        print_computer(results);
+    if let Some(dir) = &args.html_report {
+        let mut data = report::ReportData::new(&session.results, &session.points, &session.errors);
+        data.baseline(&comparisons, args.fail_threshold);
+        html::write(dir, &html::metadata(&args), &session.results, &data)?;
+    }
+
+    if args.json_to_stdout() {
+        // the json is the output
+    } else if args.computer_output {
+        print_computer(session.results, &session.points);
+        print_phases_computer(&args.phases, &session.phases);
+        print_threads_computer(&session.threads);
+        print_bins_computer(&session.bins);
     } else if args.bencher {
-        write_bencher(results);
+        write_bencher(session.results)?;
+    } else if let Some(format) = args.report {
+        match (format.report(), &json) {
+            (Some(report), _) => {
+                let mut data =
+                    report::ReportData::new(&session.results, &session.points, &session.errors);
+                data.baseline(&comparisons, args.fail_threshold);
+                write_report(&args, &report.render(&data))?;
+            }
+            (None, Some(json)) => {
+                json::write_json(args.output_file.as_deref().unwrap_or(Path::new("-")), json)?
+            }
+            (None, None) => {}
+        }
     } else {
-        print_differences(&args, results, errors);
+        print_differences(&args, &session.results, &session.errors);
+        print_retries(&retries);
+        if args.warmup > 0 {
+            print_warmup(&warmup.results, &warmup.points);
+        }
+        print_lossy_runs(&session.lossy_runs, args.allow_lossy);
+        if !discarded.is_empty() {
+            print_discarded(&discarded, &session.results);
+        }
+        match &score {
+            Ok(Some(score)) => println!("score: {:.3}", score),
+            Ok(None) => {}
+            Err(e) => println!("score: unavailable ({})", e),
+        }
+        print_details(
+            &analysis,
+            &session,
+            &budget_checks,
+            &anomaly_metrics,
+            baseline.is_some().then_some(comparisons.as_slice()),
+        );
     }
 
+    if args.fail_on_lost_events && !session.lossy_runs.is_empty() {
+        return Err(anyhow!(
+            "{} runs lost trace events",
+            session.lossy_runs.len()
+        ));
+    }
+    if failed_budgets > 0 {
+        return Err(anyhow!("{} budgets were exceeded", failed_budgets));
+    }
+    if regressions > 0 {
+        return Err(anyhow!(
+            "{} filters regressed by more than {}% against the baseline",
+            regressions,
+            args.fail_threshold.unwrap_or_default()
+        ));
+    }
     Ok(())
 }
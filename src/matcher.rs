@@ -0,0 +1,53 @@
+//! Matching span function names against the filters given on the command line
+use regex::Regex;
+
+#[derive(Debug, Clone)]
+/// How a filter matches the function name of a span
+pub enum Matcher {
+    /// The whole name is equal
+    Exact(String),
+    /// The name starts with the string
+    Prefix(String),
+    /// The name contains the string
+    Substring(String),
+    /// The regex matches somewhere in the name
+    Regex(Regex),
+}
+
+impl Matcher {
+    /// Does the function name match
+    pub fn matches(&self, function: &str) -> bool {
+        match self {
+            Matcher::Exact(name) => function == name,
+            Matcher::Prefix(prefix) => function.starts_with(prefix.as_str()),
+            Matcher::Substring(part) => function.contains(part.as_str()),
+            Matcher::Regex(regex) => regex.is_match(function),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A span filter. All matching spans are reported together under the name of the filter
+pub struct SpanFilter {
+    /// The filter as given on the command line
+    pub name: String,
+    /// Decides which span names belong to the filter
+    pub matcher: Matcher,
+}
+
+/// Parse a span filter of the form `re:<regex>`, `prefix:<name>`, `contains:<name>` or an exact name
+pub fn parse_span_filter(s: &str) -> Result<SpanFilter, String> {
+    let matcher = if let Some(regex) = s.strip_prefix("re:") {
+        Matcher::Regex(Regex::new(regex).map_err(|e| format!("Invalid regex in {}: {}", s, e))?)
+    } else if let Some(prefix) = s.strip_prefix("prefix:") {
+        Matcher::Prefix(prefix.to_owned())
+    } else if let Some(part) = s.strip_prefix("contains:") {
+        Matcher::Substring(part.to_owned())
+    } else {
+        Matcher::Exact(s.to_owned())
+    };
+    Ok(SpanFilter {
+        name: s.to_owned(),
+        matcher,
+    })
+}
@@ -0,0 +1,91 @@
+//! The pages or launch arguments of `--target` that one invocation benchmarks one after the other
+
+#[derive(Debug, Clone, PartialEq)]
+/// A page the app is started with, replacing `--homepage`
+pub(crate) struct Target {
+    /// Name of the target in the output
+    pub(crate) name: String,
+    /// The url the app loads
+    pub(crate) homepage: String,
+    /// Extra arguments of `aa start`, i.e., `--ps=--pref dom_webgpu_enabled=true`
+    pub(crate) launch_args: Vec<String>,
+}
+
+/// Parse a target of the form `name=<url> [<launch arg>...]`
+pub(crate) fn parse_target(s: &str) -> Result<Target, String> {
+    let (name, rest) = s
+        .split_once('=')
+        .ok_or(format!("Target {} is missing a `=`", s))?;
+    let mut words = rest.split_whitespace();
+    let homepage = words.next().ok_or(format!("Target {} has no url", s))?;
+    Ok(Target {
+        name: name.to_owned(),
+        homepage: homepage.to_owned(),
+        launch_args: words.map(str::to_owned).collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FilterErrors, PointResults, RunResults, args::Args, baseline, json};
+    use clap::Parser;
+    use time::Duration;
+
+    #[test]
+    fn targets_split_into_url_and_launch_args() {
+        assert_eq!(
+            parse_target("webgpu=https://servo.org --ps=--pref dom_webgpu_enabled=true").unwrap(),
+            Target {
+                name: String::from("webgpu"),
+                homepage: String::from("https://servo.org"),
+                launch_args: vec![
+                    String::from("--ps=--pref"),
+                    String::from("dom_webgpu_enabled=true")
+                ],
+            }
+        );
+        assert!(parse_target("https://servo.org").is_err());
+        assert!(parse_target("servo=").is_err());
+    }
+
+    #[test]
+    fn json_of_targets_and_devices_is_a_baseline_per_name() {
+        let args = Args::parse_from(["hitrace-bench"]);
+        let results = RunResults::from([(
+            String::from("LoadPage"),
+            vec![
+                Duration::microseconds(100),
+                Duration::microseconds(200),
+                Duration::microseconds(300),
+            ],
+        )]);
+        let path =
+            std::env::temp_dir().join(format!("hitrace-bench-{}-targets.json", std::process::id()));
+        for (group, name) in [
+            (json::Group::Target, "webgpu"),
+            (json::Group::Device, "127.0.0.1:5555"),
+        ] {
+            let document = json::JsonResults::new(
+                &args,
+                &results,
+                &PointResults::new(),
+                &FilterErrors::new(),
+                &[],
+                &[],
+                &[],
+            );
+            json::write_json(
+                &path,
+                &json::GroupedJson::new(group, [(String::from(name), document)]),
+            )
+            .unwrap();
+            let baselines = baseline::read_baselines(&path).unwrap();
+            let comparisons =
+                baseline::compare(baselines.get(name).unwrap(), &results, &PointResults::new());
+            assert_eq!(comparisons[0].baseline, Some((200, 200)));
+            assert!(baselines.get("servo").is_none());
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
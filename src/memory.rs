@@ -0,0 +1,166 @@
+//! Sampling the memory of the app while it runs, given with `--memory-interval`
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use crate::{
+    FilterErrors, PointResults,
+    backend::{Backend, Device},
+    device,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// The memory of the app at one point in time in kB
+pub(crate) struct MemorySample {
+    pub(crate) pss: i64,
+    /// Only `smaps_rollup` has the rss, `hidumper --mem` does not
+    pub(crate) rss: Option<i64>,
+}
+
+/// The value in kB of the line starting with `key`, i.e., `Pss:   12345 kB`
+fn kilobytes(text: &str, key: &str) -> Option<i64> {
+    text.lines()
+        .find_map(|l| l.strip_prefix(key))
+        .and_then(|rest| rest.split_whitespace().next())
+        .and_then(|value| value.parse().ok())
+}
+
+/// Read the output of `cat /proc/<pid>/smaps_rollup`
+pub(crate) fn parse_smaps_rollup(text: &str) -> Option<MemorySample> {
+    Some(MemorySample {
+        pss: kilobytes(text, "Pss:")?,
+        rss: kilobytes(text, "Rss:"),
+    })
+}
+
+/// Read the output of `hidumper --mem <pid>`, the first column of the `Total` row is the pss.
+/// The header has a `Total` line too, but without numbers
+pub(crate) fn parse_hidumper(text: &str) -> Option<MemorySample> {
+    let pss = text.lines().find_map(|l| {
+        let mut words = l.split_whitespace();
+        if words.next() != Some("Total") {
+            return None;
+        }
+        words.next()?.parse().ok()
+    })?;
+    Some(MemorySample { pss, rss: None })
+}
+
+/// Samples the memory of the app on a thread until it is finished
+pub(crate) struct Sampler {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<Vec<MemorySample>>>,
+}
+
+impl Sampler {
+    /// Sample every `interval` from now on. Samples that fail, i.e., before the app started, are skipped
+    pub(crate) fn start(
+        bundle_name: String,
+        backend: Backend,
+        serial: Option<String>,
+        interval: Duration,
+    ) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let handle = std::thread::spawn(move || {
+            let mut samples = Vec::new();
+            while !stopped.load(Ordering::Relaxed) {
+                let device = Device::new(backend, serial.as_deref());
+                if let Ok(Some(sample)) = device::memory_sample(&bundle_name, device) {
+                    samples.push(sample);
+                }
+                std::thread::park_timeout(interval);
+            }
+            samples
+        });
+        Sampler {
+            stop,
+            handle: Some(handle),
+        }
+    }
+
+    /// Stop sampling and return the samples
+    pub(crate) fn finish(mut self) -> Vec<MemorySample> {
+        self.stop();
+        self.handle
+            .take()
+            .and_then(|h| h.join().ok())
+            .unwrap_or_default()
+    }
+
+    fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = &self.handle {
+            handle.thread().unpark();
+        }
+    }
+}
+
+impl Drop for Sampler {
+    /// A run that failed lets the thread end on its own
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Add the peak and average of the samples of a run to its points, a run without samples is an error
+pub(crate) fn add_points(
+    points: &mut PointResults,
+    errors: &mut FilterErrors,
+    samples: &[MemorySample],
+) {
+    let Some(peak) = samples.iter().map(|s| s.pss).max() else {
+        *errors.entry("memory").or_default() += 1;
+        return;
+    };
+    let avg = samples.iter().map(|s| s.pss).sum::<i64>() / samples.len() as i64;
+    let mut values = vec![("memory pss peak (kB)", peak), ("memory pss avg (kB)", avg)];
+    if let Some(rss) = samples.iter().filter_map(|s| s.rss).max() {
+        values.push(("memory rss peak (kB)", rss));
+    }
+    for (name, value) in values {
+        points.entry(name.to_owned()).or_default().push(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_outputs_are_parsed() {
+        let rollup = "5585a59000-7ffd1e4d3000 ---p 00000000 00:00 0   [rollup]
+Rss:              123456 kB
+Pss:               98765 kB
+Pss_Anon:          50000 kB
+";
+        assert_eq!(
+            parse_smaps_rollup(rollup),
+            Some(MemorySample {
+                pss: 98765,
+                rss: Some(123456)
+            })
+        );
+        let hidumper = "-------------------------------[memory]-------------------------------
+                  Pss        Shared   Shared   Private  Private   Swap
+                  Total      Clean    Dirty    Clean    Dirty     Total
+                  ( kB )     ( kB )   ( kB )   ( kB )   ( kB )    ( kB )
+           ------------------------------------------------------------
+      GL          1024       0        0        0        1024      0
+   Total          87654      1000     2000     3000     4000      0
+";
+        assert_eq!(
+            parse_hidumper(hidumper),
+            Some(MemorySample {
+                pss: 87654,
+                rss: None
+            })
+        );
+        assert_eq!(parse_smaps_rollup("cat: Permission denied"), None);
+    }
+}
@@ -0,0 +1,132 @@
+//! The monitor subcommand, rolling statistics of the span filters while the device keeps tracing
+use anyhow::{Context, Result, anyhow};
+use std::{
+    collections::VecDeque,
+    io::BufReader,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError},
+    },
+    time::{Duration as StdDuration, Instant},
+};
+use time::Duration;
+
+use crate::{
+    args::Args,
+    backend::Device,
+    device,
+    parse::TraceReader,
+    span::{OwnedSpan, SpanStream},
+    trace::{TimeStamp, difference_of_timestamps},
+    utils::percentile,
+};
+
+/// The spans of one span filter that ended in the window, oldest first
+#[derive(Default)]
+struct Window {
+    spans: VecDeque<(TimeStamp, Duration)>,
+}
+
+impl Window {
+    fn push(&mut self, span: &OwnedSpan) {
+        self.spans.push_back((span.end, span.duration()));
+    }
+
+    /// Forget the spans that ended more than `length` before `now`
+    fn expire(&mut self, now: TimeStamp, length: Duration) {
+        while self
+            .spans
+            .front()
+            .is_some_and(|(end, _d)| difference_of_timestamps(&now, end) > length)
+        {
+            self.spans.pop_front();
+        }
+    }
+}
+
+/// Trace the device until interrupted and print the count, p50 and p95 of every span filter
+/// over the spans that ended in the last `window` seconds, every `every` seconds
+pub(crate) fn monitor(args: &Args, window: u64, every: u64) -> Result<()> {
+    if args.spans.is_empty() {
+        return Err(anyhow!("The monitor needs at least one --span"));
+    }
+    let device = Device::new(args.backend, args.device.as_deref());
+    device::start_tracing(args, device, None)?;
+    let mut pipe = device::trace_pipe(device)?;
+    let stdout = pipe
+        .stdout
+        .take()
+        .context("Could not read the trace pipe of the device")?;
+
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let handler = interrupted.clone();
+    ctrlc::set_handler(move || handler.store(true, Ordering::Relaxed))?;
+
+    let (sender, receiver) = mpsc::channel();
+    let reader = TraceReader::with_format(
+        BufReader::new(stdout),
+        device::bundle_short(args)?,
+        device.backend.trace_format(),
+    )?;
+    std::thread::spawn(move || {
+        let mut stream = SpanStream::new();
+        for event in reader {
+            let Ok(event) = event else {
+                continue;
+            };
+            if let Some(span) = stream.push(event)
+                && sender.send(span).is_err()
+            {
+                return;
+            }
+        }
+    });
+
+    let length = Duration::seconds(window as i64);
+    let mut windows = args
+        .spans
+        .iter()
+        .map(|_| Window::default())
+        .collect::<Vec<_>>();
+    let mut latest = None;
+    let mut next_print = Instant::now() + StdDuration::from_secs(every);
+    while !interrupted.load(Ordering::Relaxed) {
+        match receiver.recv_timeout(next_print.saturating_duration_since(Instant::now())) {
+            Ok(span) => {
+                latest = latest.max(Some(span.end));
+                for (filter, window) in args.spans.iter().zip(windows.iter_mut()) {
+                    if filter.matcher.matches(&span.name) {
+                        window.push(&span);
+                    }
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        if Instant::now() < next_print {
+            continue;
+        }
+        next_print += StdDuration::from_secs(every);
+        // the window goes by the trace clock, so a slow pipe does not expire spans early
+        if let Some(now) = latest {
+            windows.iter_mut().for_each(|w| w.expire(now, length));
+        }
+        println!("----last {}s------------------------", window);
+        for (filter, window) in args.spans.iter().zip(windows.iter()) {
+            let durations = window.spans.iter().map(|(_end, d)| *d).collect::<Vec<_>>();
+            match (percentile(&durations, 50.0), percentile(&durations, 95.0)) {
+                (Some(p50), Some(p95)) => println!(
+                    "{}: count {}, p50 {}, p95 {}",
+                    filter.name,
+                    durations.len(),
+                    p50,
+                    p95
+                ),
+                _ => println!("{}: count 0", filter.name),
+            }
+        }
+    }
+    pipe.kill().ok();
+    device::stop_tracing(args.trace_buffer, device)
+}
@@ -0,0 +1,51 @@
+//! Restricting a span filter to the spans that start inside the spans of a parent filter
+use std::cmp::Reverse;
+use time::Duration;
+
+use crate::{span::Span, trace::TimeStamp};
+
+/// Parse a nesting of the form `child=parent`, both are names of span filters
+pub(crate) fn parse_span_parent(s: &str) -> Result<(String, String), String> {
+    let (child, parent) = s
+        .split_once('=')
+        .ok_or(format!("Span parent {} is missing a `=`", s))?;
+    Ok((child.to_owned(), parent.to_owned()))
+}
+
+/// Does the span start inside the parent on the same process
+fn starts_in(span: &Span, parent: &Span) -> bool {
+    span.start.pid == parent.start.pid
+        && span.start.timestamp >= parent.start.timestamp
+        && span.start.timestamp < parent.end.timestamp
+        && !std::ptr::eq(span.start, parent.start)
+}
+
+/// Nested spans order after the spans containing them, even if they start at the same time
+fn nesting_order(span: &Span) -> (TimeStamp, Reverse<TimeStamp>) {
+    (span.start.timestamp, Reverse(span.end.timestamp))
+}
+
+/// The innermost parent span the child starts in, None if it starts in no parent.
+/// Nested parents contain each other, so the innermost one orders last.
+pub(crate) fn innermost_parent<'a, 'b>(
+    child: &Span,
+    parents: &'b [&'a Span<'a>],
+) -> Option<&'b &'a Span<'a>> {
+    parents
+        .iter()
+        .filter(|p| starts_in(child, p))
+        .max_by_key(|p| nesting_order(p))
+}
+
+/// Summed duration of the parent spans that are not nested in another parent span
+pub(crate) fn outermost_duration(parents: &[&Span]) -> Duration {
+    parents
+        .iter()
+        .filter(|p| {
+            !parents
+                .iter()
+                .any(|other| starts_in(p, other) && nesting_order(other) < nesting_order(p))
+        })
+        .map(|p| p.duration())
+        .sum()
+}
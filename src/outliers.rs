@@ -0,0 +1,41 @@
+//! Rejection of outliers in the durations of every filter before the statistics are computed
+use clap::ValueEnum;
+use std::collections::HashMap;
+
+use crate::{
+    RunResults,
+    utils::{reject_iqr, trim},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+/// Which durations are discarded as outliers
+pub(crate) enum OutlierPolicy {
+    /// Keep every duration
+    None,
+    /// Discard durations more than 1.5 interquartile ranges outside the quartiles
+    Iqr,
+    /// Discard the `--trim-fraction` smallest and largest durations
+    Trim,
+}
+
+/// Discard the outliers of every filter. Returns the number of discarded durations by filter
+pub(crate) fn reject_outliers(
+    results: &mut RunResults,
+    policy: OutlierPolicy,
+    fraction: f64,
+) -> HashMap<String, usize> {
+    let mut discarded = HashMap::new();
+    if policy == OutlierPolicy::None {
+        return discarded;
+    }
+    for (name, durations) in results.iter_mut() {
+        let kept = match policy {
+            OutlierPolicy::None => unreachable!(),
+            OutlierPolicy::Iqr => reject_iqr(durations),
+            OutlierPolicy::Trim => trim(durations, fraction),
+        };
+        discarded.insert(name.clone(), durations.len() - kept.len());
+        *durations = kept;
+    }
+    discarded
+}
@@ -0,0 +1,534 @@
+//! Parsing the text output of hitrace or atrace into traces
+use rayon::prelude::*;
+use regex::{Captures, Regex};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    io::BufRead,
+};
+
+use crate::{
+    error::BenchError,
+    sched::{SchedEvent, SchedKind},
+    trace::{TimeStamp, Trace, TraceMarker},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+/// A place in the traces where events are missing
+pub struct LostRegion {
+    /// The line of the dump the loss was found at, counted from 1
+    pub line: usize,
+    /// Index of the first trace after the missing events
+    pub position: usize,
+    /// The number of lost events the kernel reported, None if the time on a cpu went backwards
+    pub events: Option<u64>,
+}
+
+#[derive(Debug)]
+/// The traces of a dump and what else we learned while reading it
+pub struct ParseReport {
+    /// The parsed traces
+    pub traces: Vec<Trace>,
+    /// Number of events the kernel reported as lost because the buffer overflowed
+    pub lost_events: u64,
+    /// Where events are missing, in the order of the traces
+    pub lost_event_regions: Vec<LostRegion>,
+    /// Human readable descriptions of the lost regions
+    pub warnings: Vec<String>,
+    /// Lines that could not be read, i.e., that are not utf-8, counted from 1
+    pub unreadable_lines: Vec<usize>,
+    /// The scheduler events of all threads, only traced with the `sched` category
+    pub sched: Vec<SchedEvent>,
+}
+
+impl ParseReport {
+    /// Events are missing somewhere in the dump, so durations may be wrong
+    pub fn is_lossy(&self) -> bool {
+        !self.lost_event_regions.is_empty()
+    }
+
+    /// Keep only the traces of the processes, the lost regions keep pointing at the same traces
+    pub fn retain_processes(&mut self, pids: &HashSet<u64>) {
+        // the number of kept traces before every index
+        let mut kept = Vec::with_capacity(self.traces.len() + 1);
+        kept.push(0);
+        for trace in &self.traces {
+            let before = *kept.last().unwrap();
+            kept.push(before + usize::from(pids.contains(&trace.pid)));
+        }
+        for region in self.lost_event_regions.iter_mut() {
+            region.position = kept[region.position];
+        }
+        self.traces.retain(|t| pids.contains(&t.pid));
+    }
+}
+
+/// Parse a field of a matched line
+fn field<T: std::str::FromStr>(
+    captures: &Captures,
+    index: usize,
+    line: usize,
+) -> Result<T, BenchError>
+where
+    T::Err: fmt::Display,
+{
+    captures[index].parse().map_err(|e| BenchError::Line {
+        line,
+        message: format!("Invalid field {:?}: {}", &captures[index], e),
+    })
+}
+
+/// Read a regex matched line into a trace
+fn captures_to_trace(c: &Captures, line: usize) -> Result<Trace, BenchError> {
+    let trace_marker = TraceMarker::from(&c[7]).ok_or(BenchError::Line {
+        line,
+        message: format!("Unknown trace marker {}", &c[7]),
+    })?;
+    Ok(Trace {
+        name: c[1].to_owned(),
+        tid: field(c, 2, line)?,
+        pid: field(c, 3, line)?,
+        cpu: field(c, 4, line)?,
+        timestamp: TimeStamp {
+            seconds: field(c, 5, line)?,
+            micro: field(c, 6, line)?,
+        },
+        trace_marker,
+        number: c[8].to_owned(),
+        shorthand: c[9].to_owned(),
+        function: c[10].to_owned(),
+    })
+}
+
+/// The trace of an atrace line, None for markers we do not know, i.e., the instant events of newer Android.
+/// Atrace has no shorthand, async traces end with `|<cookie>` and counters with `|<value>`
+fn atrace_captures_to_trace(c: &Captures, line: usize) -> Result<Option<Trace>, BenchError> {
+    let Some(trace_marker) = TraceMarker::from(&c[7]) else {
+        return Ok(None);
+    };
+    let rest = c.get(9).map_or("", |m| m.as_str());
+    let (number, function) = match trace_marker {
+        TraceMarker::StartAsync | TraceMarker::EndAsync => match rest.rsplit_once('|') {
+            Some((name, cookie)) => (cookie.to_owned(), name.to_owned()),
+            None => (c[8].to_owned(), rest.to_owned()),
+        },
+        TraceMarker::Dot => (
+            c[8].to_owned(),
+            rest.rsplit_once('|')
+                .map_or(rest.to_owned(), |(name, value)| {
+                    format!("{} {}", name, value)
+                }),
+        ),
+        TraceMarker::StartSync | TraceMarker::EndSync => (c[8].to_owned(), rest.to_owned()),
+    };
+    Ok(Some(Trace {
+        name: c[1].to_owned(),
+        tid: field(c, 2, line)?,
+        pid: field(c, 3, line)?,
+        cpu: field(c, 4, line)?,
+        timestamp: TimeStamp {
+            seconds: field(c, 5, line)?,
+            micro: field(c, 6, line)?,
+        },
+        trace_marker,
+        number,
+        shorthand: String::new(),
+        function,
+    }))
+}
+
+/// The scheduler event of a line whose first captures are cpu, seconds and microseconds
+fn sched_event(c: &Captures, line: usize, kind: SchedKind) -> Result<SchedEvent, BenchError> {
+    Ok(SchedEvent {
+        cpu: field(c, 1, line)?,
+        timestamp: TimeStamp {
+            seconds: field(c, 2, line)?,
+            micro: field(c, 3, line)?,
+        },
+        kind,
+    })
+}
+
+#[derive(Debug)]
+/// What [`TraceReader`] found on a line of the dump
+pub enum Event {
+    /// A trace of the thread
+    Trace(Trace),
+    /// Events are missing before the next trace, with a human readable description
+    Lost(LostRegion, String),
+    /// The line, counted from 1, is not utf-8
+    Unreadable(usize),
+    /// A scheduler event of any thread
+    Sched(SchedEvent),
+}
+
+/// What a line of the dump is, before the lines are put in order
+enum Matched {
+    Trace(Trace),
+    /// The number of events the kernel lost
+    Lost(u64),
+    Unreadable,
+    Sched(SchedEvent),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+/// The dialect of the `tracing_mark_write` lines
+pub enum TraceFormat {
+    /// OpenHarmony hitrace, `B|44682|H: Layout`
+    #[default]
+    Hitrace,
+    /// Android atrace, `B|44682|Layout`
+    Atrace,
+}
+
+/// Matches single lines, it keeps no state so lines can be matched in any order
+struct LineMatcher {
+    format: TraceFormat,
+    regex: Regex,
+    lost_regex: Regex,
+    switch_regex: Regex,
+    wakeup_regex: Regex,
+}
+
+impl LineMatcher {
+    fn new(thread: &str, format: TraceFormat) -> Result<Self, BenchError> {
+        // The line starts with thread name, tid, pid and cpu.
+        // Example trace: `org.servo.servo-44962   (  44682) [010] .... 17864.716645: tracing_mark_write: B|44682|ML: do_single_part3_compilation`
+        let marker = match format {
+            TraceFormat::Hitrace => r"(.)\|(\d+?)\|(.*?):(.*?)",
+            // `E|44682` has no function
+            TraceFormat::Atrace => r"(.)\|(\d+)(?:\|(.*?))?",
+        };
+        let regex = Regex::new(&format!(
+            r"^.(.*?{}.*?)\-(\d+)\s*\(\s*(\d+)\)\s*\[(\d+)\].*?(\d+)\.(\d+): tracing_mark_write: {}\s*$",
+            regex::escape(thread),
+            marker
+        ))?;
+        // Lines like `CPU:3 [LOST 123 EVENTS]` tell us the buffer overflowed
+        let lost_regex = Regex::new(r"\[LOST (\d+) EVENTS\]")?;
+        // Scheduler lines of all threads, i.e.,
+        // `<idle>-0 (-----) [010] d..2 17864.716645: sched_switch: prev_comm=swapper/10 prev_pid=0 prev_prio=120 prev_state=R ==> next_comm=org.servo.servo next_pid=44962 next_prio=120`
+        let switch_regex = Regex::new(
+            r"\[(\d+)\].*?(\d+)\.(\d+): sched_switch: .*?prev_pid=(\d+) .*?prev_state=(\S+) ==> .*?next_pid=(\d+)",
+        )?;
+        // `kworker/3:1-123 (  123) [003] d..4 17864.716645: sched_wakeup: comm=org.servo.servo pid=44962 prio=120 target_cpu=010`
+        let wakeup_regex =
+            Regex::new(r"\[(\d+)\].*?(\d+)\.(\d+): sched_wakeup(?:_new)?: .*? pid=(\d+)")?;
+        Ok(LineMatcher {
+            format,
+            regex,
+            lost_regex,
+            switch_regex,
+            wakeup_regex,
+        })
+    }
+
+    /// What the line at the index is, None for lines that are skipped
+    fn matched(
+        &self,
+        index: usize,
+        line: &std::io::Result<String>,
+    ) -> Result<Option<Matched>, BenchError> {
+        let Ok(line) = line else {
+            return Ok(Some(Matched::Unreadable));
+        };
+        if let Some(c) = self.regex.captures(line) {
+            Ok(match self.format {
+                TraceFormat::Hitrace => Some(Matched::Trace(captures_to_trace(&c, index + 1)?)),
+                TraceFormat::Atrace => atrace_captures_to_trace(&c, index + 1)?.map(Matched::Trace),
+            })
+        } else if let Some(c) = self.lost_regex.captures(line) {
+            Ok(Some(Matched::Lost(field(&c, 1, index + 1)?)))
+        } else if let Some(c) = self.switch_regex.captures(line) {
+            let kind = SchedKind::Switch {
+                prev_tid: field(&c, 4, index + 1)?,
+                prev_state: c[5].to_owned(),
+                next_tid: field(&c, 6, index + 1)?,
+            };
+            Ok(Some(Matched::Sched(sched_event(&c, index + 1, kind)?)))
+        } else if let Some(c) = self.wakeup_regex.captures(line) {
+            let kind = SchedKind::Wakeup {
+                tid: field(&c, 4, index + 1)?,
+            };
+            Ok(Some(Matched::Sched(sched_event(&c, index + 1, kind)?)))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+#[derive(Default)]
+/// Puts the matched lines in order into events, finding where the time on a cpu goes backwards
+struct EventOrder {
+    /// Number of traces so far
+    traces: usize,
+    last_on_cpu: HashMap<u64, TimeStamp>,
+}
+
+impl EventOrder {
+    /// The event of the next matched line, and the lost region before it if the time went backwards
+    fn next(&mut self, index: usize, matched: Matched) -> (Option<Event>, Event) {
+        match matched {
+            Matched::Trace(trace) => {
+                let mut lost = None;
+                if let Some(last) = self.last_on_cpu.insert(trace.cpu, trace.timestamp)
+                    && trace.timestamp < last
+                {
+                    let region = LostRegion {
+                        line: index + 1,
+                        position: self.traces,
+                        events: None,
+                    };
+                    let warning = format!(
+                        "Line {}: the time on cpu {} went backwards",
+                        index + 1,
+                        trace.cpu
+                    );
+                    lost = Some(Event::Lost(region, warning));
+                }
+                self.traces += 1;
+                (lost, Event::Trace(trace))
+            }
+            Matched::Lost(events) => {
+                let region = LostRegion {
+                    line: index + 1,
+                    position: self.traces,
+                    events: Some(events),
+                };
+                let warning = format!("Line {}: lost {} events", index + 1, events);
+                (None, Event::Lost(region, warning))
+            }
+            Matched::Unreadable => (None, Event::Unreadable(index + 1)),
+            Matched::Sched(event) => (None, Event::Sched(event)),
+        }
+    }
+}
+
+/// Reads the traces of a dump line by line, so only the current line is in memory
+pub struct TraceReader<R> {
+    lines: std::iter::Enumerate<std::io::Lines<R>>,
+    matcher: LineMatcher,
+    order: EventOrder,
+    /// The trace of a line that also started a lost region, it is returned after the region
+    pending: Option<Event>,
+}
+
+impl<R: BufRead> TraceReader<R> {
+    /// Read the `tracing_mark_write` lines of threads whose name contains `thread`, see [`parse_report`]
+    pub fn new(reader: R, thread: &str) -> Result<Self, BenchError> {
+        Self::with_format(reader, thread, TraceFormat::Hitrace)
+    }
+
+    /// Read the lines of the format, see [`parse_report_with_format`]
+    pub fn with_format(reader: R, thread: &str, format: TraceFormat) -> Result<Self, BenchError> {
+        Ok(TraceReader {
+            lines: reader.lines().enumerate(),
+            matcher: LineMatcher::new(thread, format)?,
+            order: EventOrder::default(),
+            pending: None,
+        })
+    }
+}
+
+impl<R: BufRead> Iterator for TraceReader<R> {
+    type Item = Result<Event, BenchError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(event) = self.pending.take() {
+            return Some(Ok(event));
+        }
+        for (index, line) in self.lines.by_ref() {
+            match self.matcher.matched(index, &line) {
+                Ok(Some(matched)) => {
+                    let (lost, event) = self.order.next(index, matched);
+                    if let Some(lost) = lost {
+                        self.pending = Some(event);
+                        return Some(Ok(lost));
+                    }
+                    return Some(Ok(event));
+                }
+                Ok(None) => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        None
+    }
+}
+
+/// Number of lines matched in parallel by [`parse_report`]
+const CHUNK_LINES: usize = 1 << 16;
+
+/// Parse the `tracing_mark_write` lines of threads whose name contains `thread`.
+/// Other lines, like the header hitrace writes at the top, are skipped.
+/// Events are lost where the dump has a lost events line or where the time on a cpu goes backwards.
+/// Chunks of lines are matched in parallel on the current rayon pool.
+/// Use [`TraceReader`] to go through a dump without keeping all its traces.
+///
+/// ```
+/// use hitrace_bench::parse::parse_report;
+///
+/// let dump = "# tracer: nop
+///  org.servo.servo-44962   (  44682) [010] .... 17864.716645: tracing_mark_write: B|44682|H: Layout
+///  RenderThread-44970   (  44682) [003] .... 17864.716650: tracing_mark_write: B|44682|H: Draw
+///  CPU:3 [LOST 12 EVENTS]
+/// ";
+/// let report = parse_report(dump.as_bytes(), "servo")?;
+/// assert_eq!(report.traces.len(), 1);
+/// assert_eq!(report.traces[0].function, " Layout");
+/// assert_eq!(report.lost_events, 12);
+/// assert_eq!(report.lost_event_regions[0].position, 1);
+/// # Ok::<(), hitrace_bench::BenchError>(())
+/// ```
+pub fn parse_report(reader: impl BufRead, thread: &str) -> Result<ParseReport, BenchError> {
+    parse_report_with_format(reader, thread, TraceFormat::Hitrace)
+}
+
+/// Parse a dump of the format like [`parse_report`]
+///
+/// ```
+/// use hitrace_bench::parse::{TraceFormat, parse_report_with_format};
+///
+/// let dump = " RenderThread-12345 ( 12300) [002] ...1 5432.100000: tracing_mark_write: B|12300|DrawFrame
+///  RenderThread-12345 ( 12300) [002] ...1 5432.100500: tracing_mark_write: E|12300
+///  RenderThread-12345 ( 12300) [002] ...1 5432.100600: tracing_mark_write: C|12300|frames|3
+/// ";
+/// let report = parse_report_with_format(dump.as_bytes(), "", TraceFormat::Atrace)?;
+/// assert_eq!(report.traces[0].function, "DrawFrame");
+/// assert_eq!(report.traces[2].counter(), Some(("frames", 3)));
+/// # Ok::<(), hitrace_bench::BenchError>(())
+/// ```
+pub fn parse_report_with_format(
+    reader: impl BufRead,
+    thread: &str,
+    format: TraceFormat,
+) -> Result<ParseReport, BenchError> {
+    let mut report = ParseReport {
+        traces: Vec::new(),
+        lost_events: 0,
+        lost_event_regions: Vec::new(),
+        warnings: Vec::new(),
+        unreadable_lines: Vec::new(),
+        sched: Vec::new(),
+    };
+    let matcher = LineMatcher::new(thread, format)?;
+    let mut order = EventOrder::default();
+    let mut lines = reader.lines();
+    let mut first = 0;
+    loop {
+        // matching the lines is the expensive part, so a chunk of lines is matched in parallel
+        // and only putting them in order is sequential
+        let chunk = lines.by_ref().take(CHUNK_LINES).collect::<Vec<_>>();
+        if chunk.is_empty() {
+            break;
+        }
+        let matched = chunk
+            .par_iter()
+            .enumerate()
+            .map(|(i, line)| matcher.matched(first + i, line))
+            .collect::<Vec<_>>();
+        for (i, matched) in matched.into_iter().enumerate() {
+            let Some(matched) = matched? else {
+                continue;
+            };
+            let (lost, event) = order.next(first + i, matched);
+            for event in lost.into_iter().chain([event]) {
+                match event {
+                    Event::Trace(trace) => report.traces.push(trace),
+                    Event::Lost(region, warning) => {
+                        report.lost_events += region.events.unwrap_or(0);
+                        report.lost_event_regions.push(region);
+                        report.warnings.push(warning);
+                    }
+                    Event::Unreadable(line) => report.unreadable_lines.push(line),
+                    Event::Sched(event) => report.sched.push(event),
+                }
+            }
+        }
+        first += chunk.len();
+    }
+    Ok(report)
+}
+
+/// Parse the `tracing_mark_write` lines of all threads
+///
+/// ```
+/// use hitrace_bench::{parse_traces, span::find_all_spans};
+///
+/// let dump = " org.servo.servo-44962   (  44682) [010] .... 17864.716645: tracing_mark_write: B|44682|H: Layout
+///  org.servo.servo-44962   (  44682) [010] .... 17864.716945: tracing_mark_write: E|44682|H:
+/// ";
+/// let traces = parse_traces(dump.as_bytes())?;
+/// let (spans, _integrity) = find_all_spans(&traces);
+/// assert_eq!(spans[0].name(), "Layout");
+/// assert_eq!(spans[0].duration(), time::Duration::microseconds(300));
+/// # Ok::<(), hitrace_bench::BenchError>(())
+/// ```
+pub fn parse_traces(reader: impl BufRead) -> Result<Vec<Trace>, BenchError> {
+    parse_report(reader, "").map(|r| r.traces)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_keep_the_order_of_the_lines() {
+        let line = |micro: usize| {
+            format!(
+                " org.servo.servo-44962   (  44682) [010] .... 17864.{:06}: tracing_mark_write: C|44682|H:mem {}\n",
+                micro, micro
+            )
+        };
+        // the time goes backwards on the first line of the second chunk
+        let mut dump = (0..CHUNK_LINES).map(|i| line(i + 10)).collect::<String>();
+        dump.push_str(&line(0));
+        dump.push_str(" CPU:10 [LOST 3 EVENTS]\n");
+        dump.push_str(&line(CHUNK_LINES + 10));
+
+        let report = parse_report(dump.as_bytes(), "servo").unwrap();
+        assert_eq!(report.traces.len(), CHUNK_LINES + 2);
+        assert_eq!(report.traces[CHUNK_LINES].function, "mem 0");
+        assert_eq!(
+            report.lost_event_regions,
+            [
+                LostRegion {
+                    line: CHUNK_LINES + 1,
+                    position: CHUNK_LINES,
+                    events: None,
+                },
+                LostRegion {
+                    line: CHUNK_LINES + 2,
+                    position: CHUNK_LINES + 1,
+                    events: Some(3),
+                },
+            ]
+        );
+        let streamed = TraceReader::new(dump.as_bytes(), "servo")
+            .unwrap()
+            .filter_map(|e| match e.unwrap() {
+                Event::Lost(region, _warning) => Some(region),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(streamed, report.lost_event_regions);
+    }
+
+    #[test]
+    fn processes_are_resolved_by_name() {
+        let dump = " org.servo.servo-100   (  100) [010] .... 17864.000001: tracing_mark_write: B|100|H: Layout
+ other-200   (  200) [003] .... 17864.000002: tracing_mark_write: B|200|H: Layout
+ CPU:3 [LOST 3 EVENTS]
+ org.servo.servo-100   (  100) [010] .... 17864.000003: tracing_mark_write: E|100|H:
+ other-200   (  200) [003] .... 17864.000004: tracing_mark_write: E|200|H:
+";
+        let mut report = parse_report(dump.as_bytes(), "").unwrap();
+        // the kernel cut the name of the main thread to 15 bytes
+        let matcher = crate::matcher::Matcher::Exact(String::from("org.servo.servo.app"));
+        let pids = crate::trace::resolve_pids(&report.traces, &matcher);
+        assert_eq!(pids, HashSet::from([100]));
+        report.retain_processes(&pids);
+        assert!(report.traces.iter().all(|t| t.pid == 100));
+        assert_eq!(report.traces.len(), 2);
+        assert_eq!(report.lost_event_regions[0].position, 1);
+    }
+}
@@ -0,0 +1,134 @@
+//! Numeric arguments of instant markers and spans as samples
+use regex::Regex;
+use time::Duration;
+
+use crate::{
+    matcher::{SpanFilter, parse_span_filter},
+    span::Span,
+    trace::Trace,
+};
+
+#[derive(Debug, Clone)]
+/// Takes the value of an argument of every matching instant marker
+pub(crate) struct PayloadFilter {
+    /// Name of the filter in the output
+    pub(crate) name: String,
+    /// Name of the instant marker
+    pub(crate) marker: String,
+    /// Key of the argument
+    pub(crate) key: String,
+}
+
+/// Parse a payload filter of the form `name=Marker:key`
+pub(crate) fn parse_payload_filter(s: &str) -> Result<PayloadFilter, String> {
+    let (name, rest) = s
+        .split_once('=')
+        .ok_or(format!("Payload filter {} is missing a `=`", s))?;
+    let (marker, key) = rest
+        .split_once(':')
+        .ok_or(format!("Payload filter {} is missing a `:`", s))?;
+    Ok(PayloadFilter {
+        name: name.to_owned(),
+        marker: marker.to_owned(),
+        key: key.to_owned(),
+    })
+}
+
+/// The argument values of all matching markers in a run
+pub(crate) struct Payloads {
+    /// The numeric values
+    pub(crate) values: Vec<i64>,
+    /// Number of markers where the argument was missing or not a number
+    pub(crate) invalid: u32,
+}
+
+/// Collect the argument values of all markers matching the filter
+pub(crate) fn find_payloads(traces: &[Trace], filter: &PayloadFilter) -> Payloads {
+    let mut payloads = Payloads {
+        values: Vec::new(),
+        invalid: 0,
+    };
+    for trace in traces.iter().filter(|t| t.is_marker(&filter.marker)) {
+        match trace.argument(&filter.key).map(|v| v.parse::<i64>()) {
+            Some(Ok(value)) => payloads.values.push(value),
+            _ => payloads.invalid += 1,
+        }
+    }
+    payloads
+}
+
+#[derive(Debug, Clone)]
+/// Takes a number out of the arguments of every span of a span filter
+pub(crate) struct SpanArgFilter {
+    /// Name of the filter in the output
+    pub(crate) name: String,
+    /// The spans whose arguments are read
+    pub(crate) span: SpanFilter,
+    /// The first capture group is the number
+    pub(crate) regex: Regex,
+}
+
+/// Parse a span argument filter of the form `name=<span filter>@<regex>`, i.e., `decode size=prefix:ImageDecode@size=(\d+)`
+pub(crate) fn parse_span_arg_filter(s: &str) -> Result<SpanArgFilter, String> {
+    let (name, rest) = s
+        .split_once('=')
+        .ok_or(format!("Span argument filter {} is missing a `=`", s))?;
+    let (span, regex) = rest
+        .split_once('@')
+        .ok_or(format!("Span argument filter {} is missing a `@`", s))?;
+    let regex = Regex::new(regex).map_err(|e| format!("Invalid regex in {}: {}", s, e))?;
+    if regex.captures_len() < 2 {
+        return Err(format!("The regex of {} has no capture group", s));
+    }
+    Ok(SpanArgFilter {
+        name: name.to_owned(),
+        span: parse_span_filter(span)?,
+        regex,
+    })
+}
+
+/// The number in the arguments of every span with the duration of the span.
+/// Returns how many spans had no number too
+pub(crate) fn span_values(spans: &[&Span], filter: &SpanArgFilter) -> (Vec<(i64, Duration)>, u32) {
+    let mut values = Vec::new();
+    let mut invalid = 0;
+    for span in spans {
+        match filter
+            .regex
+            .captures(span.start.arguments())
+            .and_then(|c| c.get(1))
+            .and_then(|m| m.as_str().parse().ok())
+        {
+            Some(value) => values.push((value, span.duration())),
+            None => invalid += 1,
+        }
+    }
+    (values, invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hitrace_bench::{parse_traces, span::find_all_spans};
+
+    #[test]
+    fn numbers_are_taken_from_the_span_arguments() {
+        let dump = " org.servo.servo-44962   (  44682) [010] .... 17864.000100: tracing_mark_write: B|44682|H: ImageDecode size=12345 url=a.png
+ org.servo.servo-44962   (  44682) [010] .... 17864.000300: tracing_mark_write: E|44682|H:
+ org.servo.servo-44962   (  44682) [010] .... 17864.000400: tracing_mark_write: B|44682|H: ImageDecode url=b.png
+ org.servo.servo-44962   (  44682) [010] .... 17864.000500: tracing_mark_write: E|44682|H:
+";
+        let traces = parse_traces(dump.as_bytes()).unwrap();
+        let (spans, _integrity) = find_all_spans(&traces);
+        let filter = parse_span_arg_filter(r"decode size=prefix:ImageDecode@size=(\d+)").unwrap();
+        let spans = spans
+            .iter()
+            .filter(|s| filter.span.matcher.matches(s.name()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            span_values(&spans, &filter),
+            (vec![(12345, Duration::microseconds(200))], 1)
+        );
+        assert!(parse_span_arg_filter("decode=ImageDecode@size=\\d+").is_err());
+    }
+}
@@ -0,0 +1,43 @@
+//! Durations of the phases between consecutive milestone markers
+use anyhow::{Result, anyhow};
+use time::Duration;
+
+use crate::trace::{Trace, difference_of_traces};
+
+/// The names of the segments between consecutive milestones, i.e., `ProcessCreate->AbilityOnCreate`
+pub(crate) fn segment_names(milestones: &[String]) -> Vec<String> {
+    milestones
+        .windows(2)
+        .map(|w| format!("{}->{}", w[0], w[1]))
+        .collect()
+}
+
+/// Find the duration of every segment between the first occurrences of consecutive milestones.
+/// A missing milestone or milestones out of order make the run an error.
+pub(crate) fn find_phases(traces: &[Trace], milestones: &[String]) -> Result<Vec<Duration>> {
+    let found = milestones
+        .iter()
+        .map(|m| traces.iter().find(|t| t.is_marker(m)))
+        .collect::<Vec<_>>();
+    let missing = milestones
+        .iter()
+        .zip(found.iter())
+        .filter(|(_m, t)| t.is_none())
+        .map(|(m, _t)| m.as_str())
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        return Err(anyhow!("incomplete, missing {}", missing.join(", ")));
+    }
+    let found = found.into_iter().flatten().collect::<Vec<_>>();
+    found
+        .windows(2)
+        .zip(milestones.windows(2))
+        .map(|(t, m)| {
+            if t[1].timestamp < t[0].timestamp {
+                Err(anyhow!("out of order, {} before {}", m[1], m[0]))
+            } else {
+                Ok(difference_of_traces(t[1], t[0]))
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,272 @@
+//! Boolean expressions over the fields of a trace, to restrict the measurements to processes, threads or arguments
+//!
+//! Predicates are built with `&`, `|` and `!` or parsed from expressions like
+//! `function=prefix:Layout && pid_of(org.servo.servo) && !arg(url)=contains:about:blank`.
+use std::{collections::HashMap, ops, str::FromStr};
+
+use crate::{
+    matcher::{Matcher, parse_span_filter},
+    trace::{Trace, is_process},
+};
+
+#[derive(Debug, Clone)]
+/// A condition on a trace
+pub enum Predicate {
+    /// The function name without its leading space matches
+    Function(Matcher),
+    /// The process has the pid
+    Pid(u64),
+    /// The thread has the tid
+    Tid(u64),
+    /// The name of the thread matches
+    Thread(Matcher),
+    /// The name of the process, which is the name of its main thread, matches
+    Process(Matcher),
+    /// The value of the `key=value` argument in the function matches
+    Arg(String, Matcher),
+    /// Both match
+    And(Box<Predicate>, Box<Predicate>),
+    /// Either matches
+    Or(Box<Predicate>, Box<Predicate>),
+    /// The predicate does not match
+    Not(Box<Predicate>),
+}
+
+impl Predicate {
+    /// Does the trace match. `process_names` comes from [`crate::trace::process_names`]
+    pub fn matches(&self, trace: &Trace, process_names: &HashMap<u64, &str>) -> bool {
+        match self {
+            Predicate::Function(matcher) => matcher.matches(trace.function.trim()),
+            Predicate::Pid(pid) => trace.pid == *pid,
+            Predicate::Tid(tid) => trace.tid == *tid,
+            Predicate::Thread(matcher) => matcher.matches(&trace.name),
+            Predicate::Process(matcher) => process_names
+                .get(&trace.pid)
+                .is_some_and(|name| is_process(matcher, name)),
+            Predicate::Arg(key, matcher) => trace.argument(key).is_some_and(|v| matcher.matches(v)),
+            Predicate::And(a, b) => {
+                a.matches(trace, process_names) && b.matches(trace, process_names)
+            }
+            Predicate::Or(a, b) => {
+                a.matches(trace, process_names) || b.matches(trace, process_names)
+            }
+            Predicate::Not(a) => !a.matches(trace, process_names),
+        }
+    }
+}
+
+impl ops::BitAnd for Predicate {
+    type Output = Predicate;
+
+    fn bitand(self, other: Predicate) -> Predicate {
+        Predicate::And(Box::new(self), Box::new(other))
+    }
+}
+
+impl ops::BitOr for Predicate {
+    type Output = Predicate;
+
+    fn bitor(self, other: Predicate) -> Predicate {
+        Predicate::Or(Box::new(self), Box::new(other))
+    }
+}
+
+impl ops::Not for Predicate {
+    type Output = Predicate;
+
+    fn not(self) -> Predicate {
+        Predicate::Not(Box::new(self))
+    }
+}
+
+/// The matcher of a value, with the syntax of the span filters
+fn matcher(value: &str) -> Result<Matcher, String> {
+    parse_span_filter(value).map(|f| f.matcher)
+}
+
+/// A condition without operators, i.e., `pid=44682`
+fn atom(s: &str) -> Result<Predicate, String> {
+    if let Some(name) = s.strip_prefix("pid_of(").and_then(|s| s.strip_suffix(')')) {
+        return Ok(Predicate::Process(Matcher::Exact(name.to_owned())));
+    }
+    if let Some(rest) = s.strip_prefix("arg(")
+        && let Some((key, value)) = rest.split_once(")=")
+    {
+        return Ok(Predicate::Arg(key.to_owned(), matcher(value)?));
+    }
+    let (field, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("Expected <field>=<value> in {}", s))?;
+    let number = || {
+        value
+            .parse()
+            .map_err(|e| format!("Invalid number in {}: {}", s, e))
+    };
+    match field {
+        "function" => Ok(Predicate::Function(matcher(value)?)),
+        "pid" => Ok(Predicate::Pid(number()?)),
+        "tid" => Ok(Predicate::Tid(number()?)),
+        "thread" => Ok(Predicate::Thread(matcher(value)?)),
+        "process" => Ok(Predicate::Process(matcher(value)?)),
+        _ => Err(format!("Unknown field {} in {}", field, s)),
+    }
+}
+
+/// Recursive descent over the expression, `!` binds tighter than `&&` which binds tighter than `||`
+struct Parser<'a> {
+    rest: &'a str,
+}
+
+impl Parser<'_> {
+    /// Consume the token if it comes next
+    fn eat(&mut self, token: &str) -> bool {
+        self.rest = self.rest.trim_start();
+        match self.rest.strip_prefix(token) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn or(&mut self) -> Result<Predicate, String> {
+        let mut predicate = self.and()?;
+        while self.eat("||") {
+            predicate = predicate | self.and()?;
+        }
+        Ok(predicate)
+    }
+
+    fn and(&mut self) -> Result<Predicate, String> {
+        let mut predicate = self.unary()?;
+        while self.eat("&&") {
+            predicate = predicate & self.unary()?;
+        }
+        Ok(predicate)
+    }
+
+    fn unary(&mut self) -> Result<Predicate, String> {
+        if self.eat("!") {
+            return Ok(!self.unary()?);
+        }
+        if self.eat("(") {
+            let predicate = self.or()?;
+            if !self.eat(")") {
+                return Err(format!("Expected ) before {:?}", self.rest));
+            }
+            return Ok(predicate);
+        }
+        // an atom ends at whitespace or at a closing parenthesis it did not open
+        let mut depth = 0;
+        let end = self
+            .rest
+            .char_indices()
+            .find(|(_i, c)| match c {
+                '(' => {
+                    depth += 1;
+                    false
+                }
+                ')' if depth == 0 => true,
+                ')' => {
+                    depth -= 1;
+                    false
+                }
+                c => c.is_whitespace(),
+            })
+            .map_or(self.rest.len(), |(i, _c)| i);
+        if end == 0 {
+            return Err(String::from("Expected a condition at the end"));
+        }
+        let (token, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        atom(token)
+    }
+}
+
+impl FromStr for Predicate {
+    type Err = String;
+
+    /// Parse an expression of conditions combined with `&&`, `||`, `!` and parentheses. Conditions are
+    /// `function=`, `thread=` and `process=` followed by a span filter matcher like `prefix:Layout`,
+    /// `pid=` and `tid=` followed by a number, `pid_of(<process name>)` and `arg(<key>)=` followed by a matcher.
+    /// Conditions end at whitespace
+    ///
+    /// ```
+    /// use hitrace_bench::{parse_traces, predicate::Predicate, trace::process_names};
+    ///
+    /// let dump = " org.servo.servo-44682   (  44682) [010] .... 17864.716645: tracing_mark_write: B|44682|H: Load url=about:blank
+    ///  Layout-44970   (  44682) [003] .... 17864.716650: tracing_mark_write: B|44682|H: Layout
+    /// ";
+    /// let traces = parse_traces(dump.as_bytes())?;
+    /// let names = process_names(&traces);
+    /// let predicate: Predicate = "pid_of(org.servo.servo) && !(thread=contains:Layout || arg(url)=prefix:https)".parse().unwrap();
+    /// assert!(predicate.matches(&traces[0], &names));
+    /// assert!(!predicate.matches(&traces[1], &names));
+    /// # Ok::<(), hitrace_bench::BenchError>(())
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser { rest: s };
+        let predicate = parser.or()?;
+        if !parser.rest.trim().is_empty() {
+            return Err(format!("Unexpected {:?} in {}", parser.rest.trim(), s));
+        }
+        Ok(predicate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::{TimeStamp, TraceMarker};
+
+    fn trace(name: &str, pid: u64, tid: u64, function: &str) -> Trace {
+        Trace {
+            name: name.to_owned(),
+            pid,
+            tid,
+            cpu: 0,
+            timestamp: TimeStamp {
+                seconds: 1,
+                micro: 0,
+            },
+            trace_marker: TraceMarker::StartSync,
+            number: pid.to_string(),
+            shorthand: String::from("H"),
+            function: function.to_owned(),
+        }
+    }
+
+    #[test]
+    fn operators_bind_in_order() {
+        let traces = [
+            trace("org.servo.servo", 1, 1, " Layout"),
+            trace("Layout", 1, 2, " Layout"),
+            trace("other", 3, 3, " Paint"),
+        ];
+        let names = HashMap::from([(1, "org.servo.servo"), (3, "other")]);
+        let matching = |expression: &str| {
+            let predicate = expression.parse::<Predicate>().unwrap();
+            traces
+                .iter()
+                .map(|t| predicate.matches(t, &names))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            matching("pid=3 || tid=1 && function=Layout"),
+            [true, false, true]
+        );
+        assert_eq!(
+            matching("(pid=3 || tid=1) && function=Layout"),
+            [true, false, false]
+        );
+        assert_eq!(matching("!pid_of(org.servo.servo)"), [false, false, true]);
+        assert_eq!(
+            matching("function=re:^(Layout|Paint)$ && !tid=2"),
+            [true, false, true]
+        );
+        assert!("pid=abc".parse::<Predicate>().is_err());
+        assert!("pid=1 &&".parse::<Predicate>().is_err());
+        assert!("(pid=1".parse::<Predicate>().is_err());
+    }
+}
@@ -0,0 +1,172 @@
+//! The ranking of the targets of `--target` or the devices of `--all-devices` by the filter of `--rank`
+//!
+//! The targets that regressed most against their baseline come first, the ones without a baseline last.
+//! The noise of a target is the standard deviation of the filter in percent of its average.
+use serde::Serialize;
+use time::Duration;
+
+use crate::{PointResults, RunResults, baseline::Comparison, utils::avg_min_max};
+
+#[derive(Debug, Clone, Serialize)]
+/// The filter of one target, durations in microseconds
+pub(crate) struct Rank {
+    /// The name of the target or the serial of the device
+    name: String,
+    /// The filter is a point, otherwise a duration
+    point: bool,
+    runs: usize,
+    avg: i64,
+    median: i64,
+    std_dev: i64,
+    /// Standard deviation in percent of the average
+    noise_percent: Option<f64>,
+    /// Average in the baseline of the target
+    baseline_avg: Option<i64>,
+    /// Change of the average in percent of the baseline
+    delta_percent: Option<f64>,
+}
+
+impl Rank {
+    fn format(&self, value: i64) -> String {
+        if self.point {
+            value.to_string()
+        } else {
+            Duration::microseconds(value).to_string()
+        }
+    }
+}
+
+/// The filter of a target with its comparison against the baseline of the target
+pub(crate) fn rank(
+    name: &str,
+    filter: &str,
+    results: &RunResults,
+    points: &PointResults,
+    comparisons: &[Comparison],
+) -> Option<Rank> {
+    let (point, values) = match results.get(filter) {
+        Some(durations) => (
+            false,
+            durations
+                .iter()
+                .map(|d| d.whole_microseconds() as i64)
+                .collect::<Vec<_>>(),
+        ),
+        None => (true, points.get(filter)?.clone()),
+    };
+    let stats = avg_min_max(&values).ok()?;
+    let comparison = comparisons.iter().find(|c| c.name == filter);
+    Some(Rank {
+        name: name.to_owned(),
+        point,
+        runs: stats.number,
+        avg: stats.avg,
+        median: stats.median,
+        std_dev: stats.std_dev,
+        noise_percent: (stats.avg != 0).then(|| stats.std_dev as f64 / stats.avg as f64 * 100.0),
+        baseline_avg: comparison
+            .and_then(|c| c.baseline)
+            .map(|(mean, _median)| mean),
+        delta_percent: comparison
+            .and_then(|c| c.percent())
+            .map(|(mean, _median)| mean),
+    })
+}
+
+/// Sort the ranks by their change against the baseline, the largest regression first.
+/// Ranks without a baseline come last, sorted by name
+pub(crate) fn sort_worst_first(ranks: &mut [Rank]) {
+    ranks.sort_by(|a, b| match (a.delta_percent, b.delta_percent) {
+        (Some(a), Some(b)) => b.total_cmp(&a),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => a.name.cmp(&b.name),
+    });
+}
+
+/// The ranks with the largest noise first
+fn noisiest(ranks: &[Rank]) -> Vec<&Rank> {
+    let mut noisiest = ranks
+        .iter()
+        .filter(|r| r.noise_percent.is_some())
+        .collect::<Vec<_>>();
+    noisiest.sort_by(|a, b| {
+        b.noise_percent
+            .unwrap_or_default()
+            .total_cmp(&a.noise_percent.unwrap_or_default())
+    });
+    noisiest
+}
+
+/// Print the `top` worst and noisiest ranks, `title` is `targets` or `devices`
+pub(crate) fn print_ranking(title: &str, filter: &str, ranks: &[Rank], top: usize) {
+    println!(
+        "----{} by {} (avg median ±stddev, change against the baseline, worst first)------------------------",
+        title, filter
+    );
+    for r in ranks.iter().take(top) {
+        let delta = match (r.baseline_avg, r.delta_percent) {
+            (Some(base), Some(delta)) => format!("{:+.1}% of {}", delta, r.format(base)),
+            _ => String::from("no baseline"),
+        };
+        println!(
+            "{}: {} {} ±{}  {}  ({} runs)",
+            r.name,
+            r.format(r.avg),
+            r.format(r.median),
+            r.format(r.std_dev),
+            delta,
+            r.runs
+        );
+    }
+    println!(
+        "----noisiest {} by {} (stddev in % of avg)------------------------",
+        title, filter
+    );
+    for r in noisiest(ranks).into_iter().take(top) {
+        println!("{}: {:.1}%", r.name, r.noise_percent.unwrap_or_default());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn results(micros: &[i64]) -> RunResults {
+        RunResults::from([(
+            String::from("LoadPage"),
+            micros.iter().map(|m| Duration::microseconds(*m)).collect(),
+        )])
+    }
+
+    fn comparison(baseline: i64, current: i64) -> Vec<Comparison> {
+        vec![Comparison {
+            name: String::from("LoadPage"),
+            point: false,
+            baseline: Some((baseline, baseline)),
+            current: Some((current, current)),
+        }]
+    }
+
+    #[test]
+    fn regressed_targets_come_first() {
+        let points = PointResults::new();
+        let mut ranks = [
+            ("stable", results(&[100, 100, 100]), comparison(100, 100)),
+            ("new", results(&[100, 200, 300]), Vec::new()),
+            ("slower", results(&[150, 150, 150]), comparison(100, 150)),
+            ("faster", results(&[90, 90, 90]), comparison(100, 90)),
+        ]
+        .iter()
+        .filter_map(|(name, results, comparisons)| {
+            rank(name, "LoadPage", results, &points, comparisons)
+        })
+        .collect::<Vec<_>>();
+        assert!(rank("none", "Layout", &results(&[1]), &points, &[]).is_none());
+        sort_worst_first(&mut ranks);
+        let names = ranks.iter().map(|r| r.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["slower", "stable", "faster", "new"]);
+        assert_eq!(ranks[0].delta_percent, Some(50.0));
+        assert_eq!(noisiest(&ranks)[0].name, "new");
+    }
+}
@@ -0,0 +1,317 @@
+//! Reports of the statistics in formats for sharing, i.e., markdown tables for PR descriptions and csv for spreadsheets
+//!
+//! Durations are integers of microseconds so reports of two sessions diff cleanly.
+use clap::ValueEnum;
+use serde::Serialize;
+use std::{collections::BTreeMap, fmt::Write};
+
+use crate::{
+    FilterErrors, PointResults, RunResults, baseline::Comparison, samples::csv_field,
+    utils::avg_min_max,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+/// The format of the report
+pub(crate) enum ReportFormat {
+    /// Markdown tables per category, with the deltas against the baseline if there is one
+    #[value(alias = "markdown")]
+    Md,
+    /// One flat csv file with a kind column
+    Csv,
+    /// Bencher Metric Format json for `bencher run`, filters are latencies in µs and points are values
+    Bmf,
+    /// The versioned json document of `--json` with the per run values
+    Json,
+}
+
+/// One row of statistics, durations in microseconds
+pub(crate) struct Row<'a> {
+    pub(crate) name: &'a str,
+    pub(crate) runs: usize,
+    pub(crate) avg: i64,
+    pub(crate) min: i64,
+    pub(crate) max: i64,
+    pub(crate) median: i64,
+    pub(crate) p90: i64,
+    pub(crate) p95: i64,
+    pub(crate) p99: i64,
+    pub(crate) std_dev: i64,
+}
+
+/// Everything that goes into a report, sorted by name
+pub(crate) struct ReportData<'a> {
+    pub(crate) filters: Vec<Row<'a>>,
+    pub(crate) points: Vec<Row<'a>>,
+    pub(crate) errors: Vec<(&'a str, u32)>,
+    /// The comparison against the baseline, empty without one
+    pub(crate) comparisons: &'a [Comparison],
+    /// Regressions and improvements beyond this percentage are marked
+    pub(crate) threshold: Option<f64>,
+}
+
+impl<'a> ReportData<'a> {
+    pub(crate) fn new(
+        results: &'a RunResults,
+        points: &'a PointResults,
+        errors: &'a FilterErrors,
+    ) -> Self {
+        let filters = results
+            .iter()
+            .collect::<BTreeMap<_, _>>()
+            .into_iter()
+            .filter_map(|(name, durations)| {
+                let micros = durations
+                    .iter()
+                    .map(|d| d.whole_microseconds() as i64)
+                    .collect::<Vec<_>>();
+                row(name, &micros)
+            })
+            .collect();
+        let points = points
+            .iter()
+            .collect::<BTreeMap<_, _>>()
+            .into_iter()
+            .filter_map(|(name, values)| row(name, values))
+            .collect();
+        let errors = errors
+            .iter()
+            .map(|(name, count)| (*name, *count))
+            .collect::<BTreeMap<_, _>>()
+            .into_iter()
+            .collect();
+        ReportData {
+            filters,
+            points,
+            errors,
+            comparisons: &[],
+            threshold: None,
+        }
+    }
+
+    /// Add the comparison against the baseline, changes of the mean beyond the threshold are marked
+    pub(crate) fn baseline(&mut self, comparisons: &'a [Comparison], threshold: Option<f64>) {
+        self.comparisons = comparisons;
+        self.threshold = threshold;
+    }
+}
+
+/// The row of the values, None if there are none
+fn row<'a>(name: &'a str, values: &[i64]) -> Option<Row<'a>> {
+    avg_min_max(values).ok().map(|s| Row {
+        name,
+        runs: s.number,
+        avg: s.avg,
+        min: s.min,
+        max: s.max,
+        median: s.median,
+        p90: s.p90,
+        p95: s.p95,
+        p99: s.p99,
+        std_dev: s.std_dev,
+    })
+}
+
+/// A format the report can be rendered in
+pub(crate) trait Report {
+    fn render(&self, data: &ReportData) -> String;
+}
+
+/// Markdown tables for filters, points and errors
+struct Markdown;
+
+impl Markdown {
+    fn table(out: &mut String, title: &str, unit: &str, rows: &[Row]) {
+        if rows.is_empty() {
+            return;
+        }
+        writeln!(out, "### {}\n", title).unwrap();
+        writeln!(
+            out,
+            "| name | runs | avg{unit} | min{unit} | max{unit} | median{unit} | p90{unit} | p95{unit} | p99{unit} | stddev{unit} |"
+        )
+        .unwrap();
+        writeln!(out, "|---|--:|--:|--:|--:|--:|--:|--:|--:|--:|").unwrap();
+        for r in rows {
+            writeln!(
+                out,
+                "| {} | {} | {} | {} | {} | {} | {} | {} | {} | {} |",
+                r.name.replace('|', "\\|"),
+                r.runs,
+                r.avg,
+                r.min,
+                r.max,
+                r.median,
+                r.p90,
+                r.p95,
+                r.p99,
+                r.std_dev
+            )
+            .unwrap();
+        }
+        out.push('\n');
+    }
+
+    /// The means against the baseline, filters beyond the threshold get a red or green marker
+    fn baseline(out: &mut String, comparisons: &[Comparison], threshold: Option<f64>) {
+        if comparisons.is_empty() {
+            return;
+        }
+        writeln!(
+            out,
+            "### Baseline\n\n| name | baseline avg | avg | delta | change | |\n|---|--:|--:|--:|--:|---|"
+        )
+        .unwrap();
+        for c in comparisons {
+            let name = c.name.replace('|', "\\|");
+            let unit = if c.point { "" } else { " µs" };
+            match (c.baseline, c.current, c.percent()) {
+                (Some((base, _)), Some((mean, _)), Some((percent, _))) => {
+                    let marker = match threshold {
+                        Some(t) if !c.point && percent > t => "🔴 regression",
+                        Some(t) if !c.point && percent < -t => "🟢 improvement",
+                        _ => "",
+                    };
+                    writeln!(
+                        out,
+                        "| {} | {}{unit} | {}{unit} | {:+}{unit} | {:+.1}% | {} |",
+                        name,
+                        base,
+                        mean,
+                        mean - base,
+                        percent,
+                        marker
+                    )
+                    .unwrap();
+                }
+                (None, Some((mean, _)), _) => {
+                    writeln!(out, "| {} | | {}{unit} | | | added |", name, mean).unwrap()
+                }
+                (Some((base, _)), None, _) => {
+                    writeln!(out, "| {} | {}{unit} | | | | removed |", name, base).unwrap()
+                }
+                _ => writeln!(out, "| {} | | | | | no values |", name).unwrap(),
+            }
+        }
+        out.push('\n');
+    }
+}
+
+impl Report for Markdown {
+    fn render(&self, data: &ReportData) -> String {
+        let mut out = String::new();
+        Markdown::table(&mut out, "Filters", " (µs)", &data.filters);
+        Markdown::table(&mut out, "Points", "", &data.points);
+        Markdown::baseline(&mut out, data.comparisons, data.threshold);
+        if !data.errors.is_empty() {
+            writeln!(out, "### Errors\n\n| name | errors |\n|---|--:|").unwrap();
+            for (name, count) in &data.errors {
+                writeln!(out, "| {} | {} |", name.replace('|', "\\|"), count).unwrap();
+            }
+        }
+        out
+    }
+}
+
+/// One flat csv table, the kind is `filter`, `point` or `error`. Errors only fill the runs column with their count
+struct Csv;
+
+impl Report for Csv {
+    fn render(&self, data: &ReportData) -> String {
+        let mut out = String::from("kind,name,runs,avg,min,max,median,p90,p95,p99,stddev\n");
+        for (kind, rows) in [("filter", &data.filters), ("point", &data.points)] {
+            for r in rows.iter() {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{},{},{},{},{}",
+                    kind,
+                    csv_field(r.name),
+                    r.runs,
+                    r.avg,
+                    r.min,
+                    r.max,
+                    r.median,
+                    r.p90,
+                    r.p95,
+                    r.p99,
+                    r.std_dev
+                )
+                .unwrap();
+            }
+        }
+        for (name, count) in &data.errors {
+            writeln!(out, "error,{},{},,,,,,,,", csv_field(name), count).unwrap();
+        }
+        out
+    }
+}
+
+#[derive(Serialize)]
+/// One measure of a benchmark in the Bencher Metric Format
+struct Metric {
+    value: i64,
+    lower_value: i64,
+    upper_value: i64,
+}
+
+/// The Bencher Metric Format, benchmark name to measure to the mean with min and max as bounds
+struct Bmf;
+
+impl Report for Bmf {
+    fn render(&self, data: &ReportData) -> String {
+        let mut benchmarks: BTreeMap<&str, BTreeMap<&str, Metric>> = BTreeMap::new();
+        for (measure, rows) in [("latency", &data.filters), ("value", &data.points)] {
+            for r in rows.iter() {
+                benchmarks.entry(r.name).or_default().insert(
+                    measure,
+                    Metric {
+                        value: r.avg,
+                        lower_value: r.min,
+                        upper_value: r.max,
+                    },
+                );
+            }
+        }
+        let mut out = serde_json::to_string_pretty(&benchmarks).expect("Could not write json");
+        out.push('\n');
+        out
+    }
+}
+
+impl ReportFormat {
+    /// The renderer of the format, None for json which is written by [`crate::json`]
+    pub(crate) fn report(self) -> Option<Box<dyn Report>> {
+        match self {
+            ReportFormat::Md => Some(Box::new(Markdown)),
+            ReportFormat::Csv => Some(Box::new(Csv)),
+            ReportFormat::Bmf => Some(Box::new(Bmf)),
+            ReportFormat::Json => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::Duration;
+
+    #[test]
+    fn bmf_has_a_measure_per_kind() {
+        let results = RunResults::from([(
+            String::from("Layout"),
+            vec![Duration::microseconds(100), Duration::microseconds(300)],
+        )]);
+        let points = PointResults::from([(String::from("Layout"), vec![4, 6])]);
+        let errors = FilterErrors::new();
+        let data = ReportData::new(&results, &points, &errors);
+        let json: serde_json::Value = serde_json::from_str(&Bmf.render(&data)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "Layout": {
+                    "latency": {"value": 200, "lower_value": 100, "upper_value": 300},
+                    "value": {"value": 5, "lower_value": 4, "upper_value": 6},
+                }
+            })
+        );
+    }
+}
@@ -0,0 +1,392 @@
+//! Export of the raw samples and point values of every run for plotting
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+use time::Duration;
+
+use crate::{PointResults, RunResults, spill::SpilledSpans};
+
+#[derive(Debug, Serialize)]
+/// One sample of a filter
+struct Sample<'a> {
+    /// The build label given on the command line
+    label: &'a str,
+    /// The side of the comparison, see [`Side`]
+    side: &'a str,
+    /// The run starting at 1
+    run: usize,
+    /// Name of the filter
+    filter: &'a str,
+    /// Duration in microseconds
+    duration_us: i64,
+    /// Number of samples of the filter in the run before applying the sample limit
+    total_samples: usize,
+}
+
+#[derive(Debug, Serialize)]
+/// One value of a point metric
+struct PointSample<'a> {
+    /// The build label given on the command line
+    label: &'a str,
+    /// The side of the comparison, see [`Side`]
+    side: &'a str,
+    /// The run starting at 1
+    run: usize,
+    /// Name of the point metric
+    point: &'a str,
+    value: i64,
+}
+
+/// The runs of one side of a comparison, every sample is tagged with its name
+pub(crate) struct Side<'a> {
+    /// `current` for the runs of the session and `baseline` for the runs of `--baseline`,
+    /// `old` and `new` for the files of `compare`
+    pub(crate) name: &'a str,
+    /// The build label
+    pub(crate) label: &'a str,
+    pub(crate) runs: &'a [RunResults],
+    pub(crate) points: &'a [PointResults],
+    /// The spilled spans of every run, empty without `--spill`
+    pub(crate) spilled: &'a [HashMap<String, SpilledSpans>],
+    pub(crate) numbers: &'a [usize],
+}
+
+/// The durations of a filter in a run, spilled filters are read back from their file
+enum Durations<'a> {
+    Memory(&'a [Duration]),
+    /// The spans and whether the self durations are the samples
+    Spilled(&'a SpilledSpans, bool),
+}
+
+/// The durations of a filter in a run
+struct Group<'a> {
+    label: &'a str,
+    side: &'a str,
+    filter: &'a str,
+    run: usize,
+    durations: Durations<'a>,
+}
+
+impl<'a> Group<'a> {
+    /// Call `f` with every sample, at most `limit` evenly spaced ones like [`cap`]. Spilled spans are
+    /// read one after the other, so they are never all in memory
+    fn for_each(
+        &self,
+        limit: Option<usize>,
+        mut f: impl FnMut(Sample<'a>) -> Result<()>,
+    ) -> Result<()> {
+        let sample = |duration: Duration, total_samples| Sample {
+            label: self.label,
+            side: self.side,
+            run: self.run,
+            filter: self.filter,
+            duration_us: duration.whole_microseconds() as i64,
+            total_samples,
+        };
+        match self.durations {
+            Durations::Memory(durations) => {
+                for duration in cap(durations, limit) {
+                    f(sample(duration, durations.len()))?;
+                }
+            }
+            Durations::Spilled(spans, self_duration) => {
+                let count = spans.count as usize;
+                let limit = limit.unwrap_or(count).min(count);
+                let mut kept = 0;
+                for (index, record) in spans.read()?.enumerate() {
+                    let (duration, self_time) = record?;
+                    if kept < limit && index == kept * count / limit {
+                        kept += 1;
+                        f(sample(
+                            if self_duration { self_time } else { duration },
+                            count,
+                        ))?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The samples of the json export, written while they are read
+struct Samples<'a> {
+    groups: &'a [Group<'a>],
+    limit: Option<usize>,
+}
+
+impl Serialize for Samples<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::{Error, SerializeSeq};
+        let mut seq = serializer.serialize_seq(None)?;
+        for group in self.groups {
+            let mut failed = None;
+            let result = group.for_each(self.limit, |sample| {
+                seq.serialize_element(&sample).map_err(|e| {
+                    let message = e.to_string();
+                    failed = Some(e);
+                    anyhow::anyhow!(message)
+                })
+            });
+            if let Some(e) = failed {
+                return Err(e);
+            }
+            result.map_err(|e| S::Error::custom(format!("{:#}", e)))?;
+        }
+        seq.end()
+    }
+}
+
+#[derive(Serialize)]
+/// The document written for a json export
+struct SampleExport<'a> {
+    /// The limit of samples per filter and run, if any
+    sample_limit: Option<usize>,
+    samples: Samples<'a>,
+    points: Vec<PointSample<'a>>,
+}
+
+/// Keep at most `limit` evenly spaced samples
+fn cap(durations: &[Duration], limit: Option<usize>) -> Vec<Duration> {
+    match limit {
+        Some(limit) if durations.len() > limit => (0..limit)
+            .map(|i| durations[i * durations.len() / limit])
+            .collect(),
+        _ => durations.to_vec(),
+    }
+}
+
+/// The durations of every filter in every run of the sides, sorted by filter, side and run. The spilled
+/// filters are read back from their file instead of taking their sample from the results
+fn groups<'a>(sides: &'a [Side<'a>]) -> Vec<Group<'a>> {
+    let mut groups = Vec::new();
+    for (order, side) in sides.iter().enumerate() {
+        for (index, (run, number)) in side.runs.iter().zip(side.numbers).enumerate() {
+            for (filter, durations) in run.iter() {
+                let spill = side.spilled.get(index).and_then(|spilled| {
+                    match filter.strip_suffix(" (self)") {
+                        Some(name) => spilled.get(name).map(|s| (s, true)),
+                        None => spilled.get(filter).map(|s| (s, false)),
+                    }
+                });
+                let durations = match spill {
+                    Some((spans, self_duration)) => Durations::Spilled(spans, self_duration),
+                    None => Durations::Memory(durations),
+                };
+                groups.push((
+                    order,
+                    Group {
+                        label: side.label,
+                        side: side.name,
+                        filter,
+                        run: *number,
+                        durations,
+                    },
+                ));
+            }
+        }
+    }
+    groups.sort_by_key(|(order, g)| (g.filter, *order, g.run));
+    groups.into_iter().map(|(_, g)| g).collect()
+}
+
+/// Flatten the point values of the runs of the sides, sorted by point, side and run
+fn to_point_samples<'a>(sides: &'a [Side<'a>]) -> Vec<PointSample<'a>> {
+    let mut samples = Vec::new();
+    for (order, side) in sides.iter().enumerate() {
+        for (run, number) in side.points.iter().zip(side.numbers) {
+            for (point, values) in run.iter() {
+                samples.extend(values.iter().map(|value| {
+                    (
+                        order,
+                        PointSample {
+                            label: side.label,
+                            side: side.name,
+                            run: *number,
+                            point,
+                            value: *value,
+                        },
+                    )
+                }));
+            }
+        }
+    }
+    samples.sort_by_key(|(order, s)| (s.point, *order, s.run));
+    samples.into_iter().map(|(_, s)| s).collect()
+}
+
+/// Quote a csv field if needed
+pub(crate) fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+/// Write all samples and point values of all runs of the sides to the path, the sides interleaved by filter.
+/// A `.json` file gets json, everything else csv. In csv the point values are rows with an empty duration and the value in the last column.
+pub(crate) fn write_samples(path: &Path, sides: &[Side], limit: Option<usize>) -> Result<()> {
+    let groups = groups(sides);
+    let point_samples = to_point_samples(sides);
+    let file = File::create(path).with_context(|| format!("Could not create {:?}", path))?;
+    let mut writer = BufWriter::new(file);
+    if path.extension().is_some_and(|e| e == "json") {
+        let export = SampleExport {
+            sample_limit: limit,
+            samples: Samples {
+                groups: &groups,
+                limit,
+            },
+            points: point_samples,
+        };
+        serde_json::to_writer_pretty(&mut writer, &export)?;
+    } else {
+        writeln!(
+            writer,
+            "label,side,run,filter,duration_us,total_samples,value"
+        )?;
+        for group in &groups {
+            group.for_each(limit, |s| {
+                writeln!(
+                    writer,
+                    "{},{},{},{},{},{},",
+                    csv_field(s.label),
+                    csv_field(s.side),
+                    s.run,
+                    csv_field(s.filter),
+                    s.duration_us,
+                    s.total_samples
+                )?;
+                Ok(())
+            })?;
+        }
+        for p in point_samples {
+            writeln!(
+                writer,
+                "{},{},{},{},,,{}",
+                csv_field(p.label),
+                csv_field(p.side),
+                p.run,
+                csv_field(p.point),
+                p.value
+            )?;
+        }
+    }
+    writer
+        .flush()
+        .with_context(|| format!("Could not write {:?}", path))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spill::{SAMPLE_SIZE, Spill};
+
+    #[test]
+    fn spilled_spans_are_exported_past_the_sample() {
+        let mut spill = Spill::create().unwrap();
+        let count = SAMPLE_SIZE * 5;
+        for i in 0..count as i64 {
+            spill
+                .push(Duration::microseconds(i), Duration::microseconds(1))
+                .unwrap();
+        }
+        let spilled = vec![HashMap::from([(
+            String::from("Layout"),
+            spill.finish().unwrap(),
+        )])];
+        let runs = vec![RunResults::from([
+            (String::from("Layout"), Vec::new()),
+            (String::from("Layout (self)"), Vec::new()),
+        ])];
+        let points = vec![PointResults::new()];
+        let sides = [Side {
+            name: "current",
+            label: "main",
+            runs: &runs,
+            points: &points,
+            spilled: &spilled,
+            numbers: &[1],
+        }];
+        let path =
+            std::env::temp_dir().join(format!("hitrace-bench-{}-samples.csv", std::process::id()));
+        write_samples(&path, &sides, None).unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        let rows = csv.lines().skip(1).collect::<Vec<_>>();
+        assert_eq!(rows.len(), 2 * count);
+        assert_eq!(rows[0], format!("main,current,1,Layout,0,{},", count));
+        assert_eq!(
+            rows[count - 1],
+            format!("main,current,1,Layout,{},{},", count - 1, count)
+        );
+        assert_eq!(
+            rows[count],
+            format!("main,current,1,Layout (self),1,{},", count)
+        );
+
+        let path = path.with_extension("json");
+        write_samples(&path, &sides, Some(100)).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        let samples = json["samples"].as_array().unwrap();
+        assert_eq!(samples.len(), 200);
+        assert_eq!(samples[1]["duration_us"], count as i64 / 100);
+        std::fs::remove_file(path.with_extension("csv")).unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn both_sides_are_interleaved_by_filter() {
+        let run = |layout: i64, load: i64| {
+            RunResults::from([
+                (String::from("Layout"), vec![Duration::microseconds(layout)]),
+                (String::from("LoadPage"), vec![Duration::microseconds(load)]),
+            ])
+        };
+        let (old, new) = ([run(10, 100), run(11, 110)], [run(20, 200)]);
+        let points = [PointResults::from([(String::from("Nodes"), vec![5])])];
+        let sides = [
+            Side {
+                name: "old",
+                label: "a",
+                runs: &old,
+                points: &[],
+                spilled: &[],
+                numbers: &[1, 2],
+            },
+            Side {
+                name: "new",
+                label: "b",
+                runs: &new,
+                points: &points,
+                spilled: &[],
+                numbers: &[1],
+            },
+        ];
+        let path =
+            std::env::temp_dir().join(format!("hitrace-bench-{}-sides.csv", std::process::id()));
+        write_samples(&path, &sides, None).unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            csv.lines().collect::<Vec<_>>(),
+            [
+                "label,side,run,filter,duration_us,total_samples,value",
+                "a,old,1,Layout,10,1,",
+                "a,old,2,Layout,11,1,",
+                "b,new,1,Layout,20,1,",
+                "a,old,1,LoadPage,100,1,",
+                "a,old,2,LoadPage,110,1,",
+                "b,new,1,LoadPage,200,1,",
+                "b,new,1,Nodes,,,5",
+            ]
+        );
+    }
+}
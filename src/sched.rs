@@ -0,0 +1,211 @@
+//! Scheduling of the threads from the `sched_switch` and `sched_wakeup` lines of the `sched` category
+use std::collections::HashMap;
+use time::Duration;
+
+use crate::{
+    span::Span,
+    trace::{TimeStamp, difference_of_timestamps},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+/// What the scheduler did
+pub enum SchedKind {
+    /// The cpu switched from one thread to the next
+    Switch {
+        /// The thread that stopped running
+        prev_tid: u64,
+        /// The state it left in, `R` or `R+` if it is still runnable, i.e., preempted
+        prev_state: String,
+        /// The thread that runs now
+        next_tid: u64,
+    },
+    /// The thread became runnable
+    Wakeup {
+        /// The woken thread
+        tid: u64,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A scheduler event
+pub struct SchedEvent {
+    /// The cpu it happened on
+    pub cpu: u64,
+    /// When it happened
+    pub timestamp: TimeStamp,
+    /// What happened
+    pub kind: SchedKind,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+/// Where the time of a span went
+pub struct SchedStats {
+    /// Time the thread was running on a cpu
+    pub running: Duration,
+    /// Time the thread was runnable but waiting for a cpu
+    pub runnable: Duration,
+    /// Time the thread was sleeping or waiting for io
+    pub blocked: Duration,
+    /// How often the thread was switched out
+    pub switches: u32,
+}
+
+#[derive(Clone, Copy)]
+enum State {
+    Running,
+    Runnable,
+    Blocked,
+}
+
+/// The scheduler events of every thread in time order
+pub struct SchedIndex<'a> {
+    by_thread: HashMap<u64, Vec<&'a SchedEvent>>,
+}
+
+impl<'a> SchedIndex<'a> {
+    /// Index the events of a dump
+    pub fn new(events: &'a [SchedEvent]) -> Self {
+        let mut by_thread: HashMap<u64, Vec<&SchedEvent>> = HashMap::new();
+        for event in events {
+            match &event.kind {
+                SchedKind::Switch {
+                    prev_tid, next_tid, ..
+                } => {
+                    by_thread.entry(*prev_tid).or_default().push(event);
+                    if next_tid != prev_tid {
+                        by_thread.entry(*next_tid).or_default().push(event);
+                    }
+                }
+                SchedKind::Wakeup { tid } => by_thread.entry(*tid).or_default().push(event),
+            }
+        }
+        for thread in by_thread.values_mut() {
+            thread.sort_by_key(|e| e.timestamp);
+        }
+        SchedIndex { by_thread }
+    }
+
+    /// Go through the states of the thread of the span, `slice` gets every state with the cpu the thread was
+    /// last running on and how long it lasted, `switched_out` is called when the thread leaves the cpu
+    fn walk(
+        &self,
+        span: &Span,
+        mut slice: impl FnMut(State, u64, Duration),
+        mut switched_out: impl FnMut(),
+    ) {
+        let tid = span.start.tid;
+        let (start, end) = (span.start.timestamp, span.end.timestamp);
+        let mut state = State::Running;
+        let mut cpu = span.start.cpu;
+        let mut since = start;
+        let events = self.by_thread.get(&tid).map_or(&[][..], |e| e.as_slice());
+        let first = events.partition_point(|e| e.timestamp <= start);
+        for event in events[first..].iter().take_while(|e| e.timestamp < end) {
+            let next = match &event.kind {
+                SchedKind::Switch {
+                    prev_tid,
+                    prev_state,
+                    ..
+                } if *prev_tid == tid => {
+                    switched_out();
+                    if prev_state.starts_with('R') {
+                        State::Runnable
+                    } else {
+                        State::Blocked
+                    }
+                }
+                SchedKind::Switch { .. } => State::Running,
+                SchedKind::Wakeup { .. } => match state {
+                    State::Blocked => State::Runnable,
+                    other => other,
+                },
+            };
+            slice(
+                state,
+                cpu,
+                difference_of_timestamps(&event.timestamp, &since),
+            );
+            if matches!(event.kind, SchedKind::Switch { .. }) {
+                cpu = event.cpu;
+            }
+            state = next;
+            since = event.timestamp;
+        }
+        slice(state, cpu, difference_of_timestamps(&end, &since));
+    }
+
+    /// Where the time of the span went on its thread. The thread runs when the span starts as it writes the trace.
+    /// The time of async spans is only accounted on the thread that started them
+    pub fn stats(&self, span: &Span) -> SchedStats {
+        let mut stats = SchedStats::default();
+        let mut switches = 0;
+        self.walk(
+            span,
+            |state, _cpu, duration| stats.add(state, duration),
+            || switches += 1,
+        );
+        stats.switches = switches;
+        stats
+    }
+
+    /// The time the thread of the span was running on every cpu during the span, like [`SchedIndex::stats`]
+    pub fn running_on_cpus(&self, span: &Span) -> HashMap<u64, Duration> {
+        let mut cpus: HashMap<u64, Duration> = HashMap::new();
+        self.walk(
+            span,
+            |state, cpu, duration| {
+                if matches!(state, State::Running) {
+                    *cpus.entry(cpu).or_default() += duration;
+                }
+            },
+            || {},
+        );
+        cpus
+    }
+}
+
+impl SchedStats {
+    fn add(&mut self, state: State, duration: Duration) {
+        match state {
+            State::Running => self.running += duration,
+            State::Runnable => self.runnable += duration,
+            State::Blocked => self.blocked += duration,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse::parse_report, span::find_all_spans};
+
+    #[test]
+    fn span_time_is_split_by_thread_state() {
+        let dump = " org.servo.servo-44962   (  44682) [010] .... 17864.000100: tracing_mark_write: B|44682|H: Layout
+ <idle>-0     (-----) [010] d..2 17864.000200: sched_switch: prev_comm=org.servo.servo prev_pid=44962 prev_prio=120 prev_state=S ==> next_comm=swapper/10 next_pid=0 next_prio=120
+ kworker/3:1-123   (  123) [003] d..4 17864.000500: sched_wakeup: comm=org.servo.servo pid=44962 prio=120 target_cpu=010
+ <idle>-0     (-----) [010] d..2 17864.000600: sched_switch: prev_comm=swapper/10 prev_pid=0 prev_prio=120 prev_state=R ==> next_comm=org.servo.servo next_pid=44962 next_prio=120
+ org.servo.servo-44962   (  44682) [010] d..2 17864.000700: sched_switch: prev_comm=org.servo.servo prev_pid=44962 prev_prio=120 prev_state=R+ ==> next_comm=RenderThread next_pid=44970 next_prio=120
+ RenderThread-44970   (  44682) [010] d..2 17864.000800: sched_switch: prev_comm=RenderThread prev_pid=44970 prev_prio=120 prev_state=S ==> next_comm=org.servo.servo next_pid=44962 next_prio=120
+ org.servo.servo-44962   (  44682) [010] .... 17864.001000: tracing_mark_write: E|44682|H:
+";
+        let report = parse_report(dump.as_bytes(), "servo").unwrap();
+        assert_eq!(report.sched.len(), 5);
+        let (spans, _integrity) = find_all_spans(&report.traces);
+        let stats = SchedIndex::new(&report.sched).stats(&spans[0]);
+        assert_eq!(
+            stats,
+            SchedStats {
+                running: Duration::microseconds(100 + 100 + 200),
+                runnable: Duration::microseconds(100 + 100),
+                blocked: Duration::microseconds(300),
+                switches: 2,
+            }
+        );
+        // it was switched back in by cpu 10 both times
+        assert_eq!(
+            SchedIndex::new(&report.sched).running_on_cpus(&spans[0]),
+            HashMap::from([(10, Duration::microseconds(400))])
+        );
+    }
+}
@@ -0,0 +1,87 @@
+//! A single score combining several filters
+use anyhow::{Result, anyhow};
+
+use crate::{RunResults, utils::avg_min_max};
+
+#[derive(Debug, Clone)]
+/// A filter taking part in the score
+pub(crate) struct ScoreComponent {
+    /// Name of the filter
+    pub(crate) filter: String,
+    /// Weight of the filter in the geometric mean
+    pub(crate) weight: f64,
+    /// The average of the filter is divided by this reference in milliseconds, i.e., a baseline or budget
+    pub(crate) reference_ms: f64,
+}
+
+/// Parse a score component of the form `filter:weight:reference_ms`
+pub(crate) fn parse_score_component(s: &str) -> Result<ScoreComponent, String> {
+    let mut parts = s.rsplitn(3, ':');
+    let (Some(reference), Some(weight), Some(filter)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(format!(
+            "Score component {} is not of the form filter:weight:reference_ms",
+            s
+        ));
+    };
+    let weight = weight
+        .parse::<f64>()
+        .map_err(|e| format!("Invalid weight in {}: {}", s, e))?;
+    let reference_ms = reference
+        .parse::<f64>()
+        .map_err(|e| format!("Invalid reference in {}: {}", s, e))?;
+    if weight <= 0.0 || reference_ms <= 0.0 {
+        return Err(format!("Weight and reference of {} need to be positive", s));
+    }
+    Ok(ScoreComponent {
+        filter: filter.to_owned(),
+        weight,
+        reference_ms,
+    })
+}
+
+/// The command line form of a component given as a table in the config file,
+/// i.e., `{ filter = "Layout", weight = 2, reference-ms = 16 }`
+pub(crate) fn component_arg(table: &toml::Table) -> Result<String, String> {
+    let number = |key: &str| match table.get(key) {
+        Some(toml::Value::Integer(i)) => Ok(*i as f64),
+        Some(toml::Value::Float(f)) => Ok(*f),
+        _ => Err(format!(
+            "The score component {} needs a number {}",
+            table, key
+        )),
+    };
+    let filter = table
+        .get("filter")
+        .and_then(|f| f.as_str())
+        .ok_or(format!("The score component {} needs a filter", table))?;
+    let component = format!(
+        "{}:{}:{}",
+        filter,
+        number("weight")?,
+        number("reference-ms")?
+    );
+    parse_score_component(&component)?;
+    Ok(component)
+}
+
+/// The weighted geometric mean of the normalized averages of the components.
+/// Fails if a component has no results, as a partial score is not comparable.
+pub(crate) fn score(results: &RunResults, components: &[ScoreComponent]) -> Result<f64> {
+    let mut weighted_log_sum = 0.0;
+    let mut weight_sum = 0.0;
+    for component in components {
+        let avg = results
+            .get(component.filter.as_str())
+            .and_then(|v| avg_min_max(v).ok())
+            .ok_or(anyhow!("{} has no results", component.filter))?
+            .avg;
+        let normalized = avg.as_seconds_f64() * 1000.0 / component.reference_ms;
+        if normalized <= 0.0 {
+            return Err(anyhow!("{} has no positive average", component.filter));
+        }
+        weighted_log_sum += component.weight * normalized.ln();
+        weight_sum += component.weight;
+    }
+    Ok((weighted_log_sum / weight_sum).exp())
+}
@@ -0,0 +1,243 @@
+//! Tests whether two sets of samples differ by more than noise
+use std::f64::consts::PI;
+
+/// Welch's t-test of the difference of the means, which does not assume equal variances
+pub(crate) struct Welch {
+    /// Mean of the new samples minus the mean of the old samples
+    pub(crate) difference: f64,
+    /// Two-sided p-value
+    pub(crate) p: f64,
+    /// Confidence interval of the difference
+    pub(crate) interval: (f64, f64),
+}
+
+/// Mean and sample variance, None for fewer than two values
+fn mean_variance(values: &[f64]) -> Option<(f64, f64)> {
+    if values.len() < 2 {
+        return None;
+    }
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    Some((mean, variance))
+}
+
+/// Welch's t-test with the confidence interval at `confidence` in (0, 1), None if a side has fewer than two samples
+pub(crate) fn welch(old: &[f64], new: &[f64], confidence: f64) -> Option<Welch> {
+    let (mean_old, var_old) = mean_variance(old)?;
+    let (mean_new, var_new) = mean_variance(new)?;
+    let (se_old, se_new) = (var_old / old.len() as f64, var_new / new.len() as f64);
+    let se = (se_old + se_new).sqrt();
+    let difference = mean_new - mean_old;
+    if se == 0.0 {
+        // both sides are constant, they differ for sure or not at all
+        return Some(Welch {
+            difference,
+            p: if difference == 0.0 { 1.0 } else { 0.0 },
+            interval: (difference, difference),
+        });
+    }
+    // Welch–Satterthwaite degrees of freedom
+    let df = (se_old + se_new).powi(2)
+        / (se_old.powi(2) / (old.len() - 1) as f64 + se_new.powi(2) / (new.len() - 1) as f64);
+    let t = difference / se;
+    let margin = t_quantile(1.0 - confidence, df) * se;
+    Some(Welch {
+        difference,
+        p: t_two_sided(t, df),
+        interval: (difference - margin, difference + margin),
+    })
+}
+
+/// Two-sided p-value of the Mann–Whitney U test with the normal approximation and tie correction,
+/// None if a side has no samples
+pub(crate) fn mann_whitney(old: &[f64], new: &[f64]) -> Option<f64> {
+    if old.is_empty() || new.is_empty() {
+        return None;
+    }
+    let mut all = old
+        .iter()
+        .map(|v| (*v, false))
+        .chain(new.iter().map(|v| (*v, true)))
+        .collect::<Vec<_>>();
+    all.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let n = all.len() as f64;
+    let mut rank_sum_new = 0.0;
+    let mut ties = 0.0;
+    let mut i = 0;
+    while i < all.len() {
+        let j = i + all[i..].iter().take_while(|v| v.0 == all[i].0).count();
+        // tied values share the average of their ranks, which start at 1
+        let rank = (i + j + 1) as f64 / 2.0;
+        rank_sum_new += rank * all[i..j].iter().filter(|v| v.1).count() as f64;
+        let tied = (j - i) as f64;
+        ties += tied.powi(3) - tied;
+        i = j;
+    }
+    let (n_old, n_new) = (old.len() as f64, new.len() as f64);
+    let u = rank_sum_new - n_new * (n_new + 1.0) / 2.0;
+    let mean = n_old * n_new / 2.0;
+    let variance = n_old * n_new / 12.0 * ((n + 1.0) - ties / (n * (n - 1.0)));
+    if variance <= 0.0 {
+        return Some(1.0);
+    }
+    // continuity correction towards the mean
+    let z = ((u - mean).abs() - 0.5).max(0.0) / variance.sqrt();
+    Some(erfc(z / 2f64.sqrt()))
+}
+
+/// Two-sided p-value of the t statistic with `df` degrees of freedom
+fn t_two_sided(t: f64, df: f64) -> f64 {
+    incomplete_beta(df / (df + t * t), df / 2.0, 0.5)
+}
+
+/// The t value whose two-sided p-value is `alpha`, found by bisection
+fn t_quantile(alpha: f64, df: f64) -> f64 {
+    let (mut low, mut high) = (0.0, 1e6);
+    for _ in 0..200 {
+        let mid = (low + high) / 2.0;
+        if t_two_sided(mid, df) > alpha {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    (low + high) / 2.0
+}
+
+/// Logarithm of the gamma function with the Lanczos approximation
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+    if x < 0.5 {
+        // reflection formula
+        return (PI / (PI * x).sin()).ln() - ln_gamma(1.0 - x);
+    }
+    let x = x - 1.0;
+    let t = x + 7.5;
+    let sum = COEFFICIENTS[1..]
+        .iter()
+        .enumerate()
+        .fold(COEFFICIENTS[0], |sum, (i, c)| {
+            sum + c / (x + i as f64 + 1.0)
+        });
+    0.5 * (2.0 * PI).ln() + (x + 0.5) * t.ln() - t + sum.ln()
+}
+
+/// The regularized incomplete beta function I_x(a, b)
+fn incomplete_beta(x: f64, a: f64, b: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+    let front =
+        (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln()).exp();
+    // the continued fraction converges fast below this point, above it the symmetry is used
+    if x < (a + 1.0) / (a + b + 2.0) {
+        front * beta_fraction(x, a, b) / a
+    } else {
+        1.0 - front * beta_fraction(1.0 - x, b, a) / b
+    }
+}
+
+/// Continued fraction of the incomplete beta function with the modified Lentz method
+fn beta_fraction(x: f64, a: f64, b: f64) -> f64 {
+    const TINY: f64 = 1e-300;
+    let mut c = 1.0;
+    let mut d = 1.0 - (a + b) * x / (a + 1.0);
+    if d.abs() < TINY {
+        d = TINY;
+    }
+    d = 1.0 / d;
+    let mut fraction = d;
+    for m in 1..300 {
+        let m = m as f64;
+        for numerator in [
+            m * (b - m) * x / ((a + 2.0 * m - 1.0) * (a + 2.0 * m)),
+            -(a + m) * (a + b + m) * x / ((a + 2.0 * m) * (a + 2.0 * m + 1.0)),
+        ] {
+            d = 1.0 + numerator * d;
+            if d.abs() < TINY {
+                d = TINY;
+            }
+            c = 1.0 + numerator / c;
+            if c.abs() < TINY {
+                c = TINY;
+            }
+            d = 1.0 / d;
+            fraction *= d * c;
+        }
+        if (d * c - 1.0).abs() < 1e-15 {
+            break;
+        }
+    }
+    fraction
+}
+
+/// The complementary error function, with a relative error below 1.2e-7
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let polynomial = [
+        -1.265_512_23,
+        1.000_023_68,
+        0.374_091_96,
+        0.096_784_18,
+        -0.186_288_06,
+        0.278_868_07,
+        -1.135_203_98,
+        1.488_515_87,
+        -0.822_152_23,
+        0.170_872_77,
+    ]
+    .iter()
+    .rev()
+    .fold(0.0, |sum, c| sum * t + c);
+    let value = t * (-z * z + polynomial).exp();
+    if x >= 0.0 { value } else { 2.0 - value }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64, tolerance: f64) -> bool {
+        (a - b).abs() < tolerance
+    }
+
+    #[test]
+    fn t_distribution_matches_tables() {
+        // with one degree of freedom it is the cauchy distribution
+        assert!(close(t_two_sided(1.0, 1.0), 0.5, 1e-9));
+        assert!(close(t_quantile(0.05, 10.0), 2.228_139, 1e-5));
+        assert!(close(t_quantile(0.05, 1e9), 1.959_964, 1e-5));
+        assert!(close(erfc(1.959_964 / 2f64.sqrt()), 0.05, 1e-6));
+    }
+
+    #[test]
+    fn separated_samples_are_significant() {
+        let old = [10.0, 11.0, 12.0, 10.5, 11.5];
+        let new = [20.0, 21.0, 22.0, 20.5, 21.5];
+        let separated = welch(&old, &new, 0.95).unwrap();
+        assert_eq!(separated.difference, 10.0);
+        assert!(separated.p < 1e-6);
+        assert!(separated.interval.0 > 8.0 && separated.interval.1 < 12.0);
+        assert!(mann_whitney(&old, &new).unwrap() < 0.05);
+
+        let same = welch(&old, &old, 0.95).unwrap();
+        assert!(close(same.p, 1.0, 1e-9));
+        assert!(same.interval.0 < 0.0 && same.interval.1 > 0.0);
+        assert!(close(mann_whitney(&old, &old).unwrap(), 1.0, 1e-6));
+    }
+}
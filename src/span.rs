@@ -0,0 +1,791 @@
+//! Matching start and end traces into spans
+use std::{
+    borrow::Borrow,
+    cmp::Reverse,
+    collections::{HashMap, VecDeque},
+};
+use time::Duration;
+
+use crate::{
+    error::BenchError,
+    matcher::Matcher,
+    parse::{Event, LostRegion},
+    trace::{TimeStamp, Trace, TraceMarker, difference_of_timestamps, difference_of_traces},
+};
+
+#[derive(Debug)]
+/// A span given by a start trace and its matching end trace
+pub struct Span<'a> {
+    /// The trace opening the span
+    pub start: &'a Trace,
+    /// The trace closing the span
+    pub end: &'a Trace,
+    /// Number of spans with the same name open on the thread, including this one. Always 1 for async spans
+    pub recursion: usize,
+    /// Wall duration minus the time of the sync spans directly nested in it. The full duration for async spans
+    pub self_duration: Duration,
+}
+
+impl Span<'_> {
+    /// The function name of the span without surrounding whitespace
+    pub fn name(&self) -> &str {
+        self.start.function.trim()
+    }
+
+    /// Wall duration of the span
+    pub fn duration(&self) -> Duration {
+        difference_of_traces(self.end, self.start)
+    }
+
+    /// Async spans are not nested in the sync spans of their thread
+    pub fn is_async(&self) -> bool {
+        matches!(self.start.trace_marker, TraceMarker::StartAsync)
+    }
+
+    /// An async span that started in one process and ended in another
+    pub fn is_stitched(&self) -> bool {
+        self.start.pid != self.end.pid
+    }
+
+    /// A copy of the span that does not borrow the traces
+    pub fn to_owned_span(&self) -> OwnedSpan {
+        OwnedSpan {
+            name: self.name().to_owned(),
+            pid: self.start.pid,
+            tid: self.start.tid,
+            start: self.start.timestamp,
+            end: self.end.timestamp,
+            recursion: self.recursion,
+            self_duration: self.self_duration,
+            is_async: self.is_async(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A span that owns its data, for keeping spans after the traces are gone
+pub struct OwnedSpan {
+    /// The function name without surrounding whitespace
+    pub name: String,
+    /// The process of the start trace
+    pub pid: u64,
+    /// The thread of the start trace, async spans can end on another thread
+    pub tid: u64,
+    /// Time of the start trace
+    pub start: TimeStamp,
+    /// Time of the end trace
+    pub end: TimeStamp,
+    /// See [`Span::recursion`]
+    pub recursion: usize,
+    /// See [`Span::self_duration`]
+    pub self_duration: Duration,
+    /// See [`Span::is_async`]
+    pub is_async: bool,
+}
+
+impl OwnedSpan {
+    /// Wall duration of the span
+    pub fn duration(&self) -> Duration {
+        difference_of_timestamps(&self.end, &self.start)
+    }
+}
+
+#[derive(Debug, Default)]
+/// The markers that could not be paired into spans
+pub struct SpanIntegrity {
+    /// `EndSync` without an open `StartSync` on the thread and `EndAsync` without an open `StartAsync`
+    pub unmatched_ends: u32,
+    /// `StartSync` and `StartAsync` that were still open at the end of the trace, by function name
+    pub unterminated: HashMap<String, u32>,
+    /// Spans that were dropped because events are missing between their start and end
+    pub lost: u32,
+}
+
+impl SpanIntegrity {
+    /// Number of starts that were still open at the end of the trace
+    pub fn unterminated_starts(&self) -> u32 {
+        self.unterminated.values().sum()
+    }
+}
+
+/// Find all sync and async spans in the traces.
+/// A `EndSync` closes the last opened `StartSync` on the same thread.
+/// A `EndAsync` closes the oldest open `StartAsync` of the same process with the same function and cookie,
+/// the cookie is in the number field. Async spans can end on any thread or cpu, but not in another process,
+/// see [`find_handoffs`] for those.
+/// Spans are returned in the order they are closed.
+/// Direct children of a sync span cannot overlap as they are closed in stack order, so the self time
+/// subtracts their summed duration.
+pub fn find_all_spans(traces: &[Trace]) -> (Vec<Span<'_>>, SpanIntegrity) {
+    find_intact_spans(traces, &[])
+}
+
+/// Find the async spans that start in one process and end in another, i.e., started by the content process
+/// and ended by the compositor. A `EndAsync` without an open `StartAsync` of its own process closes the
+/// oldest open one of another process with the same function and cookie. Spans containing a lost region
+/// are dropped like in [`find_intact_spans`].
+/// This is a separate pass as cookies are only unique within a process, the default matching would pair
+/// unrelated spans of different processes.
+pub fn find_handoffs<'a>(traces: &'a [Trace], lost: &[LostRegion]) -> Vec<Span<'a>> {
+    let lost_before = |index: usize| lost.partition_point(|r| r.position <= index);
+    // the open starts of all processes by function and cookie, oldest first
+    let mut open: HashMap<(&str, &str), VecDeque<(&Trace, usize)>> = HashMap::new();
+    let mut handoffs = Vec::new();
+    for (index, trace) in traces.iter().enumerate() {
+        let key = (trace.function.trim(), trace.number.as_str());
+        match trace.trace_marker {
+            TraceMarker::StartAsync => open
+                .entry(key)
+                .or_default()
+                .push_back((trace, lost_before(index))),
+            TraceMarker::EndAsync => {
+                let Some(queue) = open.get_mut(&key) else {
+                    continue;
+                };
+                if let Some(same) = queue.iter().position(|(start, _)| start.pid == trace.pid) {
+                    queue.remove(same);
+                    continue;
+                }
+                if let Some((start, start_lost)) = queue.pop_front()
+                    && start_lost == lost_before(index)
+                {
+                    handoffs.push(Span {
+                        start,
+                        end: trace,
+                        recursion: 1,
+                        self_duration: difference_of_traces(trace, start),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    handoffs
+}
+
+/// A sync start with the number of lost regions before it and the time of its closed children
+type OpenSync<T> = (T, usize, Duration);
+/// An async start with the number of lost regions before it
+type OpenAsync<T> = (T, usize);
+/// A start and the end closing it with its recursion and self duration
+type Closed<T> = (T, T, usize, Duration);
+
+/// The open starts while going through the traces, the traces are borrowed or owned
+struct OpenSpans<T> {
+    /// The open starts of every thread
+    sync: HashMap<(u64, u64), Vec<OpenSync<T>>>,
+    /// The open async starts by process, function and cookie, oldest first
+    async_starts: HashMap<(u64, String, String), VecDeque<OpenAsync<T>>>,
+    integrity: SpanIntegrity,
+}
+
+impl<T: Borrow<Trace>> OpenSpans<T> {
+    fn new() -> Self {
+        OpenSpans {
+            sync: HashMap::new(),
+            async_starts: HashMap::new(),
+            integrity: SpanIntegrity::default(),
+        }
+    }
+
+    /// Take the next trace, `lost` lost regions are before it. Returns the span it closes.
+    fn push(&mut self, trace: T, lost: usize) -> Option<Closed<T>> {
+        let t = trace.borrow();
+        match t.trace_marker {
+            TraceMarker::StartSync => {
+                self.sync
+                    .entry((t.pid, t.tid))
+                    .or_default()
+                    .push((trace, lost, Duration::ZERO));
+                None
+            }
+            TraceMarker::EndSync => {
+                let stack = self.sync.entry((t.pid, t.tid)).or_default();
+                let Some((start, start_lost, children)) = stack.pop() else {
+                    self.integrity.unmatched_ends += 1;
+                    return None;
+                };
+                if start_lost != lost {
+                    self.integrity.lost += 1;
+                    return None;
+                }
+                let function = start.borrow().function.trim();
+                let recursion = stack
+                    .iter()
+                    .filter(|(t, _, _)| t.borrow().function.trim() == function)
+                    .count()
+                    + 1;
+                let duration = difference_of_traces(t, start.borrow());
+                if let Some((_parent, _lost, parent_children)) = stack.last_mut() {
+                    *parent_children += duration;
+                }
+                Some((start, trace, recursion, duration - children))
+            }
+            TraceMarker::StartAsync => {
+                self.async_starts
+                    .entry((t.pid, t.function.trim().to_owned(), t.number.clone()))
+                    .or_default()
+                    .push_back((trace, lost));
+                None
+            }
+            TraceMarker::EndAsync => {
+                let Some((start, start_lost)) = self
+                    .async_starts
+                    .get_mut(&(t.pid, t.function.trim().to_owned(), t.number.clone()))
+                    .and_then(|queue| queue.pop_front())
+                else {
+                    self.integrity.unmatched_ends += 1;
+                    return None;
+                };
+                if start_lost != lost {
+                    self.integrity.lost += 1;
+                    return None;
+                }
+                let duration = difference_of_traces(t, start.borrow());
+                Some((start, trace, 1, duration))
+            }
+            _ => None,
+        }
+    }
+
+    /// Count the starts that are still open
+    fn finish(mut self) -> SpanIntegrity {
+        for start in self
+            .sync
+            .into_values()
+            .flatten()
+            .map(|(start, _lost, _children)| start)
+            .chain(
+                self.async_starts
+                    .into_values()
+                    .flatten()
+                    .map(|(start, _lost)| start),
+            )
+        {
+            *self
+                .integrity
+                .unterminated
+                .entry(start.borrow().function.trim().to_owned())
+                .or_default() += 1;
+        }
+        self.integrity
+    }
+}
+
+impl<'a> OpenSpans<&'a Trace> {
+    /// Close the open starts at `end` as if it ended all of them, innermost first.
+    /// They still count as unterminated, the ones containing a lost region are dropped.
+    fn close_truncated(
+        self,
+        end: &'a Trace,
+        lost: usize,
+    ) -> (Vec<Closed<&'a Trace>>, SpanIntegrity) {
+        let mut closed = Vec::new();
+        let mut unterminated = Vec::new();
+        for mut stack in self.sync.into_values() {
+            while let Some((start, start_lost, children)) = stack.pop() {
+                unterminated.push(start);
+                if start_lost != lost {
+                    continue;
+                }
+                let function = start.function.trim();
+                let recursion = stack
+                    .iter()
+                    .filter(|(t, _, _)| t.function.trim() == function)
+                    .count()
+                    + 1;
+                let duration = difference_of_traces(end, start);
+                if let Some((_parent, _lost, parent_children)) = stack.last_mut() {
+                    *parent_children += duration;
+                }
+                closed.push((start, end, recursion, duration - children));
+            }
+        }
+        for (start, start_lost) in self.async_starts.into_values().flatten() {
+            unterminated.push(start);
+            if start_lost == lost {
+                closed.push((start, end, 1, difference_of_traces(end, start)));
+            }
+        }
+        let mut integrity = self.integrity;
+        for start in unterminated {
+            *integrity
+                .unterminated
+                .entry(start.function.trim().to_owned())
+                .or_default() += 1;
+        }
+        (closed, integrity)
+    }
+}
+
+/// Find the spans like [`find_all_spans`], but drop the spans that contain a lost region.
+/// Their end can pair with the wrong start and their duration includes time we know nothing about.
+pub fn find_intact_spans<'a>(
+    traces: &'a [Trace],
+    lost: &[LostRegion],
+) -> (Vec<Span<'a>>, SpanIntegrity) {
+    find_spans(traces, lost, false)
+}
+
+/// Find the spans like [`find_intact_spans`], and end the unterminated spans at the last trace.
+/// They are still counted as unterminated, their duration is how long they ran until the trace was cut off.
+pub fn find_spans_including_truncated<'a>(
+    traces: &'a [Trace],
+    lost: &[LostRegion],
+) -> (Vec<Span<'a>>, SpanIntegrity) {
+    find_spans(traces, lost, true)
+}
+
+fn find_spans<'a>(
+    traces: &'a [Trace],
+    lost: &[LostRegion],
+    include_truncated: bool,
+) -> (Vec<Span<'a>>, SpanIntegrity) {
+    // the number of lost regions before the trace at the index
+    let lost_before = |index: usize| lost.partition_point(|r| r.position <= index);
+    let mut open = OpenSpans::new();
+    let mut closed = Vec::new();
+    for (index, trace) in traces.iter().enumerate() {
+        closed.extend(open.push(trace, lost_before(index)));
+    }
+    let integrity = match traces.last() {
+        Some(last) if include_truncated => {
+            let (mut truncated, integrity) =
+                open.close_truncated(last, lost_before(traces.len() - 1));
+            // the starts come out of hash maps, inner spans start later and close first
+            truncated.sort_by_key(|(start, ..)| (Reverse(start.timestamp), start.pid, start.tid));
+            closed.extend(truncated);
+            integrity
+        }
+        _ => open.finish(),
+    };
+    let spans = closed
+        .into_iter()
+        .map(|(start, end, recursion, self_duration)| Span {
+            start,
+            end,
+            recursion,
+            self_duration,
+        })
+        .collect();
+    (spans, integrity)
+}
+
+/// Finds the spans of a stream of events like [`find_intact_spans`], keeping only the open starts in memory
+///
+/// ```
+/// use hitrace_bench::{parse::TraceReader, span::SpanStream};
+///
+/// let dump = " org.servo.servo-44962   (  44682) [010] .... 17864.716645: tracing_mark_write: B|44682|H: Layout
+///  org.servo.servo-44962   (  44682) [010] .... 17864.716945: tracing_mark_write: E|44682|H:
+/// ";
+/// let mut stream = SpanStream::new();
+/// let mut spans = Vec::new();
+/// for event in TraceReader::new(dump.as_bytes(), "servo")? {
+///     spans.extend(stream.push(event?));
+/// }
+/// assert_eq!(spans[0].name, "Layout");
+/// assert_eq!(spans[0].duration(), time::Duration::microseconds(300));
+/// assert_eq!(stream.finish().unterminated_starts(), 0);
+/// # Ok::<(), hitrace_bench::BenchError>(())
+/// ```
+pub struct SpanStream {
+    open: OpenSpans<Trace>,
+    /// Number of lost regions seen so far
+    lost: usize,
+}
+
+impl Default for SpanStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SpanStream {
+    /// A stream without open spans
+    pub fn new() -> Self {
+        SpanStream {
+            open: OpenSpans::new(),
+            lost: 0,
+        }
+    }
+
+    /// Take the next event, returns the span its trace closes
+    pub fn push(&mut self, event: Event) -> Option<OwnedSpan> {
+        match event {
+            Event::Trace(trace) => {
+                let (start, end, recursion, self_duration) = self.open.push(trace, self.lost)?;
+                Some(OwnedSpan {
+                    name: start.function.trim().to_owned(),
+                    pid: start.pid,
+                    tid: start.tid,
+                    start: start.timestamp,
+                    end: end.timestamp,
+                    recursion,
+                    self_duration,
+                    is_async: matches!(start.trace_marker, TraceMarker::StartAsync),
+                })
+            }
+            Event::Lost(..) => {
+                self.lost += 1;
+                None
+            }
+            Event::Unreadable(_) | Event::Sched(_) => None,
+        }
+    }
+
+    /// The markers that could not be paired, starts still open now are unterminated
+    pub fn finish(self) -> SpanIntegrity {
+        self.open.finish()
+    }
+}
+
+/// The spans bucketed by function name, so every filter only looks at the spans it matches
+pub struct SpanIndex<'s, 'a> {
+    spans: &'s [Span<'a>],
+    by_name: HashMap<&'s str, Vec<usize>>,
+}
+
+impl<'s, 'a> SpanIndex<'s, 'a> {
+    /// Bucket the spans
+    pub fn new(spans: &'s [Span<'a>]) -> Self {
+        let mut by_name: HashMap<&str, Vec<usize>> = HashMap::new();
+        for (index, span) in spans.iter().enumerate() {
+            by_name.entry(span.name()).or_default().push(index);
+        }
+        SpanIndex { spans, by_name }
+    }
+
+    /// The spans whose name matches, in the order they were closed
+    pub fn matching(&self, matcher: &Matcher) -> Vec<&'s Span<'a>> {
+        let mut indices = match matcher {
+            Matcher::Exact(name) => self.by_name.get(name.as_str()).cloned().unwrap_or_default(),
+            _ => self
+                .by_name
+                .iter()
+                .filter(|(name, _indices)| matcher.matches(name))
+                .flat_map(|(_name, indices)| indices.iter().copied())
+                .collect(),
+        };
+        indices.sort_unstable();
+        indices.into_iter().map(|i| &self.spans[i]).collect()
+    }
+}
+
+/// Maximum and average recursion depth of spans with the same name, fails with [`BenchError::NoValues`]
+/// if there are no spans.
+/// The depth of a call is the deepest recursion below its outermost span, the average is over the outermost spans.
+/// Outermost spans on a thread cannot overlap, so one sweep over the spans sorted by start finds the outermost
+/// span every nested span belongs to.
+pub fn recursion_depth(spans: &[&Span]) -> Result<(usize, f64), BenchError> {
+    let mut by_thread: HashMap<(u64, u64), Vec<&Span>> = HashMap::new();
+    for span in spans {
+        by_thread
+            .entry((span.start.pid, span.start.tid))
+            .or_default()
+            .push(span);
+    }
+    let mut depths = Vec::new();
+    for thread in by_thread.values_mut() {
+        thread.sort_by_key(|s| (s.start.timestamp, s.recursion));
+        let mut outer_end = None;
+        for span in thread.iter() {
+            if span.recursion == 1 {
+                depths.push(1);
+                outer_end = Some(span.end.timestamp);
+            } else if outer_end.is_some_and(|end| span.end.timestamp <= end)
+                && let Some(depth) = depths.last_mut()
+            {
+                *depth = span.recursion.max(*depth);
+            }
+        }
+    }
+    let max = *depths.iter().max().ok_or(BenchError::NoValues)?;
+    Ok((
+        max,
+        depths.iter().sum::<usize>() as f64 / depths.len() as f64,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trace(trace_marker: TraceMarker, function: &str, micro: u64) -> Trace {
+        Trace {
+            name: String::from("org.servo.servo"),
+            pid: 1,
+            tid: 1,
+            cpu: 0,
+            timestamp: TimeStamp { seconds: 1, micro },
+            trace_marker,
+            number: String::from("1"),
+            shorthand: String::from("H"),
+            function: function.to_owned(),
+        }
+    }
+
+    fn async_trace(trace_marker: TraceMarker, cookie: &str, cpu: u64, micro: u64) -> Trace {
+        Trace {
+            cpu,
+            number: cookie.to_owned(),
+            ..trace(trace_marker, " ImageDecode", micro)
+        }
+    }
+
+    #[test]
+    fn more_starts_than_ends_are_unterminated() {
+        let traces = vec![
+            trace(TraceMarker::StartSync, " LoadPage", 0),
+            trace(TraceMarker::StartSync, " Layout", 1),
+            trace(TraceMarker::StartSync, " Style", 2),
+            trace(TraceMarker::EndSync, "", 3),
+        ];
+        let (spans, integrity) = find_all_spans(&traces);
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].name(), "Style");
+        assert_eq!(integrity.unmatched_ends, 0);
+        assert_eq!(integrity.unterminated_starts(), 2);
+        assert_eq!(integrity.unterminated.get("LoadPage"), Some(&1));
+        assert_eq!(integrity.unterminated.get("Layout"), Some(&1));
+    }
+
+    #[test]
+    fn start_as_last_trace_is_unterminated() {
+        let traces = vec![
+            trace(TraceMarker::StartSync, " Layout", 0),
+            trace(TraceMarker::EndSync, "", 1),
+            trace(TraceMarker::StartSync, " LoadPage", 2),
+        ];
+        let (spans, integrity) = find_all_spans(&traces);
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].name(), "Layout");
+        assert_eq!(integrity.unterminated.get("LoadPage"), Some(&1));
+    }
+
+    #[test]
+    fn async_spans_pair_by_cookie() {
+        let traces = vec![
+            async_trace(TraceMarker::StartAsync, "1", 0, 0),
+            async_trace(TraceMarker::StartAsync, "2", 0, 1),
+            async_trace(TraceMarker::EndAsync, "2", 3, 5),
+            async_trace(TraceMarker::EndAsync, "1", 4, 9),
+        ];
+        let (spans, integrity) = find_all_spans(&traces);
+        assert_eq!(spans.len(), 2);
+        assert!(spans.iter().all(|s| s.is_async()));
+        assert_eq!(spans[0].start.number, "2");
+        assert_eq!(spans[0].duration(), Duration::microseconds(4));
+        assert_eq!(spans[1].start.number, "1");
+        assert_eq!(spans[1].duration(), Duration::microseconds(9));
+        assert_eq!(integrity.unmatched_ends, 0);
+    }
+
+    #[test]
+    fn spans_containing_lost_events_are_dropped() {
+        let traces = vec![
+            trace(TraceMarker::StartSync, " LoadPage", 0),
+            trace(TraceMarker::StartSync, " Layout", 1),
+            trace(TraceMarker::EndSync, "", 2),
+            // events are lost here
+            trace(TraceMarker::StartSync, " Paint", 3),
+            trace(TraceMarker::EndSync, "", 4),
+            trace(TraceMarker::EndSync, "", 5),
+        ];
+        let lost = [LostRegion {
+            line: 4,
+            position: 3,
+            events: Some(10),
+        }];
+        let (spans, integrity) = find_intact_spans(&traces, &lost);
+        let names = spans.iter().map(|s| s.name()).collect::<Vec<_>>();
+        assert_eq!(names, ["Layout", "Paint"]);
+        assert_eq!(integrity.lost, 1);
+        assert_eq!(integrity.unterminated_starts(), 0);
+    }
+
+    #[test]
+    fn truncated_spans_end_at_the_last_trace() {
+        let traces = vec![
+            trace(TraceMarker::StartSync, " LoadPage", 0),
+            trace(TraceMarker::StartSync, " Layout", 1),
+            trace(TraceMarker::StartSync, " Paint", 2),
+            trace(TraceMarker::EndSync, "", 4),
+            trace(TraceMarker::Dot, " mem 1", 10),
+        ];
+        let (spans, integrity) = find_spans_including_truncated(&traces, &[]);
+        let names = spans.iter().map(|s| s.name()).collect::<Vec<_>>();
+        assert_eq!(names, ["Paint", "Layout", "LoadPage"]);
+        assert_eq!(spans[1].duration(), Duration::microseconds(9));
+        assert_eq!(spans[1].self_duration, Duration::microseconds(7));
+        assert_eq!(spans[2].self_duration, Duration::microseconds(1));
+        assert_eq!(integrity.unterminated_starts(), 2);
+        assert_eq!(find_intact_spans(&traces, &[]).0.len(), 1);
+    }
+
+    #[test]
+    fn async_end_without_start_is_counted() {
+        let traces = vec![
+            async_trace(TraceMarker::StartAsync, "1", 0, 0),
+            async_trace(TraceMarker::EndAsync, "2", 0, 1),
+        ];
+        let (spans, integrity) = find_all_spans(&traces);
+        assert!(spans.is_empty());
+        assert_eq!(integrity.unmatched_ends, 1);
+        assert_eq!(integrity.unterminated_starts(), 1);
+    }
+
+    #[test]
+    fn async_handoffs_are_stitched_across_processes() {
+        let traces = vec![
+            async_trace(TraceMarker::StartAsync, "1", 0, 0),
+            Trace {
+                pid: 2,
+                ..async_trace(TraceMarker::StartAsync, "1", 0, 1)
+            },
+            async_trace(TraceMarker::EndAsync, "1", 0, 2),
+            Trace {
+                pid: 3,
+                ..async_trace(TraceMarker::EndAsync, "1", 0, 5)
+            },
+        ];
+        let (spans, integrity) = find_all_spans(&traces);
+        assert_eq!(spans.len(), 1);
+        assert!(!spans[0].is_stitched());
+        assert_eq!(spans[0].duration(), Duration::microseconds(2));
+        assert_eq!(integrity.unmatched_ends, 1);
+        assert_eq!(integrity.unterminated_starts(), 1);
+        let handoffs = find_handoffs(&traces, &[]);
+        assert_eq!(handoffs.len(), 1);
+        assert!(handoffs[0].is_stitched());
+        assert_eq!((handoffs[0].start.pid, handoffs[0].end.pid), (2, 3));
+        assert_eq!(handoffs[0].duration(), Duration::microseconds(4));
+    }
+
+    #[test]
+    fn self_time_subtracts_direct_children_once() {
+        let traces = vec![
+            trace(TraceMarker::StartSync, " LoadPage", 0),
+            trace(TraceMarker::StartSync, " Parse", 1),
+            trace(TraceMarker::EndSync, "", 3),
+            trace(TraceMarker::StartSync, " Layout", 4),
+            trace(TraceMarker::StartSync, " Style", 5),
+            trace(TraceMarker::EndSync, "", 7),
+            trace(TraceMarker::EndSync, "", 8),
+            trace(TraceMarker::EndSync, "", 10),
+        ];
+        let (spans, _integrity) = find_all_spans(&traces);
+        let self_time = |name: &str| {
+            spans
+                .iter()
+                .find(|s| s.name() == name)
+                .map(|s| s.self_duration)
+        };
+        assert_eq!(self_time("LoadPage"), Some(Duration::microseconds(4)));
+        assert_eq!(self_time("Layout"), Some(Duration::microseconds(2)));
+        assert_eq!(self_time("Style"), Some(Duration::microseconds(2)));
+    }
+
+    #[test]
+    fn index_groups_matching_names_in_closing_order() {
+        let traces = vec![
+            trace(TraceMarker::StartSync, " Layout", 0),
+            trace(TraceMarker::EndSync, "", 1),
+            trace(TraceMarker::StartSync, " LayoutTree", 2),
+            trace(TraceMarker::EndSync, "", 3),
+            trace(TraceMarker::StartSync, " Layout", 4),
+            trace(TraceMarker::EndSync, "", 5),
+        ];
+        let (spans, _integrity) = find_all_spans(&traces);
+        let index = SpanIndex::new(&spans);
+        let exact = index.matching(&Matcher::Exact(String::from("Layout")));
+        assert_eq!(exact.len(), 2);
+        let prefix = index.matching(&Matcher::Prefix(String::from("Layout")));
+        let starts = prefix
+            .iter()
+            .map(|s| s.start.timestamp.micro)
+            .collect::<Vec<_>>();
+        assert_eq!(starts, [0, 2, 4]);
+    }
+
+    /// Spans of `Outer` each with one `Inner` inside
+    fn nested_traces(spans: u64) -> Vec<Trace> {
+        (0..spans)
+            .flat_map(|i| {
+                let micro = i * 4;
+                [
+                    trace(TraceMarker::StartSync, " Outer", micro),
+                    trace(TraceMarker::StartSync, " Inner", micro + 1),
+                    trace(TraceMarker::EndSync, "", micro + 2),
+                    trace(TraceMarker::EndSync, "", micro + 3),
+                ]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn nested_traces_are_matched_in_one_pass() {
+        let traces = nested_traces(10_000);
+        let (spans, integrity) = find_all_spans(&traces);
+        let index = SpanIndex::new(&spans);
+        let outer = index.matching(&Matcher::Exact(String::from("Outer")));
+        let inner = index.matching(&Matcher::Prefix(String::from("Inn")));
+        assert_eq!(recursion_depth(&outer).ok(), Some((1, 1.0)));
+        assert_eq!(outer.len(), 10_000);
+        assert_eq!(inner.len(), 10_000);
+        assert_eq!(integrity.unterminated_starts(), 0);
+    }
+
+    #[test]
+    #[ignore = "timing, run with `cargo test --release -- --ignored`"]
+    fn million_traces_are_matched_in_one_pass() {
+        // 250k spans nested two deep, the old per filter scans needed minutes for this
+        let traces = nested_traces(250_000);
+        let start = std::time::Instant::now();
+        let (spans, _integrity) = find_all_spans(&traces);
+        let index = SpanIndex::new(&spans);
+        let outer = index.matching(&Matcher::Exact(String::from("Outer")));
+        let _ = recursion_depth(&outer);
+        let elapsed = start.elapsed();
+        assert_eq!(outer.len(), 250_000);
+        assert!(
+            elapsed < std::time::Duration::from_secs(20),
+            "took {:?}",
+            elapsed
+        );
+    }
+
+    #[test]
+    fn recursion_depth_of_ten_deep_recursion() {
+        let traces = (0..10)
+            .map(|i| trace(TraceMarker::StartSync, " recurse", i))
+            .chain((10..20).map(|i| trace(TraceMarker::EndSync, "", i)))
+            .collect::<Vec<_>>();
+        let (spans, integrity) = find_all_spans(&traces);
+        assert_eq!(spans.len(), 10);
+        assert_eq!(integrity.unmatched_ends, 0);
+        assert_eq!(integrity.unterminated_starts(), 0);
+        let spans = spans.iter().collect::<Vec<_>>();
+        assert_eq!(recursion_depth(&spans).ok(), Some((10, 10.0)));
+    }
+
+    #[test]
+    fn recursion_depth_ignores_other_names() {
+        let traces = vec![
+            trace(TraceMarker::StartSync, " recurse", 0),
+            trace(TraceMarker::StartSync, " other", 1),
+            trace(TraceMarker::StartSync, " recurse", 2),
+            trace(TraceMarker::EndSync, "", 3),
+            trace(TraceMarker::EndSync, "", 4),
+            trace(TraceMarker::EndSync, "", 5),
+            trace(TraceMarker::StartSync, " recurse", 6),
+            trace(TraceMarker::EndSync, "", 7),
+        ];
+        let (spans, _integrity) = find_all_spans(&traces);
+        let recurse = spans
+            .iter()
+            .filter(|s| s.name() == "recurse")
+            .collect::<Vec<_>>();
+        assert_eq!(recursion_depth(&recurse).ok(), Some((2, 1.5)));
+    }
+}
@@ -0,0 +1,159 @@
+//! The spans of the span filters written to a temporary file with `--spill`, so memory stays bounded
+//!
+//! Every span is a record of its duration and self duration in microseconds, two little endian `i64`.
+//! Only the count, the total and a reservoir sample are kept in memory, everything needing all spans
+//! reads the file back in order.
+use anyhow::{Context, Result};
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use time::Duration;
+
+/// The number of spans in the reservoir sample of every span filter and run
+pub(crate) const SAMPLE_SIZE: usize = 10_000;
+
+const RECORD: usize = 16;
+
+/// Numbers the spill files of the process
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+/// The spans of a span filter in a run while they are written
+pub(crate) struct Spill {
+    writer: BufWriter<File>,
+    spans: SpilledSpans,
+    /// State of the xorshift generator choosing the spans of the sample
+    state: u64,
+}
+
+impl Spill {
+    /// Create the file in the temporary directory
+    pub(crate) fn create() -> Result<Self> {
+        let path = std::env::temp_dir().join(format!(
+            "hitrace-bench-{}-{}.spill",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = File::create(&path).with_context(|| format!("Could not create {:?}", path))?;
+        Ok(Spill {
+            writer: BufWriter::new(file),
+            spans: SpilledSpans {
+                path,
+                count: 0,
+                total: Duration::ZERO,
+                sample: Vec::with_capacity(SAMPLE_SIZE),
+            },
+            state: 0x2545_f491_4f6c_dd1d,
+        })
+    }
+
+    fn random(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    /// Write the span, it replaces a random span of the sample with the probability of algorithm R
+    pub(crate) fn push(&mut self, duration: Duration, self_duration: Duration) -> Result<()> {
+        let mut record = [0; RECORD];
+        record[..8].copy_from_slice(&(duration.whole_microseconds() as i64).to_le_bytes());
+        record[8..].copy_from_slice(&(self_duration.whole_microseconds() as i64).to_le_bytes());
+        self.writer
+            .write_all(&record)
+            .with_context(|| format!("Could not write {:?}", self.spans.path))?;
+        let seen = self.spans.count;
+        self.spans.count += 1;
+        self.spans.total += duration;
+        if self.spans.sample.len() < SAMPLE_SIZE {
+            self.spans.sample.push((duration, self_duration));
+        } else {
+            let index = (self.random() % (seen + 1)) as usize;
+            if let Some(slot) = self.spans.sample.get_mut(index) {
+                *slot = (duration, self_duration);
+            }
+        }
+        Ok(())
+    }
+
+    /// Flush the file
+    pub(crate) fn finish(mut self) -> Result<SpilledSpans> {
+        self.writer
+            .flush()
+            .with_context(|| format!("Could not write {:?}", self.spans.path))?;
+        Ok(self.spans)
+    }
+}
+
+/// The spans of a span filter in a run, the file is removed when they are dropped
+pub(crate) struct SpilledSpans {
+    path: PathBuf,
+    /// Number of spans
+    pub(crate) count: u64,
+    /// Sum of their durations
+    pub(crate) total: Duration,
+    /// The durations and self durations of at most [`SAMPLE_SIZE`] spans, each span is equally likely in it
+    pub(crate) sample: Vec<(Duration, Duration)>,
+}
+
+impl SpilledSpans {
+    /// Read the durations and self durations of all spans in the order they were closed
+    pub(crate) fn read(&self) -> Result<impl Iterator<Item = Result<(Duration, Duration)>> + '_> {
+        let file =
+            File::open(&self.path).with_context(|| format!("Could not open {:?}", self.path))?;
+        let mut reader = BufReader::new(file);
+        Ok((0..self.count).map(move |_| {
+            let mut record = [0; RECORD];
+            reader
+                .read_exact(&mut record)
+                .with_context(|| format!("Could not read {:?}", self.path))?;
+            let micros = |bytes: &[u8]| {
+                Duration::microseconds(i64::from_le_bytes(bytes.try_into().unwrap_or_default()))
+            };
+            Ok((micros(&record[..8]), micros(&record[8..])))
+        }))
+    }
+}
+
+impl Drop for SpilledSpans {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn five_million_spans_keep_bounded_memory() {
+        let mut spill = Spill::create().unwrap();
+        for i in 0..5_000_000 {
+            spill
+                .push(Duration::microseconds(i % 1000), Duration::microseconds(1))
+                .unwrap();
+        }
+        let spans = spill.finish().unwrap();
+        assert_eq!(spans.count, 5_000_000);
+        assert_eq!(spans.total, Duration::microseconds(5_000 * 999 * 1000 / 2));
+        // the sample never grows past its size, so memory does not depend on the number of spans
+        assert_eq!(spans.sample.len(), SAMPLE_SIZE);
+        assert_eq!(spans.sample.capacity(), SAMPLE_SIZE);
+        let mean = spans.sample.iter().map(|(d, _)| *d).sum::<Duration>() / SAMPLE_SIZE as u32;
+        assert!((mean.whole_microseconds() - 500).abs() < 20, "{}", mean);
+        let (mut read, mut total) = (0, Duration::ZERO);
+        for record in spans.read().unwrap() {
+            let (duration, self_duration) = record.unwrap();
+            assert_eq!(self_duration, Duration::microseconds(1));
+            read += 1;
+            total += duration;
+        }
+        assert_eq!(read, 5_000_000);
+        assert_eq!(total, spans.total);
+        let path = spans.path.clone();
+        drop(spans);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,389 @@
+//! A history of the results of every session in a SQLite database given with `--store`
+use anyhow::{Context, Result};
+use rusqlite::{Connection, params};
+use std::{path::Path, process::Command};
+use time::{Duration, OffsetDateTime};
+
+use crate::{PointResults, RunResults, args::Args, bins, utils::avg_min_max};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS sessions (
+    id INTEGER PRIMARY KEY,
+    timestamp INTEGER NOT NULL,
+    commit_hash TEXT,
+    device TEXT,
+    homepage TEXT NOT NULL,
+    target TEXT
+);
+CREATE TABLE IF NOT EXISTS results (
+    session INTEGER NOT NULL REFERENCES sessions(id),
+    name TEXT NOT NULL,
+    point INTEGER NOT NULL,
+    runs INTEGER NOT NULL,
+    avg INTEGER NOT NULL,
+    min INTEGER NOT NULL,
+    max INTEGER NOT NULL,
+    median INTEGER NOT NULL,
+    std_dev INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS samples (
+    session INTEGER NOT NULL REFERENCES sessions(id),
+    name TEXT NOT NULL,
+    point INTEGER NOT NULL,
+    sample INTEGER NOT NULL,
+    value INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS scores (
+    session INTEGER NOT NULL REFERENCES sessions(id),
+    score REAL NOT NULL
+);
+CREATE INDEX IF NOT EXISTS results_by_name ON results (name, session);
+";
+
+/// Open the database and create the tables it does not have yet
+fn open(path: &Path) -> Result<Connection> {
+    let connection =
+        Connection::open(path).with_context(|| format!("Could not open database {:?}", path))?;
+    connection.execute_batch(SCHEMA)?;
+    // databases written before the targets were stored do not have the column
+    if connection
+        .prepare("SELECT target FROM sessions LIMIT 0")
+        .is_err()
+    {
+        connection.execute_batch("ALTER TABLE sessions ADD COLUMN target TEXT")?;
+    }
+    Ok(connection)
+}
+
+/// What a session benchmarked. `--target` and `--all-devices` store a session per target or device
+pub(crate) struct SessionKey<'a> {
+    /// The name of the target of `--target`
+    pub(crate) target: Option<&'a str>,
+    pub(crate) homepage: &'a str,
+    /// The serial of the device
+    pub(crate) device: Option<&'a str>,
+}
+
+impl<'a> SessionKey<'a> {
+    /// The homepage and device given on the command line
+    pub(crate) fn new(args: &'a Args) -> Self {
+        SessionKey {
+            target: None,
+            homepage: &args.homepage,
+            device: args.device.as_deref(),
+        }
+    }
+}
+
+/// The commit under test, the label if there is one and otherwise the HEAD of the git repository we are in
+fn commit_hash(args: &Args) -> Option<String> {
+    if !args.label.is_empty() {
+        return Some(args.label.clone());
+    }
+    let output = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+/// Append the session to the database. Durations are stored in microseconds, the score only if there is one
+pub(crate) fn store(
+    path: &Path,
+    args: &Args,
+    key: &SessionKey,
+    results: &RunResults,
+    points: &PointResults,
+    score: Option<f64>,
+) -> Result<()> {
+    let mut connection = open(path)?;
+    let transaction = connection.transaction()?;
+    transaction.execute(
+        "INSERT INTO sessions (timestamp, commit_hash, device, homepage, target) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            OffsetDateTime::now_utc().unix_timestamp(),
+            commit_hash(args),
+            key.device,
+            key.homepage,
+            key.target
+        ],
+    )?;
+    let session = transaction.last_insert_rowid();
+    if let Some(score) = score {
+        transaction.execute(
+            "INSERT INTO scores (session, score) VALUES (?1, ?2)",
+            params![session, score],
+        )?;
+    }
+    let filters = results.iter().map(|(name, durations)| {
+        let micros = durations
+            .iter()
+            .map(|d| d.whole_microseconds() as i64)
+            .collect::<Vec<_>>();
+        (name, false, micros)
+    });
+    let points = points
+        .iter()
+        .map(|(name, values)| (name, true, values.clone()));
+    {
+        let mut result = transaction.prepare(
+            "INSERT INTO results (session, name, point, runs, avg, min, max, median, std_dev)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        )?;
+        let mut sample = transaction.prepare(
+            "INSERT INTO samples (session, name, point, sample, value) VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        for (name, point, values) in filters.chain(points) {
+            let Ok(stats) = avg_min_max(&values) else {
+                continue;
+            };
+            result.execute(params![
+                session,
+                name,
+                point,
+                stats.number as i64,
+                stats.avg,
+                stats.min,
+                stats.max,
+                stats.median,
+                stats.std_dev
+            ])?;
+            for (index, value) in values.iter().enumerate() {
+                sample.execute(params![session, name, point, index as i64, value])?;
+            }
+        }
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+/// A statistic stored for every session
+pub(crate) enum Statistic {
+    /// The average of the session
+    Avg,
+    /// The median of the session
+    Median,
+    /// The smallest value of the session
+    Min,
+    /// The largest value of the session
+    Max,
+    /// The standard deviation of the session
+    StdDev,
+}
+
+impl Statistic {
+    /// The column of the results table
+    pub(crate) fn column(self) -> &'static str {
+        match self {
+            Statistic::Avg => "avg",
+            Statistic::Median => "median",
+            Statistic::Min => "min",
+            Statistic::Max => "max",
+            Statistic::StdDev => "std_dev",
+        }
+    }
+}
+
+/// The statistic of a filter in one stored session
+pub(crate) struct StoredValue {
+    pub(crate) timestamp: i64,
+    pub(crate) commit_hash: Option<String>,
+    pub(crate) device: Option<String>,
+    pub(crate) target: Option<String>,
+    /// A point metric, otherwise a duration in microseconds
+    pub(crate) point: bool,
+    pub(crate) value: i64,
+}
+
+#[derive(Default)]
+/// The stored sessions a series is read from, sessions not matching a given field are left out
+pub(crate) struct SessionFilter<'a> {
+    /// A glob on the label or commit
+    pub(crate) label: Option<&'a str>,
+    /// The serial of the device
+    pub(crate) device: Option<&'a str>,
+    /// The name of the target of `--target`
+    pub(crate) target: Option<&'a str>,
+}
+
+/// The statistic of the filter in the last `last` stored sessions matching the filter, oldest first
+pub(crate) fn read_series(
+    path: &Path,
+    name: &str,
+    statistic: Statistic,
+    filter: &SessionFilter,
+    last: usize,
+) -> Result<Vec<StoredValue>> {
+    let connection = open(path)?;
+    let mut statement = connection.prepare(&format!(
+        "SELECT s.timestamp, s.commit_hash, s.device, s.target, r.point, r.{}
+         FROM results r JOIN sessions s ON s.id = r.session
+         WHERE r.name = ?1 AND (?2 IS NULL OR s.commit_hash GLOB ?2) AND (?3 IS NULL OR s.device = ?3)
+            AND (?4 IS NULL OR s.target = ?4)
+         ORDER BY s.id DESC LIMIT ?5",
+        statistic.column()
+    ))?;
+    let mut values = statement
+        .query_map(
+            params![
+                name,
+                filter.label,
+                filter.device,
+                filter.target,
+                last as i64
+            ],
+            |row| {
+                Ok(StoredValue {
+                    timestamp: row.get(0)?,
+                    commit_hash: row.get(1)?,
+                    device: row.get(2)?,
+                    target: row.get(3)?,
+                    point: row.get(4)?,
+                    value: row.get(5)?,
+                })
+            },
+        )?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    values.reverse();
+    Ok(values)
+}
+
+/// One stored session of a filter
+struct Entry {
+    timestamp: i64,
+    commit_hash: Option<String>,
+    device: Option<String>,
+    target: Option<String>,
+    point: bool,
+    runs: i64,
+    avg: i64,
+    median: i64,
+    std_dev: i64,
+}
+
+/// Print the last `last` stored sessions of the filter, oldest first, with a sparkline of the averages
+pub(crate) fn print_history(path: &Path, name: &str, last: usize) -> Result<()> {
+    let connection = open(path)?;
+    let mut statement = connection.prepare(
+        "SELECT s.timestamp, s.commit_hash, s.device, s.target, r.point, r.runs, r.avg, r.median, r.std_dev
+         FROM results r JOIN sessions s ON s.id = r.session
+         WHERE r.name = ?1 ORDER BY s.id DESC LIMIT ?2",
+    )?;
+    let mut entries = statement
+        .query_map(params![name, last as i64], |row| {
+            Ok(Entry {
+                timestamp: row.get(0)?,
+                commit_hash: row.get(1)?,
+                device: row.get(2)?,
+                target: row.get(3)?,
+                point: row.get(4)?,
+                runs: row.get(5)?,
+                avg: row.get(6)?,
+                median: row.get(7)?,
+                std_dev: row.get(8)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    entries.reverse();
+    if entries.is_empty() {
+        println!("{:?} has no results of {}", path, name);
+        return Ok(());
+    }
+    let format = |entry: &Entry, value: i64| {
+        if entry.point {
+            value.to_string()
+        } else {
+            Duration::microseconds(value).to_string()
+        }
+    };
+    println!(
+        "----history of {} (avg median ±stddev, last {} sessions)------------------------",
+        name,
+        entries.len()
+    );
+    for entry in &entries {
+        let time = OffsetDateTime::from_unix_timestamp(entry.timestamp)
+            .map_or(entry.timestamp.to_string(), |t| t.date().to_string());
+        let labels = [
+            Some(time.as_str()),
+            entry.commit_hash.as_deref(),
+            entry.device.as_deref(),
+            entry.target.as_deref(),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+        println!(
+            "{}: {} {} ±{}  ({} runs)",
+            labels.join(" "),
+            format(entry, entry.avg),
+            format(entry, entry.median),
+            format(entry, entry.std_dev),
+            entry.runs
+        );
+    }
+    let averages = entries.iter().map(|e| e.avg as f64).collect::<Vec<_>>();
+    println!("trend: {}", bins::sparkline(&averages));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn stored_sessions_are_read_back_in_order() {
+        let path =
+            std::env::temp_dir().join(format!("hitrace-bench-{}.sqlite", std::process::id()));
+        let args = Args::parse_from(["hitrace-bench", "--label", "abc"]);
+        for micros in [100, 200] {
+            let results = RunResults::from([(
+                String::from("LoadPage"),
+                vec![Duration::microseconds(micros)],
+            )]);
+            let score = Some(micros as f64 / 100.0);
+            let key = SessionKey {
+                target: (micros == 200).then_some("webgpu"),
+                ..SessionKey::new(&args)
+            };
+            store(&path, &args, &key, &results, &PointResults::new(), score).unwrap();
+        }
+        let connection = open(&path).unwrap();
+        let averages = connection
+            .prepare(
+                "SELECT avg, commit_hash FROM results JOIN sessions ON id = session ORDER BY id",
+            )
+            .unwrap()
+            .query_map([], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+            })
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .unwrap();
+        let scores = connection
+            .prepare("SELECT score FROM scores ORDER BY session")
+            .unwrap()
+            .query_map([], |row| row.get::<_, f64>(0))
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .unwrap();
+        let filter = SessionFilter {
+            target: Some("webgpu"),
+            ..SessionFilter::default()
+        };
+        let series = read_series(&path, "LoadPage", Statistic::Avg, &filter, 10).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(scores, [1.0, 2.0]);
+        assert_eq!(series.len(), 1);
+        assert_eq!(series[0].value, 200);
+        assert_eq!(
+            averages,
+            [(100, String::from("abc")), (200, String::from("abc"))]
+        );
+    }
+}
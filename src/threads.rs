@@ -0,0 +1,78 @@
+//! Split the spans of a filter by the thread they ran on
+use std::{cmp::Reverse, collections::HashMap};
+use time::Duration;
+
+use crate::span::Span;
+
+#[derive(Debug, Default)]
+/// The spans of a filter that ran on one thread
+pub(crate) struct ThreadShare {
+    /// Number of spans
+    pub(crate) count: usize,
+    /// Summed duration of the spans
+    pub(crate) total: Duration,
+}
+
+/// The spans of a filter in one run keyed by pid and thread name
+pub(crate) type ThreadBreakdown = HashMap<(u64, String), ThreadShare>;
+
+/// Compute the share of every thread for the spans
+pub(crate) fn thread_breakdown(spans: &[&Span]) -> ThreadBreakdown {
+    let mut breakdown = ThreadBreakdown::new();
+    for span in spans {
+        let share = breakdown
+            .entry((span.start.pid, span.start.name.clone()))
+            .or_default();
+        share.count += 1;
+        share.total += span.duration();
+    }
+    breakdown
+}
+
+/// A thread averaged over all runs
+pub(crate) struct ThreadSummary {
+    /// The thread name or `other` for the remaining threads
+    pub(crate) name: String,
+    /// Average number of spans per run
+    pub(crate) count: f64,
+    /// Average summed duration per run
+    pub(crate) total: Duration,
+    /// Average duration of a single span
+    pub(crate) mean: Duration,
+}
+
+/// Average the thread breakdowns over all runs.
+/// The pid changes with every start of the app, so threads are joined by name over the runs.
+/// Only the `top` threads with the highest total are kept, all others are merged into `other`.
+pub(crate) fn summarize(runs: &[ThreadBreakdown], top: usize) -> Vec<ThreadSummary> {
+    let mut per_thread: HashMap<&str, ThreadShare> = HashMap::new();
+    for run in runs {
+        for ((_pid, name), share) in run.iter() {
+            let entry = per_thread.entry(name).or_default();
+            entry.count += share.count;
+            entry.total += share.total;
+        }
+    }
+    let mut threads = per_thread.into_iter().collect::<Vec<_>>();
+    threads.sort_by_key(|(_name, share)| Reverse(share.total));
+
+    let mut other = ThreadShare::default();
+    for (_name, share) in threads.iter().skip(top) {
+        other.count += share.count;
+        other.total += share.total;
+    }
+    let other = (threads.len() > top).then_some(("other", other));
+
+    let number_of_runs = runs.len() as f64;
+    threads
+        .into_iter()
+        .take(top)
+        .chain(other)
+        .map(|(name, share)| ThreadSummary {
+            name: name.to_owned(),
+            count: share.count as f64 / number_of_runs,
+            total: share.total / number_of_runs,
+            mean: share.total / share.count as f64,
+        })
+        .collect()
+}
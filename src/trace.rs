@@ -1,12 +1,17 @@
-/// Functions about the traces
-use anyhow::{Result, anyhow};
+//! Functions about the traces
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Debug, Display, write};
 use time::Duration;
 
-#[derive(Debug)]
-pub(crate) struct TimeStamp {
-    pub(crate) seconds: u64,
-    pub(crate) micro: u64,
+use crate::matcher::Matcher;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+/// A timestamp of the trace clock
+pub struct TimeStamp {
+    /// Whole seconds
+    pub seconds: u64,
+    /// Microseconds within the second
+    pub micro: u64,
 }
 
 impl Display for TimeStamp {
@@ -16,57 +21,142 @@ impl Display for TimeStamp {
 }
 
 #[derive(Debug)]
-pub(crate) enum TraceMarker {
+/// The kind of a trace
+pub enum TraceMarker {
+    /// `B`, starts a sync span on the thread
     StartSync,
+    /// `E`, ends the last sync span started on the thread
     EndSync,
+    /// `S`, starts an async span identified by function and cookie
     StartAsync,
+    /// `F`, ends the async span with the same function and cookie
     EndAsync,
+    /// `C`, a counter or a single point in time
     Dot,
 }
 
 impl TraceMarker {
-    pub(crate) fn from(val: &str) -> Result<Self> {
+    /// The marker of its letter in the trace, None for unknown letters
+    pub fn from(val: &str) -> Option<Self> {
         match val {
-            "B" => Ok(TraceMarker::StartSync),
-            "E" => Ok(TraceMarker::EndSync),
-            "S" => Ok(TraceMarker::StartAsync),
-            "F" => Ok(TraceMarker::EndAsync),
-            "C" => Ok(TraceMarker::Dot),
-            _ => Err(anyhow!("Could not parse Trace Marker")),
+            "B" => Some(TraceMarker::StartSync),
+            "E" => Some(TraceMarker::EndSync),
+            "S" => Some(TraceMarker::StartAsync),
+            "F" => Some(TraceMarker::EndAsync),
+            "C" => Some(TraceMarker::Dot),
+            _ => None,
         }
     }
 }
 
 #[derive(Debug)]
 /// A parsed trace
-pub(crate) struct Trace {
-    /// Name of the program, i.e., org.servo.servo
-    #[allow(unused)]
-    pub(crate) name: String,
-    /// pid
-    #[allow(unused)]
-    pub(crate) pid: u64,
+pub struct Trace {
+    /// Name of the thread, i.e., org.servo.servo for the main thread
+    pub name: String,
+    /// pid of the process
+    pub pid: u64,
+    /// id of the thread
+    pub tid: u64,
     /// the cpu it ran on
-    #[allow(unused)]
-    pub(crate) cpu: u64,
+    pub cpu: u64,
     /// timestamp of the trace
-    pub(crate) timestamp: TimeStamp,
+    pub timestamp: TimeStamp,
     /// Tells us if the trace ended and when
-    #[allow(unused)]
-    pub(crate) trace_marker: TraceMarker,
-    /// No idea what this is
-    #[allow(unused)]
-    pub(crate) number: String,
+    pub trace_marker: TraceMarker,
+    /// The pid of the writing process for sync traces and the cookie for async traces
+    pub number: String,
     /// Some shorthand code
-    pub(crate) shorthand: String,
+    pub shorthand: String,
     /// Full function name
-    pub(crate) function: String,
+    pub function: String,
+}
+
+impl Trace {
+    /// Is the trace the instant marker with the name
+    pub fn is_marker(&self, name: &str) -> bool {
+        matches!(self.trace_marker, TraceMarker::Dot)
+            && self.function.split_whitespace().next() == Some(name)
+    }
+
+    /// The arguments after the function name, i.e., `size=12345` of `ImageDecode size=12345`
+    pub fn arguments(&self) -> &str {
+        let function = self.function.trim();
+        function
+            .split_once(char::is_whitespace)
+            .map_or("", |(_name, arguments)| arguments.trim_start())
+    }
+
+    /// The value of a `key=value` argument in the function
+    pub fn argument(&self, key: &str) -> Option<&str> {
+        self.function
+            .split_whitespace()
+            .filter_map(|arg| arg.split_once('='))
+            .find(|(k, _v)| *k == key)
+            .map(|(_k, v)| v)
+    }
+
+    /// The name and value of a counter trace, i.e., `C|44682|H:mem.graphics 1024`
+    pub fn counter(&self) -> Option<(&str, i64)> {
+        if !matches!(self.trace_marker, TraceMarker::Dot) {
+            return None;
+        }
+        let mut words = self.function.split_whitespace();
+        let name = words.next()?;
+        let value = words.last()?.parse().ok()?;
+        Some((name, value))
+    }
+}
+
+/// The name of every process in the traces, which is the name of its main thread
+pub fn process_names(traces: &[Trace]) -> HashMap<u64, &str> {
+    traces
+        .iter()
+        .filter(|t| t.pid == t.tid)
+        .map(|t| (t.pid, t.name.as_str()))
+        .collect()
+}
+
+/// Linux keeps only this many bytes of a thread name
+const TASK_COMM_LEN: usize = 15;
+
+/// Does the process name from [`process_names`] match. The kernel cuts thread names to 15 bytes,
+/// so a cut name also matches an exact name it is the start of
+pub fn is_process(matcher: &Matcher, name: &str) -> bool {
+    matcher.matches(name)
+        || matches!(matcher, Matcher::Exact(exact)
+            if name.len() == TASK_COMM_LEN && exact.as_bytes().starts_with(name.as_bytes()))
+}
+
+/// The pids of the processes whose name matches. An app gets a new pid every launch, and with a pattern
+/// like `prefix:org.servo` all processes of a multi-process app match
+pub fn resolve_pids(traces: &[Trace], matcher: &Matcher) -> HashSet<u64> {
+    process_names(traces)
+        .into_iter()
+        .filter(|(_pid, name)| is_process(matcher, name))
+        .map(|(pid, _name)| pid)
+        .collect()
+}
+
+/// The timestamp all offsets of a run are relative to.
+/// This is the first trace whose function contains `anchor`, or the first trace if no anchor is given.
+pub fn find_anchor<'a>(traces: &'a [Trace], anchor: Option<&str>) -> Option<&'a TimeStamp> {
+    match anchor {
+        Some(anchor) => traces.iter().find(|t| t.function.contains(anchor)),
+        None => traces.first(),
+    }
+    .map(|t| &t.timestamp)
 }
 
 /// Calculates the timestamp difference equaivalent to trace1-trace2
-pub(crate) fn difference_of_traces(trace1: &Trace, trace2: &Trace) -> Duration {
+pub fn difference_of_traces(trace1: &Trace, trace2: &Trace) -> Duration {
+    difference_of_timestamps(&trace1.timestamp, &trace2.timestamp)
+}
+
+/// Calculates the difference equivalent to t1-t2
+pub fn difference_of_timestamps(t1: &TimeStamp, t2: &TimeStamp) -> Duration {
     Duration::new(
-        trace1.timestamp.seconds as i64 - trace2.timestamp.seconds as i64,
-        (trace1.timestamp.micro as i32 - trace2.timestamp.micro as i32) * 1000,
+        t1.seconds as i64 - t2.seconds as i64,
+        (t1.micro as i32 - t2.micro as i32) * 1000,
     )
 }
@@ -0,0 +1,174 @@
+//! The `trend` subcommand, fitting a line through a statistic of a filter over the sessions stored with `--store`
+use anyhow::Result;
+use serde::Serialize;
+use std::path::Path;
+use time::{Duration, OffsetDateTime};
+
+use crate::store::{self, SessionFilter, Statistic, StoredValue};
+
+#[derive(Debug, PartialEq, Serialize)]
+/// The trend of the values of consecutive sessions
+pub(crate) struct Trend {
+    /// Change per session of the least squares line
+    pub(crate) slope: f64,
+    /// Change of the line from the first to the last session in percent of its start
+    pub(crate) drift_percent: Option<f64>,
+    /// Index of the session after the largest step between two consecutive sessions
+    pub(crate) changepoint: usize,
+    /// The value at the changepoint minus the one before
+    pub(crate) step: f64,
+}
+
+/// Fit the trend, None with fewer than two values
+pub(crate) fn fit(values: &[f64]) -> Option<Trend> {
+    if values.len() < 2 {
+        return None;
+    }
+    let n = values.len() as f64;
+    let mean_x = (n - 1.0) / 2.0;
+    let mean_y = values.iter().sum::<f64>() / n;
+    let (covariance, variance) = values
+        .iter()
+        .enumerate()
+        .map(|(x, y)| (x as f64 - mean_x, y - mean_y))
+        .fold((0.0, 0.0), |(c, v), (dx, dy)| (c + dx * dy, v + dx * dx));
+    let slope = covariance / variance;
+    let start = mean_y - slope * mean_x;
+    let (changepoint, step) = values
+        .windows(2)
+        .map(|w| w[1] - w[0])
+        .enumerate()
+        .max_by(|(_, a), (_, b)| a.abs().total_cmp(&b.abs()))?;
+    Some(Trend {
+        slope,
+        drift_percent: (start != 0.0).then(|| slope * (n - 1.0) / start.abs() * 100.0),
+        changepoint: changepoint + 1,
+        step,
+    })
+}
+
+#[derive(Debug, Serialize)]
+/// A session in the json output
+struct SessionJson<'a> {
+    timestamp: i64,
+    label: Option<&'a str>,
+    device: Option<&'a str>,
+    target: Option<&'a str>,
+    value: i64,
+}
+
+#[derive(Debug, Serialize)]
+/// The json output of the trend
+struct TrendJson<'a> {
+    name: &'a str,
+    statistic: &'static str,
+    /// The values are durations in microseconds, otherwise a point
+    duration: bool,
+    sessions: Vec<SessionJson<'a>>,
+    /// Null with fewer than two sessions
+    trend: Option<Trend>,
+}
+
+fn date(timestamp: i64) -> String {
+    OffsetDateTime::from_unix_timestamp(timestamp)
+        .map_or(timestamp.to_string(), |t| t.date().to_string())
+}
+
+/// The session as `date label device target`
+fn describe(value: &StoredValue) -> String {
+    let time = date(value.timestamp);
+    [
+        Some(time.as_str()),
+        value.commit_hash.as_deref(),
+        value.device.as_deref(),
+        value.target.as_deref(),
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>()
+    .join(" ")
+}
+
+/// Print the trend of the statistic of the filter over the last `last` matching sessions
+pub(crate) fn print_trend(
+    path: &Path,
+    name: &str,
+    statistic: Statistic,
+    filter: &SessionFilter,
+    last: usize,
+    json: bool,
+) -> Result<()> {
+    let series = store::read_series(path, name, statistic, filter, last)?;
+    let values = series.iter().map(|v| v.value as f64).collect::<Vec<_>>();
+    let trend = fit(&values);
+    let point = series.iter().any(|v| v.point);
+    let statistic_name = statistic.column();
+    if json {
+        let output = TrendJson {
+            name,
+            statistic: statistic_name,
+            duration: !point,
+            sessions: series
+                .iter()
+                .map(|v| SessionJson {
+                    timestamp: v.timestamp,
+                    label: v.commit_hash.as_deref(),
+                    device: v.device.as_deref(),
+                    target: v.target.as_deref(),
+                    value: v.value,
+                })
+                .collect(),
+            trend,
+        };
+        println!("{}", serde_json::to_string_pretty(&output)?);
+        return Ok(());
+    }
+    let format = |value: f64| {
+        if point {
+            format!("{:.1}", value)
+        } else {
+            Duration::microseconds(value.round() as i64).to_string()
+        }
+    };
+    println!(
+        "----trend of the {} of {} (last {} sessions)------------------------",
+        statistic_name,
+        name,
+        series.len()
+    );
+    let Some(trend) = trend else {
+        println!(
+            "{:?} has fewer than two matching sessions of {}",
+            path, name
+        );
+        return Ok(());
+    };
+    println!("slope: {} per session", format(trend.slope));
+    match trend.drift_percent {
+        Some(drift) => println!("drift: {:+.1}% over the window", drift),
+        None => println!("drift: _"),
+    }
+    println!(
+        "largest step: {} from {} to {}",
+        format(trend.step),
+        describe(&series[trend.changepoint - 1]),
+        describe(&series[trend.changepoint])
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trend_has_slope_drift_and_step() {
+        let trend = fit(&[100.0, 102.0, 101.0, 130.0, 131.0]).unwrap();
+        assert!((trend.slope - 9.0).abs() < 1e-9);
+        assert_eq!(trend.changepoint, 3);
+        assert_eq!(trend.step, 29.0);
+        // the line goes from 94.8 to 130.8
+        assert!((trend.drift_percent.unwrap() - 36.0 / 94.8 * 100.0).abs() < 1e-9);
+        assert_eq!(fit(&[100.0]), None);
+    }
+}
@@ -0,0 +1,299 @@
+//! Statistics over the results of the runs
+use serde::Serialize;
+use std::iter::Sum;
+use time::Duration;
+
+use crate::error::BenchError;
+
+/// Values we can average
+pub trait Average: Copy + Ord + Sum {
+    /// The average of `number` values summing up to `sum`
+    fn average(sum: Self, number: usize) -> Self;
+    /// The population standard deviation of the values around their average
+    fn std_dev(values: &[Self], avg: Self) -> Self;
+    /// The value as a float, used for bucket boundaries
+    fn as_f64(self) -> f64;
+    /// The value closest to the float, durations are rounded to the microseconds of the trace clock
+    fn from_f64(value: f64) -> Self;
+}
+
+impl Average for Duration {
+    fn average(sum: Self, number: usize) -> Self {
+        sum / number as f64
+    }
+
+    fn std_dev(values: &[Self], avg: Self) -> Self {
+        let variance = values
+            .iter()
+            .map(|v| (*v - avg).as_seconds_f64().powi(2))
+            .sum::<f64>()
+            / values.len() as f64;
+        Duration::seconds_f64(variance.sqrt())
+    }
+
+    fn as_f64(self) -> f64 {
+        self.as_seconds_f64()
+    }
+
+    fn from_f64(value: f64) -> Self {
+        Duration::microseconds((value * 1_000_000.0).round() as i64)
+    }
+}
+
+impl Average for i64 {
+    fn average(sum: Self, number: usize) -> Self {
+        sum / number as i64
+    }
+
+    fn std_dev(values: &[Self], avg: Self) -> Self {
+        let variance = values
+            .iter()
+            .map(|v| ((*v - avg) as f64).powi(2))
+            .sum::<f64>()
+            / values.len() as f64;
+        variance.sqrt().round() as i64
+    }
+
+    fn as_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn from_f64(value: f64) -> Self {
+        value.round() as i64
+    }
+}
+
+#[derive(Debug, Serialize)]
+/// Statistics over the values of a metric
+pub struct AvgMinMax<T> {
+    /// Average of the values
+    pub avg: T,
+    /// Smallest value
+    pub min: T,
+    /// Largest value
+    pub max: T,
+    /// The 50th percentile
+    pub median: T,
+    /// The 90th percentile
+    pub p90: T,
+    /// The 95th percentile
+    pub p95: T,
+    /// The 99th percentile
+    pub p99: T,
+    /// Population standard deviation
+    pub std_dev: T,
+    /// Number of values
+    pub number: usize,
+}
+
+impl<T> AvgMinMax<T> {
+    /// Convert the statistics to another unit
+    pub fn map<U>(self, f: impl Fn(T) -> U) -> AvgMinMax<U> {
+        AvgMinMax {
+            avg: f(self.avg),
+            min: f(self.min),
+            max: f(self.max),
+            median: f(self.median),
+            p90: f(self.p90),
+            p95: f(self.p95),
+            p99: f(self.p99),
+            std_dev: f(self.std_dev),
+            number: self.number,
+        }
+    }
+}
+
+/// Statistics over the values, fails with [`BenchError::NoValues`] if there are none
+pub fn avg_min_max<T: Average>(values: &[T]) -> Result<AvgMinMax<T>, BenchError> {
+    let number = values.len();
+    let mut sorted = values.to_vec();
+    sorted.sort();
+    let (Some(min), Some(max)) = (sorted.first().copied(), sorted.last().copied()) else {
+        return Err(BenchError::NoValues);
+    };
+    let avg = T::average(values.iter().copied().sum(), number);
+    let rank = |p| nearest_rank(&sorted, p).ok_or(BenchError::NoValues);
+    Ok(AvgMinMax {
+        avg,
+        min,
+        max,
+        median: rank(50.0)?,
+        p90: rank(90.0)?,
+        p95: rank(95.0)?,
+        p99: rank(99.0)?,
+        std_dev: T::std_dev(values, avg),
+        number,
+    })
+}
+
+#[derive(Debug)]
+/// The values between two boundaries
+pub struct Bucket<T> {
+    /// Lower boundary, inclusive
+    pub low: T,
+    /// Upper boundary, inclusive for the last bucket
+    pub high: T,
+    /// Number of values in the bucket
+    pub count: usize,
+}
+
+/// Bucket the values into `buckets` equally wide buckets between the minimum and maximum.
+/// With `log` the buckets are equally wide on a log scale, which needs a positive minimum and falls back
+/// to linear buckets otherwise. If all values are equal there is a single bucket.
+pub fn histogram<T: Average>(values: &[T], buckets: usize, log: bool) -> Vec<Bucket<T>> {
+    let (Some(min), Some(max)) = (values.iter().min(), values.iter().max()) else {
+        return Vec::new();
+    };
+    if min == max || buckets == 0 {
+        return vec![Bucket {
+            low: *min,
+            high: *max,
+            count: values.len(),
+        }];
+    }
+    let (low, high) = (min.as_f64(), max.as_f64());
+    let log = log && low > 0.0;
+    let scale = |v: f64| if log { v.ln() } else { v };
+    let unscale = |v: f64| if log { v.exp() } else { v };
+    let (low, high) = (scale(low), scale(high));
+    let width = (high - low) / buckets as f64;
+    let mut counts = vec![0; buckets];
+    for v in values {
+        let index = ((scale(v.as_f64()) - low) / width) as usize;
+        counts[index.min(buckets - 1)] += 1;
+    }
+    counts
+        .into_iter()
+        .enumerate()
+        .map(|(i, count)| Bucket {
+            low: if i == 0 {
+                *min
+            } else {
+                T::from_f64(unscale(low + width * i as f64))
+            },
+            high: if i == buckets - 1 {
+                *max
+            } else {
+                T::from_f64(unscale(low + width * (i + 1) as f64))
+            },
+            count,
+        })
+        .collect()
+}
+
+/// The nearest rank percentile `p` of already sorted values
+fn nearest_rank<T: Copy>(sorted: &[T], p: f64) -> Option<T> {
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted.get(rank.max(1) - 1).copied()
+}
+
+/// The nearest rank percentile `p` of the values, None if there are no values
+pub fn percentile<T: Copy + Ord>(values: &[T], p: f64) -> Option<T> {
+    let mut sorted = values.to_vec();
+    sorted.sort();
+    nearest_rank(&sorted, p)
+}
+
+/// The values within 1.5 interquartile ranges of the quartiles, in their order
+pub fn reject_iqr<T: Average>(values: &[T]) -> Vec<T> {
+    let mut sorted = values.to_vec();
+    sorted.sort();
+    let (Some(q1), Some(q3)) = (nearest_rank(&sorted, 25.0), nearest_rank(&sorted, 75.0)) else {
+        return Vec::new();
+    };
+    let (q1, q3) = (q1.as_f64(), q3.as_f64());
+    let fence = 1.5 * (q3 - q1);
+    values
+        .iter()
+        .copied()
+        .filter(|v| (q1 - fence..=q3 + fence).contains(&v.as_f64()))
+        .collect()
+}
+
+/// The values without the `fraction` smallest and the `fraction` largest ones, in their order
+pub fn trim<T: Copy + Ord>(values: &[T], fraction: f64) -> Vec<T> {
+    let cut = (values.len() as f64 * fraction).floor() as usize;
+    if cut == 0 {
+        return values.to_vec();
+    }
+    let mut ranked = (0..values.len()).collect::<Vec<_>>();
+    ranked.sort_by_key(|i| values[*i]);
+    let mut keep = vec![false; values.len()];
+    for i in ranked
+        .iter()
+        .skip(cut)
+        .take(values.len().saturating_sub(2 * cut))
+    {
+        keep[*i] = true;
+    }
+    values
+        .iter()
+        .zip(keep)
+        .filter_map(|(v, keep)| keep.then_some(*v))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_values_have_no_statistics() {
+        assert!(matches!(avg_min_max::<i64>(&[]), Err(BenchError::NoValues)));
+        assert!(avg_min_max::<Duration>(&[]).is_err());
+        assert!(percentile::<i64>(&[], 50.0).is_none());
+    }
+
+    #[test]
+    fn single_value_is_every_percentile() {
+        let stats = avg_min_max(&[Duration::milliseconds(7)]).unwrap();
+        assert_eq!(stats.median, Duration::milliseconds(7));
+        assert_eq!(stats.p90, Duration::milliseconds(7));
+        assert_eq!(stats.p95, Duration::milliseconds(7));
+        assert_eq!(stats.p99, Duration::milliseconds(7));
+        assert_eq!(stats.std_dev, Duration::ZERO);
+    }
+
+    #[test]
+    fn identical_values_are_one_bucket() {
+        let buckets = histogram(&[5, 5, 5], 10, false);
+        assert_eq!(buckets.len(), 1);
+        assert_eq!(buckets[0].count, 3);
+    }
+
+    #[test]
+    fn histogram_counts_every_value() {
+        let values = (0..100).collect::<Vec<i64>>();
+        let buckets = histogram(&values, 10, false);
+        assert_eq!(buckets.len(), 10);
+        assert!(buckets.iter().all(|b| b.count == 10));
+        let log = histogram(&[1, 10, 100, 1000], 3, true);
+        assert_eq!(log.iter().map(|b| b.count).collect::<Vec<_>>(), [1, 1, 2]);
+    }
+
+    #[test]
+    fn percentiles_and_std_dev() {
+        let values = (1..=100).collect::<Vec<i64>>();
+        let stats = avg_min_max(&values).unwrap();
+        assert_eq!(stats.median, 50);
+        assert_eq!(stats.p90, 90);
+        assert_eq!(stats.p95, 95);
+        assert_eq!(stats.p99, 99);
+        assert_eq!(avg_min_max(&[2, 4, 4, 4, 5, 5, 7, 9]).unwrap().std_dev, 2);
+    }
+
+    #[test]
+    fn iqr_rejects_the_far_outlier() {
+        let values = [10, 12, 11, 13, 100, 12];
+        assert_eq!(reject_iqr(&values), [10, 12, 11, 13, 12]);
+        assert!(reject_iqr::<i64>(&[]).is_empty());
+    }
+
+    #[test]
+    fn trim_drops_both_ends_and_keeps_order() {
+        let values = [5, 1, 9, 3, 7, 2, 8, 4, 6, 10];
+        assert_eq!(trim(&values, 0.2), [5, 3, 7, 8, 4, 6]);
+        assert_eq!(trim(&values, 0.05), values);
+        assert!(trim(&values, 0.5).is_empty());
+    }
+}